@@ -0,0 +1,294 @@
+use serde::{Deserialize, Serialize};
+
+/// Steps the colour shader has room for, must match `AdjustmentStack` in compute.wgsl
+pub const MAX_STACK_STEPS: usize = 32;
+
+/// One of the colour pass's operations, run in the order the stack lists them. The numbers
+/// are the step kinds in compute.wgsl.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Adjustment {
+    Exposure = 0,
+    Vignette = 1,
+    BlackAndWhite = 2,
+    Tone = 3,
+    Vibrance = 4,
+    HslMixer = 5,
+    Toning = 6,
+    ColorGrading = 7,
+}
+
+/// Every adjustment in the order the colour pass has always run them
+pub const ADJUSTMENTS: [Adjustment; 8] = [
+    Adjustment::Exposure,
+    Adjustment::Vignette,
+    Adjustment::BlackAndWhite,
+    Adjustment::Tone,
+    Adjustment::Vibrance,
+    Adjustment::HslMixer,
+    Adjustment::Toning,
+    Adjustment::ColorGrading,
+];
+
+/// What an adjustment expects its input to be. Steps only move within their own domain,
+/// tone mapping sits fixed between linear light and the display encoded ones.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Domain {
+    /// Scene linear light
+    Linear,
+    /// After tone mapping, gamma encoded
+    Display,
+}
+
+impl Domain {
+    pub fn name(self) -> &'static str {
+        match self {
+            Domain::Linear => "Linear Light",
+            Domain::Display => "After Tone Mapping",
+        }
+    }
+}
+
+impl Adjustment {
+    pub fn name(self) -> &'static str {
+        match self {
+            Adjustment::Exposure => "Exposure & Brightness",
+            Adjustment::Vignette => "Vignette",
+            Adjustment::BlackAndWhite => "Black & White",
+            Adjustment::Tone => "Contrast, Shadows & Highlights",
+            Adjustment::Vibrance => "Vibrance",
+            Adjustment::HslMixer => "HSL Mixer",
+            Adjustment::Toning => "Toning",
+            Adjustment::ColorGrading => "Colour Grading",
+        }
+    }
+
+    pub fn domain(self) -> Domain {
+        match self {
+            Adjustment::Exposure | Adjustment::Vignette | Adjustment::BlackAndWhite => Domain::Linear,
+            _ => Domain::Display,
+        }
+    }
+}
+
+/// One step of the stack. Its settings are the adjustment's own controls, shared by any
+/// duplicates, which only differ in where they run and how strongly.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StackStep {
+    pub adjustment: Adjustment,
+    pub enabled: bool,
+    /// 0 to 1, blend of the step's result over its input
+    pub opacity: f32,
+}
+
+impl StackStep {
+    fn new(adjustment: Adjustment) -> Self {
+        Self { adjustment, enabled: true, opacity: 1.0 }
+    }
+}
+
+/// Order of the colour pass's adjustments, saved with the edit
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdjustmentStack {
+    pub steps: Vec<StackStep>,
+}
+
+impl Default for AdjustmentStack {
+    fn default() -> Self {
+        Self { steps: ADJUSTMENTS.map(StackStep::new).to_vec() }
+    }
+}
+
+// Mirrors `StackStep` in compute.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct StackStepUniform {
+    pub kind: u32,
+    pub opacity: f32,
+    pub _pad: [u32; 2],
+}
+
+// Mirrors `AdjustmentStack` in compute.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct AdjustmentStackUniform {
+    pub info: [u32; 4], // step count, first step after tone mapping, unused x2
+    pub steps: [StackStepUniform; MAX_STACK_STEPS],
+}
+
+impl AdjustmentStack {
+    /// A saved stack made fit to run: grouped by domain, every adjustment in it at least
+    /// once, missing ones where they run by default
+    pub fn restore(saved: Self) -> Self {
+        let mut steps = saved.steps;
+        for adjustment in ADJUSTMENTS {
+            if !steps.iter().any(|s| s.adjustment == adjustment) {
+                steps.push(StackStep::new(adjustment));
+            }
+        }
+        // Over the limit, duplicates go from the end so every adjustment keeps a step
+        while steps.len() > MAX_STACK_STEPS {
+            let Some(i) = (0..steps.len())
+                .rev()
+                .find(|&i| steps.iter().filter(|s| s.adjustment == steps[i].adjustment).count() > 1)
+            else {
+                break;
+            };
+            steps.remove(i);
+        }
+        steps.sort_by_key(|s| s.adjustment.domain());
+        Self { steps }
+    }
+
+    pub fn uniform(&self) -> AdjustmentStackUniform {
+        let running: Vec<&StackStep> = self.steps.iter().filter(|s| s.enabled).take(MAX_STACK_STEPS).collect();
+        let mut steps = [bytemuck::Zeroable::zeroed(); MAX_STACK_STEPS];
+        for (slot, step) in steps.iter_mut().zip(&running) {
+            *slot = StackStepUniform { kind: step.adjustment as u32, opacity: step.opacity, _pad: [0; 2] };
+        }
+        let before_tone_mapping = running.iter().filter(|s| s.adjustment.domain() != Domain::Display).count();
+        AdjustmentStackUniform { info: [running.len() as u32, before_tone_mapping as u32, 0, 0], steps }
+    }
+
+    /// Moves a step to `to`, or as close to it as its domain allows
+    fn move_step(&mut self, from: usize, to: usize) {
+        let domain = self.steps[from].adjustment.domain();
+        let step = self.steps.remove(from);
+        let first = self.steps.iter().position(|s| s.adjustment.domain() >= domain).unwrap_or(self.steps.len());
+        let end = self.steps.iter().position(|s| s.adjustment.domain() > domain).unwrap_or(self.steps.len());
+        // Past the removed step everything has shifted up by one
+        let to = if to > from { to - 1 } else { to };
+        self.steps.insert(to.clamp(first, end), step);
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.label("Drag to change the order adjustments are applied in");
+
+        let full = self.steps.len() >= MAX_STACK_STEPS;
+        let mut moved = None;
+        let mut duplicated = None;
+        let mut removed = None;
+        for i in 0..self.steps.len() {
+            let domain = self.steps[i].adjustment.domain();
+            if i == 0 || self.steps[i - 1].adjustment.domain() != domain {
+                ui.add_space(4.0);
+                ui.label(egui::RichText::new(domain.name()).small().weak());
+            }
+            let duplicates = self.steps.iter().filter(|s| s.adjustment == self.steps[i].adjustment).count();
+            let step = &mut self.steps[i];
+
+            let row = ui.horizontal(|ui| {
+                ui.checkbox(&mut step.enabled, "");
+                ui.dnd_drag_source(ui.id().with(("adjustment_step", i)), i, |ui| {
+                    ui.label(format!("☰ {}", step.adjustment.name()));
+                });
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    // Every adjustment keeps a step, the last one can only be switched off
+                    if ui.add_enabled(duplicates > 1, egui::Button::new("✕").small()).clicked() {
+                        removed = Some(i);
+                    }
+                    if ui.add_enabled(!full, egui::Button::new("⧉").small()).on_hover_text("Duplicate").clicked() {
+                        duplicated = Some(i);
+                    }
+                    ui.add_enabled(step.enabled, egui::DragValue::new(&mut step.opacity).range(0.0..=1.0).speed(0.01))
+                        .on_hover_text("Opacity");
+                });
+            });
+
+            let response = row.response;
+            if let Some(pointer) = ui.input(|input| input.pointer.interact_pos())
+                && response.dnd_hover_payload::<usize>().is_some()
+            {
+                // Line where the dragged step would land
+                let y = if pointer.y < response.rect.center().y { response.rect.top() } else { response.rect.bottom() };
+                ui.painter().hline(response.rect.x_range(), y, ui.visuals().selection.stroke);
+            }
+            if let Some(from) = response.dnd_release_payload::<usize>()
+                && let Some(pointer) = ui.input(|input| input.pointer.interact_pos())
+            {
+                let to = if pointer.y < response.rect.center().y { i } else { i + 1 };
+                moved = Some((*from, to));
+            }
+        }
+
+        if let Some((from, to)) = moved {
+            self.move_step(from, to);
+        } else if let Some(i) = duplicated {
+            self.steps.insert(i + 1, self.steps[i]);
+        } else if let Some(i) = removed {
+            self.steps.remove(i);
+        }
+
+        ui.add_space(4.0);
+        if ui.button("Reset Order").clicked() {
+            *self = Self::default();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(stack: &AdjustmentStack) -> Vec<Adjustment> {
+        stack.steps.iter().map(|s| s.adjustment).collect()
+    }
+
+    #[test]
+    fn moves_within_a_domain() {
+        let mut stack = AdjustmentStack::default();
+        stack.move_step(1, 0);
+        assert_eq!(order(&stack)[..3], [Adjustment::Vignette, Adjustment::Exposure, Adjustment::BlackAndWhite]);
+
+        // Dropping a step below the last one puts it at the end
+        let mut stack = AdjustmentStack::default();
+        stack.move_step(3, 8);
+        assert_eq!(order(&stack).last(), Some(&Adjustment::Tone));
+    }
+
+    #[test]
+    fn moves_stop_at_the_tone_mapping() {
+        let mut stack = AdjustmentStack::default();
+        stack.move_step(0, 6);
+        assert_eq!(order(&stack)[..4], [Adjustment::Vignette, Adjustment::BlackAndWhite, Adjustment::Exposure, Adjustment::Tone]);
+
+        let mut stack = AdjustmentStack::default();
+        stack.move_step(7, 0);
+        assert_eq!(order(&stack)[2..4], [Adjustment::BlackAndWhite, Adjustment::ColorGrading]);
+    }
+
+    #[test]
+    fn restore_groups_by_domain_and_fills_in_missing_steps() {
+        let saved = AdjustmentStack {
+            steps: vec![
+                StackStep::new(Adjustment::ColorGrading),
+                StackStep { opacity: 0.5, ..StackStep::new(Adjustment::Exposure) },
+                StackStep::new(Adjustment::Tone),
+            ],
+        };
+        let restored = AdjustmentStack::restore(saved);
+        assert_eq!(
+            order(&restored),
+            [
+                Adjustment::Exposure,
+                Adjustment::Vignette,
+                Adjustment::BlackAndWhite,
+                Adjustment::ColorGrading,
+                Adjustment::Tone,
+                Adjustment::Vibrance,
+                Adjustment::HslMixer,
+                Adjustment::Toning,
+            ]
+        );
+        assert_eq!(restored.steps[0].opacity, 0.5);
+    }
+
+    #[test]
+    fn restore_keeps_within_the_shader_limit() {
+        let saved = AdjustmentStack { steps: vec![StackStep::new(Adjustment::Vibrance); MAX_STACK_STEPS + 4] };
+        let restored = AdjustmentStack::restore(saved);
+        assert_eq!(restored.steps.len(), MAX_STACK_STEPS);
+        assert!(ADJUSTMENTS.iter().all(|a| restored.steps.iter().any(|s| s.adjustment == *a)));
+        assert_eq!(restored.uniform().info[0], MAX_STACK_STEPS as u32);
+    }
+}
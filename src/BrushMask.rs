@@ -0,0 +1,155 @@
+use serde::{Deserialize, Serialize};
+use crate::Geometry::{Geometry, GeometryUniform};
+use crate::SpotHealing::SpotGpu;
+use crate::LocalAdjustments::{LocalAdjustments, MaskShape, MAX_LOCAL_ADJUSTMENTS};
+
+/// Longest side of the brush mask textures. Painted masks are soft so they're kept
+/// smaller than the image, edge refinement brings the edges back to full resolution.
+pub const MASK_RESOLUTION: u32 = 2048;
+
+/// One mask per channel of an Rgba8Unorm texture layer, a layer per four local adjustments
+pub const MASKS_PER_LAYER: usize = 4;
+pub const MASK_LAYERS: u32 = MAX_LOCAL_ADJUSTMENTS.div_ceil(MASKS_PER_LAYER) as u32;
+
+/// One stroke of the brush. Points are in mask coordinates like the other mask shapes, so
+/// the stroke list doesn't depend on the image or mask resolution.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BrushStroke {
+    pub points: Vec<[f32; 2]>,
+    pub radius: f32,
+    /// Share of the radius that fades out
+    pub feather: f32,
+    /// How much one stroke adds (or takes away), strokes build up on top of each other
+    pub flow: f32,
+    pub erase: bool,
+}
+
+/// What the next stroke is painted with
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BrushSettings {
+    pub radius: f32,
+    pub feather: f32,
+    pub flow: f32,
+    pub erase: bool,
+}
+
+impl Default for BrushSettings {
+    fn default() -> Self {
+        Self { radius: 0.03, feather: 0.5, flow: 1.0, erase: false }
+    }
+}
+
+// Mirrors `Stroke` in brush.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct StrokeGpu {
+    pub bounds: [f32; 4], // min x, min y, max x, max y including the radius
+    pub params: [f32; 4], // radius, feather, flow, erase
+    pub range: [u32; 4],  // first point, point count, mask slot, unused
+}
+
+// Mirrors `BrushUniform` in brush.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BrushUniform {
+    /// Output pixel -> source pixel, like the geometry pass
+    pub to_source_x: [f32; 4],
+    pub to_source_y: [f32; 4],
+    /// Output pixels per mask pixel on x and y, mask units per source pixel, unused
+    pub scale: [f32; 4],
+    /// Stroke count, unused x3
+    pub counts: [u32; 4],
+}
+
+/// Mask texture size for an output size, keeping the aspect
+pub fn mask_size(output: [u32; 2]) -> [u32; 2] {
+    let longest = output[0].max(output[1]);
+    if longest <= MASK_RESOLUTION {
+        return output;
+    }
+    let scale = MASK_RESOLUTION as f32 / longest as f32;
+    [
+        ((output[0] as f32 * scale).round() as u32).max(1),
+        ((output[1] as f32 * scale).round() as u32).max(1),
+    ]
+}
+
+/// Everything the brush passes read. The last one run is kept so unchanged masks aren't
+/// rasterised again every frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BrushMaskData {
+    pub uniform: BrushUniform,
+    pub strokes: Vec<StrokeGpu>,
+    pub points: Vec<[f32; 2]>,
+    /// Any layer wants edge refinement, the guided filter passes only run then
+    pub refine: bool,
+    /// What the refinement guide was made from while refining, so a lens or spot change
+    /// refines the masks again even when the strokes haven't changed
+    pub guide: Option<BrushGuide>,
+}
+
+/// The framing and healing behind the guide image, the geometry pass's output
+#[derive(Debug, Clone, PartialEq)]
+pub struct BrushGuide {
+    pub geometry: GeometryUniform,
+    pub spots: Vec<SpotGpu>,
+    pub spot_points: Vec<[f32; 2]>,
+}
+
+impl BrushMaskData {
+    pub fn new(local: &LocalAdjustments, geometry: &Geometry, source: [u32; 2], full_frame: bool, guide: BrushGuide) -> Self {
+        let output = geometry.output_size(source, full_frame);
+        let mask = mask_size(output);
+        let [to_source_x, to_source_y] = geometry.source_rows(source, full_frame);
+
+        let mut strokes = Vec::new();
+        let mut points = Vec::new();
+        let mut refine = false;
+        for (slot, _, layer) in local.slots() {
+            let MaskShape::Brush { strokes: layer_strokes, refine: layer_refine } = &layer.shape else {
+                continue;
+            };
+            refine |= *layer_refine;
+            for stroke in layer_strokes.iter().filter(|s| !s.points.is_empty()) {
+                let mut bounds = [f32::MAX, f32::MAX, f32::MIN, f32::MIN];
+                for p in &stroke.points {
+                    bounds = [bounds[0].min(p[0]), bounds[1].min(p[1]), bounds[2].max(p[0]), bounds[3].max(p[1])];
+                }
+                let r = stroke.radius;
+                strokes.push(StrokeGpu {
+                    bounds: [bounds[0] - r, bounds[1] - r, bounds[2] + r, bounds[3] + r],
+                    params: [r, stroke.feather, stroke.flow, stroke.erase as u32 as f32],
+                    range: [points.len() as u32, stroke.points.len() as u32, slot as u32, 0],
+                });
+                points.extend_from_slice(&stroke.points);
+            }
+        }
+
+        let stroke_count = strokes.len() as u32;
+        // Storage bindings can't be empty
+        if strokes.is_empty() {
+            strokes.push(bytemuck::Zeroable::zeroed());
+        }
+        if points.is_empty() {
+            points.push([0.0; 2]);
+        }
+
+        Self {
+            uniform: BrushUniform {
+                to_source_x,
+                to_source_y,
+                scale: [
+                    output[0] as f32 / mask[0] as f32,
+                    output[1] as f32 / mask[1] as f32,
+                    1.0 / source[0].max(source[1]) as f32,
+                    0.0,
+                ],
+                counts: [stroke_count, 0, 0, 0],
+            },
+            strokes,
+            points,
+            refine,
+            guide: refine.then_some(guide),
+        }
+    }
+}
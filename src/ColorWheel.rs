@@ -0,0 +1,53 @@
+use std::f32::consts::TAU;
+use egui::ecolor::Hsva;
+use egui::{Color32, Mesh, Sense, Shape, Stroke, Vec2};
+
+/// Hue/saturation disc. The angle picks the hue (in degrees) and the distance from the
+/// centre picks the saturation (0..1). Double-click resets to neutral.
+pub fn color_wheel(ui: &mut egui::Ui, hue: &mut f32, saturation: &mut f32, diameter: f32) -> egui::Response {
+    let (rect, mut response) = ui.allocate_exact_size(Vec2::splat(diameter), Sense::click_and_drag());
+    let center = rect.center();
+    let radius = diameter * 0.5 - 2.0;
+
+    if response.double_clicked() {
+        *hue = 0.0;
+        *saturation = 0.0;
+        response.mark_changed();
+    } else if (response.dragged() || response.clicked())
+        && let Some(pos) = response.interact_pointer_pos()
+    {
+        let d = pos - center;
+        // Screen y points down, flip it so hue runs counter-clockwise like a colour wheel
+        *hue = (-d.y).atan2(d.x).rem_euclid(TAU).to_degrees();
+        *saturation = (d.length() / radius).min(1.0);
+        response.mark_changed();
+    }
+
+    if ui.is_rect_visible(rect) {
+        let painter = ui.painter();
+
+        // Triangle fan from a grey centre out to fully saturated hues on the rim
+        let segments = 64;
+        let mut mesh = Mesh::default();
+        mesh.colored_vertex(center, Color32::from_gray(128));
+        for i in 0..=segments {
+            let h = i as f32 / segments as f32;
+            let angle = h * TAU;
+            let rim = center + radius * Vec2::new(angle.cos(), -angle.sin());
+            mesh.colored_vertex(rim, Hsva::new(h, 1.0, 1.0, 1.0).into());
+        }
+        for i in 0..segments {
+            mesh.add_triangle(0, i + 1, i + 2);
+        }
+        painter.add(Shape::mesh(mesh));
+        painter.circle_stroke(center, radius, ui.visuals().widgets.noninteractive.bg_stroke);
+
+        // Current value
+        let angle = hue.to_radians();
+        let knob = center + radius * *saturation * Vec2::new(angle.cos(), -angle.sin());
+        painter.circle_stroke(knob, 4.0, Stroke::new(1.5, Color32::WHITE));
+        painter.circle_stroke(knob, 5.5, Stroke::new(1.0, Color32::BLACK));
+    }
+
+    response
+}
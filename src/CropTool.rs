@@ -0,0 +1,349 @@
+use egui::{Color32, Pos2, Rect, Stroke, Vec2};
+use crate::Geometry::{CropRect, Geometry, Orientation, ASPECT_FREE, ASPECT_PRESETS, EXIF_ORIENTATION_NAMES, MAX_STRAIGHTEN};
+
+/// Pointer distance in points at which a crop edge or corner can be grabbed
+const HANDLE_REACH: f32 = 10.0;
+
+/// Smallest crop side, as a fraction of the frame
+const MIN_CROP: f32 = 0.02;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum CropHandle {
+    Move,
+    Left,
+    Right,
+    Top,
+    Bottom,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl CropHandle {
+    /// Which sides the handle moves, as (left, right, top, bottom)
+    fn sides(self) -> (bool, bool, bool, bool) {
+        match self {
+            CropHandle::Move => (true, true, true, true),
+            CropHandle::Left => (true, false, false, false),
+            CropHandle::Right => (false, true, false, false),
+            CropHandle::Top => (false, false, true, false),
+            CropHandle::Bottom => (false, false, false, true),
+            CropHandle::TopLeft => (true, false, true, false),
+            CropHandle::TopRight => (false, true, true, false),
+            CropHandle::BottomLeft => (true, false, false, true),
+            CropHandle::BottomRight => (false, true, false, true),
+        }
+    }
+
+    fn cursor(self) -> egui::CursorIcon {
+        match self {
+            CropHandle::Move => egui::CursorIcon::Move,
+            CropHandle::Left | CropHandle::Right => egui::CursorIcon::ResizeHorizontal,
+            CropHandle::Top | CropHandle::Bottom => egui::CursorIcon::ResizeVertical,
+            CropHandle::TopLeft | CropHandle::BottomRight => egui::CursorIcon::ResizeNwSe,
+            CropHandle::TopRight | CropHandle::BottomLeft => egui::CursorIcon::ResizeNeSw,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct CropDrag {
+    handle: CropHandle,
+    start_crop: CropRect,
+    start_pos: Pos2,
+}
+
+/// Interactive crop and straighten. While `active` the viewer shows the whole frame with
+/// the crop drawn over it, the crop itself is applied once the tool is closed.
+#[derive(Default)]
+pub struct CropTool {
+    pub active: bool,
+    /// Next drag on the image draws the horizon instead of moving the crop
+    pub leveling: bool,
+    drag: Option<CropDrag>,
+    horizon_start: Option<Pos2>,
+}
+
+fn crop_screen_rect(crop: &CropRect, frame: Rect) -> Rect {
+    Rect::from_min_size(
+        frame.min + Vec2::new(crop.x * frame.width(), crop.y * frame.height()),
+        Vec2::new(crop.w * frame.width(), crop.h * frame.height()),
+    )
+}
+
+fn handle_at(pos: Pos2, rect: Rect) -> Option<CropHandle> {
+    let near_left = (pos.x - rect.left()).abs() < HANDLE_REACH;
+    let near_right = (pos.x - rect.right()).abs() < HANDLE_REACH;
+    let near_top = (pos.y - rect.top()).abs() < HANDLE_REACH;
+    let near_bottom = (pos.y - rect.bottom()).abs() < HANDLE_REACH;
+    let within_x = pos.x > rect.left() - HANDLE_REACH && pos.x < rect.right() + HANDLE_REACH;
+    let within_y = pos.y > rect.top() - HANDLE_REACH && pos.y < rect.bottom() + HANDLE_REACH;
+
+    match (near_left, near_right, near_top, near_bottom) {
+        (true, _, true, _) => Some(CropHandle::TopLeft),
+        (_, true, true, _) => Some(CropHandle::TopRight),
+        (true, _, _, true) => Some(CropHandle::BottomLeft),
+        (_, true, _, true) => Some(CropHandle::BottomRight),
+        (true, _, _, _) if within_y => Some(CropHandle::Left),
+        (_, true, _, _) if within_y => Some(CropHandle::Right),
+        (_, _, true, _) if within_x => Some(CropHandle::Top),
+        (_, _, _, true) if within_x => Some(CropHandle::Bottom),
+        _ if rect.contains(pos) => Some(CropHandle::Move),
+        _ => None,
+    }
+}
+
+/// Crop after dragging `handle` by `delta` (normalised), keeping `ratio` (normalised w / h) if set
+fn dragged_crop(start: CropRect, handle: CropHandle, delta: Vec2, ratio: Option<f32>) -> CropRect {
+    let (left, right, top, bottom) = start.sides_moved(handle, delta);
+    let mut crop = CropRect { x: left, y: top, w: right - left, h: bottom - top };
+    if handle == CropHandle::Move {
+        crop.x = crop.x.clamp(0.0, 1.0 - crop.w);
+        crop.y = crop.y.clamp(0.0, 1.0 - crop.h);
+        return crop;
+    }
+
+    crop.w = crop.w.max(MIN_CROP);
+    crop.h = crop.h.max(MIN_CROP);
+    let (moves_left, moves_right, moves_top, moves_bottom) = handle.sides();
+    if moves_left {
+        crop.x = start.x + start.w - crop.w;
+    }
+    if moves_top {
+        crop.y = start.y + start.h - crop.h;
+    }
+
+    if let Some(ratio) = ratio {
+        let horizontal = moves_left || moves_right;
+        let vertical = moves_top || moves_bottom;
+        if horizontal && !vertical {
+            // Side handle: the other dimension follows about the centre
+            let h = crop.w / ratio;
+            crop.y = start.y + (start.h - h) * 0.5;
+            crop.h = h;
+        } else if vertical && !horizontal {
+            let w = crop.h * ratio;
+            crop.x = start.x + (start.w - w) * 0.5;
+            crop.w = w;
+        } else {
+            // Corner: width leads, height follows from the opposite corner
+            crop.h = crop.w / ratio;
+            if moves_top {
+                crop.y = start.y + start.h - crop.h;
+            }
+        }
+    }
+    crop
+}
+
+impl CropRect {
+    fn sides_moved(&self, handle: CropHandle, delta: Vec2) -> (f32, f32, f32, f32) {
+        let (left, right, top, bottom) = handle.sides();
+        let mut sides = (self.x, self.x + self.w, self.y, self.y + self.h);
+        if left {
+            sides.0 += delta.x;
+        }
+        if right {
+            sides.1 += delta.x;
+        }
+        if top {
+            sides.2 += delta.y;
+        }
+        if bottom {
+            sides.3 += delta.y;
+        }
+        sides
+    }
+
+    fn within_frame(&self) -> bool {
+        self.x >= -1e-4 && self.y >= -1e-4 && self.x + self.w <= 1.0001 && self.y + self.h <= 1.0001
+    }
+}
+
+impl CropTool {
+    /// Crop and straighten section for the side panel
+    pub fn panel_ui(&mut self, ui: &mut egui::Ui, geometry: &mut Geometry, source: [u32; 2]) {
+        ui.horizontal(|ui| {
+            if ui.selectable_label(self.active, "Crop").clicked() {
+                self.active = !self.active;
+                self.leveling = false;
+            }
+            if ui.button("⟲ 90°").clicked() {
+                geometry.rotate(false);
+            }
+            if ui.button("⟳ 90°").clicked() {
+                geometry.rotate(true);
+            }
+            if ui.button("Flip H").clicked() {
+                geometry.flip_horizontally();
+            }
+            if ui.button("Flip V").clicked() {
+                geometry.flip_vertically();
+            }
+        });
+
+        ui.horizontal(|ui| {
+            let aspect = geometry.aspect;
+            egui::ComboBox::from_label("Aspect")
+                .selected_text(ASPECT_PRESETS.get(geometry.aspect).map_or("", |preset| preset.0))
+                .show_ui(ui, |ui| {
+                    for (i, (name, _)) in ASPECT_PRESETS.iter().enumerate() {
+                        ui.selectable_value(&mut geometry.aspect, i, *name);
+                    }
+                });
+            let swap = ui
+                .add_enabled(geometry.aspect != ASPECT_FREE, egui::Button::new("⇄"))
+                .on_hover_text("Swap between landscape and portrait");
+            if swap.clicked() {
+                geometry.aspect_portrait = !geometry.aspect_portrait;
+            }
+            if geometry.aspect != aspect || swap.clicked() {
+                geometry.apply_aspect(source);
+            }
+        });
+
+        ui.horizontal(|ui| {
+            let mut angle = geometry.straighten;
+            let slider = egui::Slider::new(&mut angle, -MAX_STRAIGHTEN..=MAX_STRAIGHTEN)
+                .text("Straighten")
+                .suffix("°")
+                .fixed_decimals(2);
+            if ui.add(slider).changed() {
+                geometry.set_straighten(angle, source);
+            }
+            if ui
+                .selectable_label(self.leveling, "Level")
+                .on_hover_text("Draw along something that should be level or upright")
+                .clicked()
+            {
+                self.leveling = !self.leveling;
+                if self.leveling {
+                    self.active = true;
+                }
+            }
+        });
+
+        let orientation_label = |orientation: Orientation| EXIF_ORIENTATION_NAMES[orientation.to_exif() as usize - 1];
+        let mut orientation_override = geometry.orientation_override;
+        egui::ComboBox::from_label("Orientation")
+            .selected_text(match orientation_override {
+                Some(orientation) => orientation_label(orientation).to_string(),
+                None => format!("As Shot ({})", orientation_label(geometry.source_orientation)),
+            })
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut orientation_override, None, "As Shot");
+                for exif in 1..=8 {
+                    let orientation = Orientation::from_exif(exif);
+                    ui.selectable_value(&mut orientation_override, Some(orientation), orientation_label(orientation));
+                }
+            });
+        geometry.set_orientation_override(orientation_override);
+
+        if ui.button("Reset Geometry").clicked() {
+            geometry.reset();
+        }
+    }
+
+    /// Crop overlay and interaction on top of the viewer. `frame` is the screen rect of the
+    /// whole rotated frame, which is what the viewer shows while the tool is active.
+    pub fn ui(&mut self, ui: &egui::Ui, response: &egui::Response, frame: Rect, geometry: &mut Geometry, source: [u32; 2]) {
+        if !self.active {
+            return;
+        }
+
+        let pointer = response.interact_pointer_pos();
+        let crop_rect = crop_screen_rect(&geometry.crop, frame);
+
+        if self.leveling {
+            if response.drag_started() {
+                self.horizon_start = pointer;
+            }
+            if response.drag_stopped()
+                && let (Some(start), Some(end)) = (self.horizon_start.take(), response.hover_pos().or(pointer))
+            {
+                let d = end - start;
+                if d.length() > 10.0 {
+                    // Screen y points down, so flip it to get the usual counter-clockwise angle
+                    let mut tilt = (-d.y).atan2(d.x).to_degrees();
+                    // Steep lines are verticals, measure those against upright instead
+                    tilt = (tilt + 45.0).rem_euclid(90.0) - 45.0;
+                    geometry.set_straighten(geometry.straighten - tilt, source);
+                    self.leveling = false;
+                }
+            }
+        } else {
+            if response.drag_started() {
+                self.drag = pointer
+                    .and_then(|p| Some((p, handle_at(p, crop_rect)?)))
+                    .map(|(start_pos, handle)| CropDrag { handle, start_crop: geometry.crop, start_pos });
+            }
+            if let (Some(drag), Some(pos)) = (self.drag, pointer)
+                && response.dragged()
+            {
+                let delta = (pos - drag.start_pos) / frame.size();
+                let ratio = geometry.aspect_ratio(source).map(|r| {
+                    let [w, h] = geometry.oriented_size(source);
+                    r * h / w
+                });
+                let crop = dragged_crop(drag.start_crop, drag.handle, delta, ratio);
+                if crop.within_frame() && geometry.crop_fits(&crop, source) {
+                    geometry.crop = crop;
+                }
+            }
+            if response.drag_stopped() {
+                self.drag = None;
+            }
+            if response.double_clicked() || ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                self.active = false;
+            }
+
+            let hovered = self.drag.map(|d| d.handle).or_else(|| handle_at(response.hover_pos()?, crop_rect));
+            if let Some(handle) = hovered {
+                ui.ctx().set_cursor_icon(handle.cursor());
+            }
+        }
+
+        self.paint(ui, frame, crop_screen_rect(&geometry.crop, frame), response.hover_pos());
+    }
+
+    fn paint(&self, ui: &egui::Ui, frame: Rect, crop: Rect, hover: Option<Pos2>) {
+        let painter = ui.painter_at(frame);
+        let shade = Color32::from_black_alpha(150);
+
+        // Darken everything outside the crop
+        painter.rect_filled(Rect::from_min_max(frame.min, egui::pos2(frame.right(), crop.top())), 0.0, shade);
+        painter.rect_filled(Rect::from_min_max(egui::pos2(frame.left(), crop.bottom()), frame.max), 0.0, shade);
+        painter.rect_filled(Rect::from_min_max(egui::pos2(frame.left(), crop.top()), egui::pos2(crop.left(), crop.bottom())), 0.0, shade);
+        painter.rect_filled(Rect::from_min_max(egui::pos2(crop.right(), crop.top()), egui::pos2(frame.right(), crop.bottom())), 0.0, shade);
+
+        // Rule of thirds
+        let thirds = Stroke::new(1.0, Color32::from_white_alpha(90));
+        for i in 1..3 {
+            let t = i as f32 / 3.0;
+            let x = crop.left() + crop.width() * t;
+            let y = crop.top() + crop.height() * t;
+            painter.line_segment([egui::pos2(x, crop.top()), egui::pos2(x, crop.bottom())], thirds);
+            painter.line_segment([egui::pos2(crop.left(), y), egui::pos2(crop.right(), y)], thirds);
+        }
+        painter.rect_stroke(crop, 0.0, Stroke::new(1.5, Color32::WHITE), egui::StrokeKind::Middle);
+
+        // Corner brackets
+        let arm = 14.0_f32.min(crop.width() * 0.5).min(crop.height() * 0.5);
+        let bracket = Stroke::new(3.0, Color32::WHITE);
+        for (corner, dx, dy) in [
+            (crop.left_top(), 1.0, 1.0),
+            (crop.right_top(), -1.0, 1.0),
+            (crop.left_bottom(), 1.0, -1.0),
+            (crop.right_bottom(), -1.0, -1.0),
+        ] {
+            painter.line_segment([corner, corner + Vec2::new(arm * dx, 0.0)], bracket);
+            painter.line_segment([corner, corner + Vec2::new(0.0, arm * dy)], bracket);
+        }
+
+        if let (Some(start), Some(end)) = (self.horizon_start, hover) {
+            painter.line_segment([start, end], Stroke::new(3.0, Color32::BLACK));
+            painter.line_segment([start, end], Stroke::new(1.5, Color32::YELLOW));
+        }
+    }
+}
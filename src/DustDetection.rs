@@ -0,0 +1,241 @@
+use crate::SpotHealing::SpotSearch;
+
+/// A "defect" covering more pixels than this is more likely detail in the picture
+const MAX_COMPONENT_PIXELS: usize = 4000;
+
+/// Points a detected hair is traced with
+const MAX_PATH_POINTS: usize = 24;
+
+/// Analysis pixels added around a defect so its soft edge is covered too
+const DEFECT_MARGIN: f32 = 1.5;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DustSettings {
+    /// 0 finds only the most obvious specks, 1 finds faint ones too
+    pub sensitivity: f32,
+    /// Widest defect looked for, in source pixels. Hairs can be longer, only their width counts.
+    pub max_size: f32,
+}
+
+impl Default for DustSettings {
+    fn default() -> Self {
+        Self { sensitivity: 0.5, max_size: 12.0 }
+    }
+}
+
+/// A found defect waiting for review, in mask coordinates like the heal spots
+#[derive(Debug, Clone, PartialEq)]
+pub struct DustCandidate {
+    /// Centre of a speck, or a path along a hair
+    pub points: Vec<[f32; 2]>,
+    pub radius: f32,
+    /// How far it stands out from its surroundings, in gamma encoded luma
+    pub strength: f32,
+    /// Cleared when the user decides it's part of the picture
+    pub accepted: bool,
+}
+
+/// Running min or max over a window of `2 * radius + 1` along a line, edges repeated.
+/// van Herk / Gil-Werman, so the cost doesn't grow with the window.
+fn running_extreme(line: &[f32], radius: usize, out: &mut [f32], pick: fn(f32, f32) -> f32) {
+    let n = line.len();
+    let window = 2 * radius + 1;
+    let padded: Vec<f32> = (0..n + 2 * radius).map(|i| line[i.saturating_sub(radius).min(n - 1)]).collect();
+    let len = padded.len();
+    let mut forward = vec![0.0; len];
+    let mut backward = vec![0.0; len];
+    for i in 0..len {
+        forward[i] = if i % window == 0 { padded[i] } else { pick(forward[i - 1], padded[i]) };
+    }
+    for i in (0..len).rev() {
+        backward[i] = if (i + 1) % window == 0 || i + 1 == len { padded[i] } else { pick(backward[i + 1], padded[i]) };
+    }
+    for (j, o) in out.iter_mut().enumerate() {
+        *o = pick(backward[j], forward[j + window - 1]);
+    }
+}
+
+/// Min or max over a square, one direction at a time
+fn square_extreme(image: &[f32], width: usize, height: usize, radius: usize, pick: fn(f32, f32) -> f32) -> Vec<f32> {
+    let mut rows = vec![0.0; image.len()];
+    for y in 0..height {
+        let span = y * width..(y + 1) * width;
+        running_extreme(&image[span.clone()], radius, &mut rows[span], pick);
+    }
+    let mut out = vec![0.0; image.len()];
+    let mut column = vec![0.0; height];
+    let mut column_out = vec![0.0; height];
+    for x in 0..width {
+        for y in 0..height {
+            column[y] = rows[y * width + x];
+        }
+        running_extreme(&column, radius, &mut column_out, pick);
+        for y in 0..height {
+            out[y * width + x] = column_out[y];
+        }
+    }
+    out
+}
+
+fn distance_to_path(path: &[[f32; 2]], p: [f32; 2]) -> f32 {
+    let mut best = f32::MAX;
+    for (i, &a) in path.iter().enumerate() {
+        let b = path.get(i + 1).copied().unwrap_or(a);
+        let (abx, aby) = (b[0] - a[0], b[1] - a[1]);
+        let (apx, apy) = (p[0] - a[0], p[1] - a[1]);
+        let t = ((apx * abx + apy * aby) / (abx * abx + aby * aby).max(1e-6)).clamp(0.0, 1.0);
+        best = best.min((apx - abx * t).hypot(apy - aby * t));
+    }
+    best
+}
+
+/// Traces one connected defect: a centre for a speck, a path for a hair. Returns the
+/// points and radius in analysis pixels.
+fn trace_defect(pixels: &[[f32; 2]]) -> (Vec<[f32; 2]>, f32) {
+    let count = pixels.len() as f32;
+    let mean = pixels.iter().fold([0.0, 0.0], |m, p| [m[0] + p[0] / count, m[1] + p[1] / count]);
+    let (mut xx, mut xy, mut yy) = (0.0, 0.0, 0.0);
+    for p in pixels {
+        let (dx, dy) = (p[0] - mean[0], p[1] - mean[1]);
+        xx += dx * dx;
+        xy += dx * dy;
+        yy += dy * dy;
+    }
+    // Long axis of the pixel cloud
+    let angle = 0.5 * (2.0 * xy).atan2(xx - yy);
+    let axis = [angle.cos(), angle.sin()];
+    let along = |p: &[f32; 2]| (p[0] - mean[0]) * axis[0] + (p[1] - mean[1]) * axis[1];
+    let (low, high) = pixels.iter().map(along).fold((f32::MAX, f32::MIN), |(l, h), t| (l.min(t), h.max(t)));
+    let length = high - low + 1.0;
+    let width = count / length;
+
+    let path = if length <= 2.0 * width + 2.0 {
+        vec![mean]
+    } else {
+        // Centroids of slices along the axis follow a curved hair
+        let bins = ((length / (2.0 * width).max(2.0)).ceil() as usize).clamp(2, MAX_PATH_POINTS);
+        let mut sums = vec![[0.0f32; 3]; bins];
+        for p in pixels {
+            let bin = (((along(p) - low) / length * bins as f32) as usize).min(bins - 1);
+            sums[bin] = [sums[bin][0] + p[0], sums[bin][1] + p[1], sums[bin][2] + 1.0];
+        }
+        sums.iter().filter(|s| s[2] > 0.0).map(|s| [s[0] / s[2], s[1] / s[2]]).collect()
+    };
+    let radius = pixels.iter().map(|&p| distance_to_path(&path, p)).fold(0.0, f32::max) + 0.5 + DEFECT_MARGIN;
+    (path, radius)
+}
+
+/// Small high contrast defects in the picture, strongest first. A morphological top-hat
+/// both ways round finds anything brighter or darker than its surroundings that's too
+/// narrow for a square of `max_size` to fit in: dust, scratches and hairs, but not edges.
+pub fn detect_dust(search: &SpotSearch, settings: &DustSettings, unit: f32) -> Vec<DustCandidate> {
+    let [width, height] = search.size();
+    let (width, height) = (width as usize, height as usize);
+    let luma = search.luma();
+    if luma.is_empty() {
+        return Vec::new();
+    }
+    let radius = (settings.max_size * search.scale() * 0.5).ceil().max(1.0) as usize;
+
+    // Opening takes out bright specks, closing fills in dark ones
+    let eroded = square_extreme(luma, width, height, radius, f32::min);
+    let opened = square_extreme(&eroded, width, height, radius, f32::max);
+    drop(eroded);
+    let dilated = square_extreme(luma, width, height, radius, f32::max);
+    let closed = square_extreme(&dilated, width, height, radius, f32::min);
+    drop(dilated);
+    let residual: Vec<f32> = (0..luma.len()).map(|i| (luma[i] - opened[i]).max(closed[i] - luma[i])).collect();
+
+    let threshold = 0.16 - 0.13 * settings.sensitivity.clamp(0.0, 1.0);
+    let mut visited = vec![false; luma.len()];
+    let mut candidates = Vec::new();
+    let mut stack = Vec::new();
+    for start in 0..luma.len() {
+        if visited[start] || residual[start] <= threshold {
+            continue;
+        }
+        // Flood fill the defect, 8-connected so diagonal hairs hold together
+        visited[start] = true;
+        stack.push(start);
+        let mut pixels = Vec::new();
+        let mut strength = 0.0f32;
+        while let Some(i) = stack.pop() {
+            let (x, y) = (i % width, i / width);
+            pixels.push([x as f32 + 0.5, y as f32 + 0.5]);
+            strength = strength.max(residual[i]);
+            for dy in -1i32..=1 {
+                for dx in -1i32..=1 {
+                    let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+                    if nx < 0 || ny < 0 || nx >= width as i32 || ny >= height as i32 {
+                        continue;
+                    }
+                    let n = ny as usize * width + nx as usize;
+                    if !visited[n] && residual[n] > threshold {
+                        visited[n] = true;
+                        stack.push(n);
+                    }
+                }
+            }
+        }
+        if pixels.len() > MAX_COMPONENT_PIXELS {
+            continue;
+        }
+
+        let (path, path_radius) = trace_defect(&pixels);
+        candidates.push(DustCandidate {
+            points: path.iter().map(|&p| search.to_mask(p, unit)).collect(),
+            radius: search.to_mask([path_radius, 0.0], unit)[0],
+            strength,
+            accepted: true,
+        });
+    }
+    candidates.sort_by(|a, b| b.strength.total_cmp(&a.strength));
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: u32 = 64;
+
+    /// Mid grey with the given pixels black
+    fn search(dark: impl Fn(u32, u32) -> bool) -> SpotSearch {
+        let pixels: Vec<[f32; 3]> = (0..SIZE * SIZE)
+            .map(|i| if dark(i % SIZE, i / SIZE) { [0.0; 3] } else { [0.18; 3] })
+            .collect();
+        SpotSearch::new(&pixels, SIZE, SIZE)
+    }
+
+    #[test]
+    fn finds_a_speck_at_its_centre() {
+        let search = search(|x, y| (20..23).contains(&x) && (30..33).contains(&y));
+        let found = detect_dust(&search, &DustSettings::default(), 1.0);
+        assert_eq!(found.len(), 1);
+        let speck = &found[0];
+        assert_eq!(speck.points.len(), 1);
+        let [x, y] = speck.points[0];
+        assert!((x - 21.5).abs() < 1e-3 && (y - 31.5).abs() < 1e-3, "{:?}", speck.points);
+        assert!(speck.radius >= 1.5 + DEFECT_MARGIN && speck.radius < 4.0 + DEFECT_MARGIN);
+        assert!(speck.accepted);
+    }
+
+    #[test]
+    fn traces_a_hair_along_its_length() {
+        let search = search(|x, y| y == 50 && (10..40).contains(&x));
+        let found = detect_dust(&search, &DustSettings::default(), 1.0);
+        assert_eq!(found.len(), 1);
+        let points = &found[0].points;
+        assert!(points.len() > 2);
+        assert!(points.iter().all(|p| (p[1] - 50.5).abs() < 1e-3));
+        let (left, right) = points.iter().fold((f32::MAX, f32::MIN), |(l, r), p| (l.min(p[0]), r.max(p[0])));
+        assert!(left < 15.0 && right > 35.0, "{points:?}");
+    }
+
+    #[test]
+    fn leaves_out_shapes_wider_than_the_max_size() {
+        let search = search(|x, y| (10..40).contains(&x) && (10..40).contains(&y));
+        assert!(detect_dust(&search, &DustSettings::default(), 1.0).is_empty());
+        assert!(detect_dust(&SpotSearch::empty(), &DustSettings::default(), 1.0).is_empty());
+    }
+}
@@ -1,9 +1,32 @@
-use crate::ImageControls::ImageControls;
+use crate::ImageControls::{rgb_to_hue, HslMixerState, HslProperty, ImageControls};
 use crate::ImageTextureView::ImageTextureView;
-use crate::GpuImageRenderPipeline::GpuImageRenderPipeline;
-use crate::GpuImageComputePipeline::GpuImageComputePipeline;
+use crate::GpuPipelines::GpuPipelines;
+use crate::GpuHistogramPipeline::HISTOGRAM_BINS;
+use crate::GpuReadback::GpuReadback;
+use crate::HistogramView::HistogramView;
+use crate::GpuScopesPipeline::{VECTORSCOPE_BINS, WAVEFORM_BINS};
+use crate::ScopesView::ScopesView;
+use crate::PixelProbeView::{PixelProbeView, MAX_PROBE_POINTS, PROBE_SLOT_BYTES};
+use crate::Geometry::{Geometry, GeometryUniform};
+use crate::CropTool::CropTool;
+use crate::LensCorrection::LensCorrection;
+use crate::LocalAdjustments::{LocalAdjustments, LocalAdjustmentsUniform};
+use crate::AdjustmentStack::AdjustmentStack;
+use crate::MaskTool::MaskTool;
+use crate::SpotHealing::{SpotGpu, SpotHealing, SpotSearch, MAX_HEAL_POINTS, MAX_HEAL_SPOTS, SPOT_LIST_HEADER};
+use crate::SpotTool::SpotTool;
+use crate::BrushMask::{BrushGuide, BrushMaskData};
+use crate::GpuBrushMaskPipeline::BrushMaskResources;
+use crate::GpuSharpenPipeline::SharpenResources;
+use crate::GpuDenoisePipeline::DenoiseResources;
+use crate::NoiseReduction::NoiseReduction;
+use crate::GpuLocalContrastPipeline::LocalContrastResources;
+use crate::GpuLocalAdjustmentsPipeline::{LocalAdjustmentsResources, RangePicker};
+use crate::LocalContrast::estimate_airlight;
+use crate::ProcessingGraph::{ComputeStage, GraphStage, Intermediate, IntermediatePool, ProcessingGraph, Stage, StageBypass, StageFrame};
+use crate::ProcessingState::ProcessingState;
 use crate::ImageRenderResources::ImageRenderResources;
-use crate::ViewportUniform::ViewportUniform;
+use crate::ViewportUniform::{ViewportUniform, COMPARE_OFF};
 use crate::View;
 use eframe::wgpu;
 use eframe::wgpu::util::DeviceExt;
@@ -14,17 +37,44 @@ use egui_file_dialog::FileDialog;
 use std::path::PathBuf;
 
 pub struct FilmEmulator {
+    /// Built once at startup and shared by every image
+    pipelines: GpuPipelines,
     file_dialog: FileDialog,
     selected_image_path: Option<PathBuf>,
     controls: Option<ImageControls>,
+    hsl_mixer: HslMixerState,
+    histogram: HistogramView,
+    scopes: ScopesView,
+    probe: PixelProbeView,
+    geometry: Geometry,
+    crop_tool: CropTool,
+    lens: LensCorrection,
+    local_adjustments: LocalAdjustments,
+    mask_tool: MaskTool,
+    spot_healing: SpotHealing,
+    spot_tool: SpotTool,
+    noise_reduction: NoiseReduction,
+    adjustment_stack: AdjustmentStack,
+    /// Why the image's saved edit couldn't be loaded. Exports don't save over it while set.
+    unreadable_sidecar: Option<String>,
+    /// Why the last save of the edit failed, cleared by the next one that works
+    save_error: Option<String>,
+    /// Stages switched off for comparison, not saved
+    bypass: StageBypass,
+    /// Haze colour for dehaze, estimated once on load
+    airlight: [f32; 3],
+    /// Decoded image size, before any geometry
+    source_size: [u32; 2],
     image: Option<ImageTextureView>,
     image_loaded: bool,
     export_pending: bool,
 }
 
 impl FilmEmulator {
-    pub fn new(_cc: &eframe::CreationContext<'_>) -> Option<Self> {
+    pub fn new(cc: &eframe::CreationContext<'_>) -> Option<Self> {
         use std::sync::Arc;
+
+        let pipelines = GpuPipelines::new(cc.wgpu_render_state.as_ref()?);
         
         let file_dialog = FileDialog::new().add_file_filter(
             "Image Files",
@@ -40,9 +90,28 @@ impl FilmEmulator {
         );
         
         Some(Self {
+            pipelines,
             file_dialog,
             selected_image_path: None,
             controls: None,
+            hsl_mixer: HslMixerState::default(),
+            histogram: HistogramView::default(),
+            scopes: ScopesView::default(),
+            probe: PixelProbeView::default(),
+            geometry: Geometry::default(),
+            crop_tool: CropTool::default(),
+            lens: LensCorrection::default(),
+            local_adjustments: LocalAdjustments::default(),
+            mask_tool: MaskTool::default(),
+            spot_healing: SpotHealing::default(),
+            spot_tool: SpotTool::default(),
+            noise_reduction: NoiseReduction::default(),
+            adjustment_stack: AdjustmentStack::default(),
+            unreadable_sidecar: None,
+            save_error: None,
+            bypass: StageBypass::default(),
+            airlight: [1.0; 3],
+            source_size: [1, 1],
             image: None,
             image_loaded: false,
             export_pending: false,
@@ -52,16 +121,15 @@ impl FilmEmulator {
     fn load_image(&mut self, path: PathBuf, wgpu_render_state: &eframe::egui_wgpu::RenderState) {
         let device = &wgpu_render_state.device;
         let queue = &wgpu_render_state.queue;
-        let gpu_render_pipeline = GpuImageRenderPipeline::new(&device);
-        let gpu_compute_pipeline = GpuImageComputePipeline::new(&device);
-        let mut image_texture_view = ImageTextureView::default();
-        let image_controls = ImageControls::default();
 
         let path_str = path.to_string_lossy().to_string();
-        let (width, height, pixels) = load_image_to_linear_rgb(&path_str);
+        let decoded = load_image_to_linear_rgb(&path_str);
+        let (width, height) = (decoded.width, decoded.height);
+        let search = SpotSearch::new(&decoded.pixels, width, height);
+        self.airlight = estimate_airlight(&decoded.pixels, width, height);
         let mut rgba_pixels = Vec::<f32>::with_capacity((width * height * 4) as usize);
 
-        for [r, g, b] in pixels {
+        for [r, g, b] in decoded.pixels {
             rgba_pixels.push(r);
             rgba_pixels.push(g);
             rgba_pixels.push(b);
@@ -85,27 +153,179 @@ impl FilmEmulator {
             view_formats: &[],
         });
 
-        let texture_view = texture.create_view(&wgpu::TextureViewDescriptor {
-            label: Some("RAW Texture View"),
-            ..Default::default()
-        });
-
-        let processed_texture = device.create_texture(&TextureDescriptor {
-            label: Some("Processed Texture"),
-            size: Extent3d {
+        queue.write_texture(
+            TexelCopyTextureInfo {
+                texture: &texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            bytemuck::cast_slice(&rgba_pixels),
+            TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(16 * width),
+                rows_per_image: Some(height),
+            },
+            Extent3d {
                 width,
                 height,
                 depth_or_array_layers: 1,
             },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: TextureDimension::D2,
-            format: TextureFormat::Rgba8Unorm,
-            usage: TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_SRC,
-            view_formats: &[],
+        );
+
+        self.controls = Some(ImageControls::default());
+        self.geometry = Geometry {
+            source_orientation: decoded.orientation,
+            ..Geometry::default()
+        };
+        self.crop_tool = CropTool::default();
+        self.lens = LensCorrection::for_lens(decoded.lens.as_ref());
+        self.local_adjustments = LocalAdjustments::default();
+        self.mask_tool = MaskTool::default();
+        self.spot_healing = SpotHealing::default();
+        self.spot_tool = SpotTool::default();
+        self.spot_tool.search = search;
+        self.noise_reduction = NoiseReduction::default();
+        self.adjustment_stack = AdjustmentStack::default();
+        self.unreadable_sidecar = None;
+        self.save_error = None;
+        match ProcessingState::load(&path) {
+            Ok(Some(saved)) => {
+                self.controls = Some(saved.controls);
+                self.geometry = Geometry {
+                    source_orientation: decoded.orientation,
+                    ..saved.geometry
+                };
+                self.lens.restore(saved.lens);
+                self.local_adjustments = saved.local_adjustments;
+                self.spot_healing = saved.spot_healing;
+                self.noise_reduction = saved.noise_reduction;
+                self.adjustment_stack = AdjustmentStack::restore(saved.adjustment_stack);
+            }
+            Ok(None) => {}
+            Err(err) => self.unreadable_sidecar = Some(err.to_string()),
+        }
+        self.probe = PixelProbeView::default();
+        self.source_size = [width, height];
+        self.image = Some(ImageTextureView::default());
+        self.create_resources(wgpu_render_state, texture);
+        self.image_loaded = true;
+    }
+
+    /// Writes the current edit next to the source image. Saving on purpose replaces a saved
+    /// edit that couldn't be loaded.
+    fn save_processing_state(&mut self) {
+        if let (Some(path), Some(controls)) = (&self.selected_image_path, self.controls) {
+            let state = ProcessingState {
+                controls,
+                geometry: self.geometry,
+                lens: self.lens.clone(),
+                local_adjustments: self.local_adjustments.clone(),
+                spot_healing: self.spot_healing.clone(),
+                noise_reduction: self.noise_reduction,
+                adjustment_stack: self.adjustment_stack.clone(),
+            };
+            match state.save(path) {
+                Ok(()) => {
+                    self.unreadable_sidecar = None;
+                    self.save_error = None;
+                }
+                Err(err) => {
+                    eprintln!("Failed to save edit for {:?}: {}", path, err);
+                    self.save_error = Some(err.to_string());
+                }
+            }
+        }
+    }
+
+    /// Creates everything sized to the framed image and installs it as the paint callback's
+    /// resources. Runs on load and again whenever the geometry changes the output size.
+    fn create_resources(&mut self, wgpu_render_state: &eframe::egui_wgpu::RenderState, raw_texture: wgpu::Texture) {
+        let device = &wgpu_render_state.device;
+        let GpuPipelines {
+            render: gpu_render_pipeline,
+            compute: gpu_compute_pipeline,
+            geometry: gpu_geometry_pipeline,
+            histogram: gpu_histogram_pipeline,
+            scopes: gpu_scopes_pipeline,
+            brush_mask: gpu_brush_mask_pipeline,
+            sharpen: gpu_sharpen_pipeline,
+            denoise: gpu_denoise_pipeline,
+            local_contrast: gpu_local_contrast_pipeline,
+            local_adjustments: gpu_local_adjustments_pipeline,
+        } = &self.pipelines;
+        let image_controls = self.controls.unwrap_or_default();
+
+        let [width, height] = self.geometry.output_size(self.source_size, self.crop_tool.active);
+
+        let texture_view = raw_texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("RAW Texture View"),
+            ..Default::default()
         });
 
-        let processed_view = processed_texture.create_view(&Default::default());
+        let mut pool = IntermediatePool::new(device);
+
+        // Source after crop, rotation and flips, what the colour pipeline reads
+        let geometry_texture = pool.output(
+            "Geometry Texture",
+            Intermediate::new([width, height], TextureFormat::Rgba32Float),
+            TextureUsages::COPY_SRC,
+        );
+
+        let geometry_view = geometry_texture.create_view(&Default::default());
+
+        let geometry_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Geometry Buffer"),
+            contents: bytemuck::bytes_of(&self.geometry_uniform()),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        // Fixed size so the bind group survives spots being added, zeroed means no spots
+        let spot_list_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Heal Spot Buffer"),
+            size: SPOT_LIST_HEADER + (MAX_HEAL_SPOTS * size_of::<SpotGpu>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let spot_points_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Heal Spot Points Buffer"),
+            size: (MAX_HEAL_POINTS * size_of::<[f32; 2]>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let geometry_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Geometry Bind Group"),
+            layout: &gpu_geometry_pipeline.bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(&geometry_view),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: geometry_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: spot_list_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: spot_points_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let geometry = ComputeStage::new(
+            device,
+            Stage::Geometry,
+            &gpu_geometry_pipeline.pipeline,
+            &gpu_geometry_pipeline.input_layout,
+            &texture_view,
+            geometry_bind_group,
+            &geometry_view,
+        );
 
         let settings_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Settings Buffer"),
@@ -113,6 +333,59 @@ impl FilmEmulator {
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
+        // Each stage reads the one before it, the graph rebinds them around any it leaves out.
+        // The brush guide and the before view read the geometry output, they never see noise
+        // reduction.
+        let denoise = DenoiseResources::new(device, &mut pool, gpu_denoise_pipeline, &geometry_view, [width, height]);
+        let sharpen = SharpenResources::new(device, &mut pool, gpu_sharpen_pipeline, denoise.output(), &settings_buffer, [width, height]);
+        let local_contrast = LocalContrastResources::new(
+            device,
+            &mut pool,
+            gpu_local_contrast_pipeline,
+            sharpen.output(),
+            &settings_buffer,
+            [width, height],
+            self.airlight,
+        );
+        let brush_masks = BrushMaskResources::new(device, &mut pool, gpu_brush_mask_pipeline, &geometry_view, [width, height]);
+        let local_adjustments = LocalAdjustmentsResources::new(
+            device,
+            &mut pool,
+            gpu_local_adjustments_pipeline,
+            brush_masks,
+            local_contrast.output(),
+            &settings_buffer,
+            [width, height],
+        );
+
+        let processed_texture = pool.output(
+            "Processed Texture",
+            Intermediate::new([width, height], TextureFormat::Rgba8Unorm),
+            TextureUsages::COPY_SRC,
+        );
+
+        let processed_view = processed_texture.create_view(&Default::default());
+
+        // Edited colour before the output clamp, for the clipping and gamut overlays
+        let working_texture = pool.output(
+            "Working Texture",
+            Intermediate::new([width, height], TextureFormat::Rgba16Float),
+            TextureUsages::COPY_SRC,
+        );
+
+        let working_view = working_texture.create_view(&Default::default());
+
+        // Unedited image through the output transform, for before/after
+        let original_view = pool
+            .output("Original Texture", Intermediate::new([width, height], TextureFormat::Rgba8Unorm), TextureUsages::empty())
+            .create_view(&Default::default());
+
+        let adjustment_stack_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Adjustment Stack Buffer"),
+            contents: bytemuck::bytes_of(&self.adjustment_stack.uniform()),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
         let viewport_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Viewport buffer"),
             size: size_of::<ViewportUniform>() as u64,
@@ -124,10 +397,6 @@ impl FilmEmulator {
             label: Some("Compute Bind Group"),
             layout: &gpu_compute_pipeline.bind_group_layout,
             entries: &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: BindingResource::TextureView(&texture_view),
-                },
                 BindGroupEntry {
                     binding: 1,
                     resource: BindingResource::TextureView(&processed_view),
@@ -142,31 +411,112 @@ impl FilmEmulator {
                         },
                     ),
                 },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::TextureView(&working_view),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: BindingResource::TextureView(&original_view),
+                },
+                BindGroupEntry {
+                    binding: 5,
+                    resource: BindingResource::TextureView(&geometry_view),
+                },
+                BindGroupEntry {
+                    binding: 6,
+                    resource: adjustment_stack_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 7,
+                    resource: BindingResource::TextureView(&local_adjustments.overlay_view),
+                },
             ],
         });
 
-        let compute_pipeline = gpu_compute_pipeline.pipeline.clone();
+        let color = ComputeStage::new(
+            device,
+            Stage::Color,
+            &gpu_compute_pipeline.pipeline,
+            &gpu_compute_pipeline.input_layout,
+            local_adjustments.output(),
+            compute_bind_group,
+            &processed_view,
+        );
 
-        queue.write_texture(
-            TexelCopyTextureInfo {
-                texture: &texture,
-                mip_level: 0,
-                origin: Origin3d::ZERO,
-                aspect: TextureAspect::All,
-            },
-            bytemuck::cast_slice(&rgba_pixels),
-            TexelCopyBufferLayout {
-                offset: 0,
-                bytes_per_row: Some(16 * width),
-                rows_per_image: Some(height),
-            },
-            Extent3d {
-                width,
-                height,
-                depth_or_array_layers: 1,
-            },
+        let graph = ProcessingGraph::new(
+            &texture_view,
+            vec![
+                Box::new(geometry),
+                Box::new(denoise),
+                Box::new(sharpen),
+                Box::new(local_contrast),
+                Box::new(local_adjustments),
+                Box::new(color),
+            ],
         );
 
+        let range_picker = RangePicker::new(device, gpu_local_adjustments_pipeline, &settings_buffer);
+
+        let histogram_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Histogram Buffer"),
+            size: (HISTOGRAM_BINS * size_of::<u32>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let histogram_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Histogram Bind Group"),
+            layout: &gpu_histogram_pipeline.bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&processed_view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: histogram_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let histogram_readback = GpuReadback::new(device, "Histogram Readback Buffer", histogram_buffer.size());
+
+        let scope_buffer = |label: &str, bins: usize| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: (bins * size_of::<u32>()) as u64,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            })
+        };
+        let waveform_buffer = scope_buffer("Waveform Buffer", WAVEFORM_BINS);
+        let vectorscope_buffer = scope_buffer("Vectorscope Buffer", VECTORSCOPE_BINS);
+
+        let scopes_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Scopes Bind Group"),
+            layout: &gpu_scopes_pipeline.bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&processed_view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: waveform_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: vectorscope_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let waveform_readback = GpuReadback::new(device, "Waveform Readback Buffer", waveform_buffer.size());
+        let vectorscope_readback = GpuReadback::new(device, "Vectorscope Readback Buffer", vectorscope_buffer.size());
+
+        let probe_readback = GpuReadback::new(device, "Pixel Probe Readback Buffer", MAX_PROBE_POINTS as u64 * PROBE_SLOT_BYTES);
+
         let render_bind_group = device.create_bind_group(&BindGroupDescriptor {
             label: Some("Render Bind Group"),
             layout: &gpu_render_pipeline.bind_group_layout,
@@ -179,6 +529,14 @@ impl FilmEmulator {
                     binding: 1,
                     resource: viewport_buffer.as_entire_binding(),
                 },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::TextureView(&working_view),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::TextureView(&original_view),
+                },
             ],
         });
 
@@ -190,23 +548,125 @@ impl FilmEmulator {
             .insert(ImageRenderResources {
                 render_pipeline,
                 render_bind_group,
-                compute_pipeline,
-                compute_bind_group,
+                graph,
+                range_picker,
+                histogram_pipeline: gpu_histogram_pipeline.pipeline.clone(),
+                histogram_bind_group,
+                histogram_buffer,
+                histogram_readback,
+                waveform_pipeline: gpu_scopes_pipeline.waveform_pipeline.clone(),
+                vectorscope_pipeline: gpu_scopes_pipeline.vectorscope_pipeline.clone(),
+                scopes_bind_group,
+                waveform_buffer,
+                vectorscope_buffer,
+                waveform_readback,
+                vectorscope_readback,
                 settings_buffer,
+                adjustment_stack_buffer,
                 viewport_buffer,
+                geometry_buffer,
+                spot_list_buffer,
+                spot_points_buffer,
+                raw_texture,
+                geometry_texture,
+                working_texture,
                 processed_texture,
+                probe_readback,
+                probe_points: Default::default(),
                 width: width as i32,
                 height: height as i32,
             });
 
-        image_texture_view.image_width = width as f32;
-        image_texture_view.image_height = height as f32;
+        if let Some(image) = &mut self.image {
+            image.image_width = width as f32;
+            image.image_height = height as f32;
+        }
+    }
 
-        self.controls = Some(image_controls);
-        self.image = Some(image_texture_view);
-        self.image_loaded = true;
+    fn geometry_uniform(&self) -> GeometryUniform {
+        let lens = self.lens.coefficients(self.source_size);
+        self.geometry.uniform(self.source_size, self.crop_tool.active, &lens)
     }
-    
+
+    fn local_adjustments_uniform(&self) -> LocalAdjustmentsUniform {
+        self.local_adjustments
+            .uniform(&self.geometry, self.source_size, self.crop_tool.active, self.mask_tool.overlay())
+    }
+
+    /// Rebuilds the per-image resources if the geometry now gives a different output size
+    fn sync_geometry_size(&mut self, wgpu_render_state: &eframe::egui_wgpu::RenderState) {
+        let [width, height] = self.geometry.output_size(self.source_size, self.crop_tool.active);
+        let raw_texture = {
+            let renderer = wgpu_render_state.renderer.read();
+            let Some(resources) = renderer.callback_resources.get::<ImageRenderResources>() else {
+                return;
+            };
+            if resources.width == width as i32 && resources.height == height as i32 {
+                return;
+            }
+            resources.raw_texture.clone()
+        };
+        self.create_resources(wgpu_render_state, raw_texture);
+        // Pinned points were in the old frame
+        self.probe.pinned.clear();
+    }
+
+    /// Target adjustment: pick the hue under the cursor when a drag starts, then push the
+    /// matching HSL bands up or down as the pointer moves vertically.
+    fn handle_hsl_target(&mut self, response: &egui::Response, wgpu_render_state: &eframe::egui_wgpu::RenderState) {
+        if response.drag_started() {
+            self.hsl_mixer.target_hue = None;
+            let coords = response
+                .interact_pointer_pos()
+                .and_then(|pos| self.image.as_ref()?.image_coords(pos));
+            if let Some([x, y]) = coords {
+                let renderer = wgpu_render_state.renderer.read();
+                if let Some(resources) = renderer.callback_resources.get::<ImageRenderResources>() {
+                    let pixel = resources.read_processed_pixel(&wgpu_render_state.device, &wgpu_render_state.queue, x, y);
+                    self.hsl_mixer.target_hue = pixel.and_then(|[r, g, b, _]| {
+                        rgb_to_hue([r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0])
+                    });
+                }
+            }
+        }
+
+        if response.dragged()
+            && let (Some(hue), Some(controls)) = (self.hsl_mixer.target_hue, &mut self.controls)
+        {
+            // 100 points of drag covers the full saturation/luminance range, hue moves in degrees
+            let scale = match self.hsl_mixer.property {
+                HslProperty::Hue => 0.3,
+                _ => 0.01,
+            };
+            controls.adjust_hsl_for_hue(self.hsl_mixer.property, hue, -response.drag_delta().y * scale);
+        }
+
+        if response.drag_stopped() {
+            self.hsl_mixer.target_hue = None;
+        }
+    }
+
+    /// Range mask eyedropper: the colour under the click as the range masks see it, after
+    /// global exposure and the output transform, becomes the selected layer's colour range
+    fn pick_range_color(&mut self, response: &egui::Response, wgpu_render_state: &eframe::egui_wgpu::RenderState) {
+        let coords = response
+            .interact_pointer_pos()
+            .and_then(|pos| self.image.as_ref()?.image_coords(pos));
+        let Some([x, y]) = coords else {
+            return;
+        };
+        let pixel = {
+            let renderer = wgpu_render_state.renderer.read();
+            let Some(resources) = renderer.callback_resources.get::<ImageRenderResources>() else {
+                return;
+            };
+            resources.read_range_color(&wgpu_render_state.device, &wgpu_render_state.queue, x, y)
+        };
+        if let Some(rgb) = pixel {
+            self.mask_tool.pick_color(&mut self.local_adjustments, rgb);
+        }
+    }
+
     fn export_image(&self, save_path: PathBuf, wgpu_render_state: &eframe::egui_wgpu::RenderState) {
         use image::{ImageBuffer, Rgba};
         
@@ -319,6 +779,11 @@ impl eframe::App for FilmEmulator {
                 if let Some(rs) = frame.wgpu_render_state() {
                     self.export_image(path, rs);
                 }
+                // An exported look should be reproducible from the original, but not at the
+                // cost of a saved edit that failed to load
+                if self.unreadable_sidecar.is_none() {
+                    self.save_processing_state();
+                }
                 self.export_pending = false;
             }
         } else {
@@ -338,6 +803,13 @@ impl eframe::App for FilmEmulator {
             }
         }
 
+        // Crop and rotation change the size of everything downstream
+        if self.image_loaded
+            && let Some(rs) = frame.wgpu_render_state()
+        {
+            self.sync_geometry_size(rs);
+        }
+
         // Prepare resources if image is loaded
         if self.image_loaded {
             if let (Some(rs), Some(image)) = (frame.wgpu_render_state(), &self.image) {
@@ -350,14 +822,151 @@ impl eframe::App for FilmEmulator {
                         .callback_resources
                         .get_mut::<ImageRenderResources>()
                     {
+                        let geometry = self.geometry_uniform();
+                        resources.write_geometry(queue, &geometry);
+                        let (spots, spot_points) = self.spot_healing.gpu(self.source_size);
+                        resources.write_spots(queue, &spots, &spot_points);
+                        resources.write_stage_bypass(self.bypass);
+                        resources.write_adjustment_stack(queue, &self.adjustment_stack.uniform());
+                        let brush_masks = BrushMaskData::new(
+                            &self.local_adjustments,
+                            &self.geometry,
+                            self.source_size,
+                            self.crop_tool.active,
+                            BrushGuide { geometry, spots, spot_points },
+                        );
                         if let Some(controls) = &self.controls {
-                            resources.prepare(device, queue, controls, rect);
+                            let controls = &controls.showing_mask(self.mask_tool.overlay().is_some()).bypassing(&self.bypass);
+                            let stage_frame = StageFrame {
+                                device,
+                                queue,
+                                controls,
+                                noise_reduction: &self.noise_reduction,
+                                local_adjustments: &self.local_adjustments_uniform(),
+                                brush_masks: &brush_masks,
+                            };
+                            resources.prepare(&stage_frame, rect, &image.effective_options(), self.scopes.wants_data());
+
+                            if let Some(points) = self.probe.due_points(controls)
+                                && resources.probe_pixels(device, queue, &points)
+                            {
+                                self.probe.mark_requested(points, controls);
+                            }
+                        }
+                        if let Some(samples) = resources.take_probe() {
+                            self.probe.set_samples(samples);
+                        }
+                        if let Some(bins) = resources.take_histogram() {
+                            self.histogram.set_bins(bins);
+                        }
+                        if let Some(bins) = resources.waveform_readback.take_u32() {
+                            self.scopes.set_waveform(bins);
+                        }
+                        if let Some(bins) = resources.vectorscope_readback.take_u32() {
+                            self.scopes.set_vectorscope(bins);
+                        }
+                        if resources.readbacks_pending() {
+                            // Keep frames coming until the readback lands
+                            ctx.request_repaint();
                         }
                     }
                 }
             }
         }
 
+        if self.image_loaded {
+            egui::SidePanel::left("image_controls")
+                .resizable(true)
+                .default_width(320.0)
+                .show(ctx, |ui| {
+                    if let Some(controls) = &mut self.controls {
+                        self.histogram.ui(ui, controls);
+                        ui.checkbox(&mut self.scopes.visible, "Show Scopes");
+                        ui.separator();
+                    }
+
+                    egui::ScrollArea::vertical().show(ui, |ui| {
+                        egui::CollapsingHeader::new("Crop & Rotate").show(ui, |ui| {
+                            let cropping = self.crop_tool.active;
+                            self.crop_tool.panel_ui(ui, &mut self.geometry, self.source_size);
+                            // One tool on the canvas at a time
+                            if self.crop_tool.active && !cropping {
+                                self.mask_tool.active = false;
+                                self.spot_tool.active = false;
+                            }
+                        });
+                        egui::CollapsingHeader::new("Lens Correction").show(ui, |ui| {
+                            self.lens.ui(ui);
+                        });
+                        egui::CollapsingHeader::new("Spot Removal").show(ui, |ui| {
+                            let healing = self.spot_tool.active;
+                            self.spot_tool.panel_ui(ui, &mut self.spot_healing, self.source_size);
+                            if self.spot_tool.active && !healing {
+                                self.crop_tool.active = false;
+                                self.mask_tool.active = false;
+                            }
+                        });
+                        egui::CollapsingHeader::new("Noise Reduction").show(ui, |ui| {
+                            self.noise_reduction.ui(ui);
+                        });
+                        egui::CollapsingHeader::new("Local Adjustments").show(ui, |ui| {
+                            let editing = self.mask_tool.active;
+                            self.mask_tool.panel_ui(ui, &mut self.local_adjustments, &self.geometry, self.source_size, self.crop_tool.active);
+                            if self.mask_tool.active && !editing {
+                                self.crop_tool.active = false;
+                                self.spot_tool.active = false;
+                            }
+                        });
+                        egui::CollapsingHeader::new("Adjustment Stack").show(ui, |ui| {
+                            self.adjustment_stack.ui(ui);
+                        });
+                        egui::CollapsingHeader::new("Processing Stages").show(ui, |ui| {
+                            self.bypass.ui(ui);
+                        });
+
+                        if let Some(controls) = &mut self.controls {
+                            controls.ui(ui);
+                            controls.hsl_ui(ui, &mut self.hsl_mixer);
+
+                            ui.separator();
+
+                            ui.horizontal(|ui| {
+                                if ui.button("Save Edit").on_hover_text("Stored next to the image and restored when it's opened again").clicked() {
+                                    self.save_processing_state();
+                                }
+
+                                // Export button
+                                if ui.button("Export Image").clicked() {
+                                    // Export is whatever the viewer holds, so apply the crop and drop the mask overlay first
+                                    self.crop_tool.active = false;
+                                    self.mask_tool.active = false;
+                                    self.export_pending = true;
+                                    self.file_dialog.save_file();
+                                }
+                            });
+                            if let Some(err) = &self.unreadable_sidecar {
+                                ui.colored_label(
+                                    ui.visuals().error_fg_color,
+                                    format!("The saved edit couldn't be loaded ({err}). Exporting won't replace it, saving will."),
+                                );
+                            }
+                            if let Some(err) = &self.save_error {
+                                ui.colored_label(ui.visuals().error_fg_color, format!("The edit couldn't be saved ({err})."));
+                            }
+                        }
+                    });
+                });
+        }
+
+        if self.image_loaded {
+            self.scopes.show(ctx);
+
+            egui::TopBottomPanel::bottom("pixel_probe").show(ctx, |ui| {
+                self.probe.ui(ui);
+            });
+        }
+
+        let mut image_response = None;
         egui::CentralPanel::default().show(ctx, |ui| {
             if !self.image_loaded {
                 // Show file picker UI when no image is loaded
@@ -373,22 +982,62 @@ impl eframe::App for FilmEmulator {
                     }
                 });
             } else {
-                // Show controls and image when loaded
-                if let Some(controls) = &mut self.controls {
-                    controls.ui(ui);
-                    
-                    ui.separator();
-                    
-                    // Export button
-                    if ui.button("Export Image").clicked() {
-                        self.export_pending = true;
-                        self.file_dialog.save_file();
-                    }
-                }
+                // Controls live in the side panel, the image fills the rest
                 if let Some(image) = &mut self.image {
-                    image.ui(ui);
+                    if self.crop_tool.active || self.mask_tool.active || self.spot_tool.active {
+                        // The crop, mask and spot overlays are drawn over one whole image
+                        image.options.compare_mode = COMPARE_OFF;
+                    }
+                    let response = image.ui(ui);
+                    if self.crop_tool.active {
+                        if let Some(frame_rect) = image.image_rect() {
+                            self.crop_tool.ui(ui, &response, frame_rect, &mut self.geometry, self.source_size);
+                        }
+                    } else if self.mask_tool.active {
+                        if let Some(frame_rect) = image.image_rect() {
+                            self.mask_tool.ui(ui, &response, frame_rect, &mut self.local_adjustments, &self.geometry, self.source_size);
+                        }
+                    } else if self.spot_tool.active {
+                        if let Some(frame_rect) = image.image_rect() {
+                            self.spot_tool.ui(ui, &response, frame_rect, &mut self.spot_healing, &self.geometry, self.source_size);
+                        }
+                    } else {
+                        image.paint_sample_markers(ui, &self.probe.pinned);
+                    }
+                    image_response = Some(response);
                 }
             }
         });
+
+        if let (Some(response), Some(image)) = (&image_response, &self.image) {
+            self.probe.hover = response.hover_pos().and_then(|pos| image.image_coords(pos));
+            if response.clicked()
+                && !self.crop_tool.active
+                && !self.mask_tool.active
+                && !self.spot_tool.active
+                && ctx.input(|i| i.modifiers.shift)
+                && let Some(coords) = self.probe.hover
+            {
+                self.probe.toggle_pin(coords);
+            }
+        }
+
+        if let (Some(response), Some(rs)) = (&image_response, frame.wgpu_render_state())
+            && self.mask_tool.active
+            && self.mask_tool.picking_color
+            && response.clicked()
+        {
+            self.pick_range_color(response, rs);
+        }
+
+        if let (Some(response), Some(rs)) = (image_response, frame.wgpu_render_state())
+            && self.hsl_mixer.targeting
+            && !self.crop_tool.active
+            && !self.mask_tool.active
+            && !self.spot_tool.active
+            && !self.image.as_ref().is_some_and(|image| image.is_dragging_divider())
+        {
+            self.handle_hsl_target(&response, rs);
+        }
     }
 }
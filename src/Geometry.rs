@@ -0,0 +1,508 @@
+use crate::LensCorrection::LensCoefficients;
+
+/// Output pixel -> source pixel transform for geometry.wgsl, as two rows of a 2x3 affine
+/// matrix, followed by the lens corrections which happen in source pixels
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GeometryUniform {
+    pub row_x: [f32; 4],
+    pub row_y: [f32; 4],
+    pub lens_center: [f32; 4], // centre x, y, 1 / half shorter side, 1 / half diagonal
+    pub distortion: [f32; 4],  // Ru^1..Ru^4 terms of Rd / Ru
+    pub lens: [f32; 4],        // constant term, red scale, blue scale, fill scale
+    pub vignetting: [f32; 4],  // k1..k3, unused
+}
+
+/// Long side over short side. Which way round it goes is `Geometry::aspect_portrait`.
+pub const ASPECT_PRESETS: [(&str, Option<f32>); 7] = [
+    ("Free", None),
+    ("Original", None),
+    ("3:2", Some(3.0 / 2.0)),
+    ("4:5", Some(5.0 / 4.0)),
+    ("1:1", Some(1.0)),
+    // 56 x 69.5mm frame
+    ("6x7", Some(69.5 / 56.0)),
+    ("65:24 XPan", Some(65.0 / 24.0)),
+];
+pub const ASPECT_FREE: usize = 0;
+pub const ASPECT_ORIGINAL: usize = 1;
+
+/// Straighten is limited to this many degrees either way
+pub const MAX_STRAIGHTEN: f32 = 45.0;
+
+/// A quarter-turn rotation followed by an optional left-right mirror, which covers all
+/// eight EXIF orientations
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub struct Orientation {
+    /// Clockwise 90 degree turns, 0..4
+    pub quarter_turns: u32,
+    pub flip_horizontal: bool,
+}
+
+/// EXIF orientation values 1..=8 in order, with names for the override menu
+pub const EXIF_ORIENTATION_NAMES: [&str; 8] = [
+    "Normal",
+    "Mirrored",
+    "Rotated 180°",
+    "Mirrored Vertically",
+    "Mirrored, Rotated 90° CW",
+    "Rotated 90° CW",
+    "Mirrored, Rotated 90° CCW",
+    "Rotated 90° CCW",
+];
+
+impl Orientation {
+    /// From the EXIF Orientation tag (1..=8), anything else reads as upright
+    pub fn from_exif(value: u8) -> Self {
+        let (quarter_turns, flip_horizontal) = match value {
+            2 => (0, true),
+            3 => (2, false),
+            4 => (2, true),
+            5 => (1, true),
+            6 => (1, false),
+            7 => (3, true),
+            8 => (3, false),
+            _ => (0, false),
+        };
+        Self { quarter_turns, flip_horizontal }
+    }
+
+    pub fn to_exif(self) -> u8 {
+        (1..=8).find(|&v| Self::from_exif(v) == self).unwrap_or(1)
+    }
+
+    /// From LibRaw's `sizes.flip`: 3 is upside down, 5 and 6 are turned a quarter
+    pub fn from_libraw_flip(flip: i32) -> Self {
+        match flip {
+            3 => Self::from_exif(3),
+            5 => Self::from_exif(8),
+            6 => Self::from_exif(6),
+            _ => Self::default(),
+        }
+    }
+
+    /// This orientation followed by `next`
+    fn then(self, next: Orientation) -> Orientation {
+        // A mirror reverses the direction of any turn that comes after it
+        let turns = if self.flip_horizontal { 4 - next.quarter_turns % 4 } else { next.quarter_turns };
+        Orientation {
+            quarter_turns: (self.quarter_turns + turns) % 4,
+            flip_horizontal: self.flip_horizontal != next.flip_horizontal,
+        }
+    }
+}
+
+/// Crop rectangle, normalised to the rotated and flipped frame
+#[derive(Debug, Copy, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CropRect {
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+}
+
+impl CropRect {
+    pub const FULL: CropRect = CropRect { x: 0.0, y: 0.0, w: 1.0, h: 1.0 };
+
+    fn center(&self) -> [f32; 2] {
+        [self.x + self.w * 0.5, self.y + self.h * 0.5]
+    }
+}
+
+type Affine = [[f32; 3]; 3];
+
+fn multiply(a: &Affine, b: &Affine) -> Affine {
+    let mut out = [[0.0; 3]; 3];
+    for (i, row) in out.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    out
+}
+
+fn translate(x: f32, y: f32) -> Affine {
+    [[1.0, 0.0, x], [0.0, 1.0, y], [0.0, 0.0, 1.0]]
+}
+
+fn linear(xx: f32, xy: f32, yx: f32, yy: f32) -> Affine {
+    [[xx, xy, 0.0], [yx, yy, 0.0], [0.0, 0.0, 1.0]]
+}
+
+/// Non-destructive framing of the image. Applied in order: the camera's orientation,
+/// quarter turns, flips, straighten, crop.
+#[derive(Debug, Copy, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Geometry {
+    /// Orientation recorded in the file, read again on load rather than saved
+    #[serde(skip)]
+    pub source_orientation: Orientation,
+    /// Replaces `source_orientation` when the camera got it wrong
+    pub orientation_override: Option<Orientation>,
+    /// Clockwise 90 degree turns, 0..4
+    pub quarter_turns: u32,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+    /// Degrees, positive turns the picture counter-clockwise
+    pub straighten: f32,
+    pub crop: CropRect,
+    /// Index into ASPECT_PRESETS
+    pub aspect: usize,
+    pub aspect_portrait: bool,
+}
+
+impl Default for Geometry {
+    fn default() -> Self {
+        Self {
+            source_orientation: Orientation::default(),
+            orientation_override: None,
+            quarter_turns: 0,
+            flip_horizontal: false,
+            flip_vertical: false,
+            straighten: 0.0,
+            crop: CropRect::FULL,
+            aspect: ASPECT_FREE,
+            aspect_portrait: false,
+        }
+    }
+}
+
+impl Geometry {
+    /// This geometry with an aspect preset that isn't one put back to free, for geometry
+    /// read from a file
+    pub fn validated(self) -> Self {
+        let aspect = if self.aspect < ASPECT_PRESETS.len() { self.aspect } else { ASPECT_FREE };
+        Self { aspect, ..self }
+    }
+
+    /// Upright as far as the camera (or the override) knows, before any edits
+    pub fn base_orientation(&self) -> Orientation {
+        self.orientation_override.unwrap_or(self.source_orientation)
+    }
+
+    /// Base orientation followed by the user's turns and flips
+    fn orientation(&self) -> Orientation {
+        // A vertical flip is a half turn and a horizontal flip
+        let edit = Orientation {
+            quarter_turns: self.quarter_turns + if self.flip_vertical { 2 } else { 0 },
+            flip_horizontal: self.flip_horizontal != self.flip_vertical,
+        };
+        self.base_orientation().then(edit)
+    }
+
+    /// Switches the base orientation. The crop was relative to the old frame so it's reset.
+    pub fn set_orientation_override(&mut self, orientation: Option<Orientation>) {
+        if orientation != self.orientation_override {
+            self.orientation_override = orientation;
+            self.crop = CropRect::FULL;
+        }
+    }
+
+    /// Size of the frame after orientation and quarter turns, in pixels
+    pub fn oriented_size(&self, source: [u32; 2]) -> [f32; 2] {
+        if self.orientation().quarter_turns % 2 == 1 {
+            [source[1] as f32, source[0] as f32]
+        } else {
+            [source[0] as f32, source[1] as f32]
+        }
+    }
+
+    /// Size of the image the colour pipeline sees. With `full_frame` the crop is left out,
+    /// that's what the crop tool shows while it's being edited.
+    pub fn output_size(&self, source: [u32; 2], full_frame: bool) -> [u32; 2] {
+        let [w, h] = self.oriented_size(source);
+        if full_frame {
+            return [w as u32, h as u32];
+        }
+        [
+            ((self.crop.w * w).round() as u32).max(1),
+            ((self.crop.h * h).round() as u32).max(1),
+        ]
+    }
+
+    /// Output pixel to source pixel, leaving the lens out
+    fn source_transform(&self, source: [u32; 2], full_frame: bool) -> Affine {
+        let [sw, sh] = [source[0] as f32, source[1] as f32];
+        let [w, h] = self.oriented_size(source);
+
+        // Built backwards, from an output pixel to where it comes from in the source
+        let uncrop = if full_frame {
+            translate(0.0, 0.0)
+        } else {
+            translate(self.crop.x * w, self.crop.y * h)
+        };
+
+        let (sin, cos) = self.straighten.to_radians().sin_cos();
+        let unstraighten = multiply(
+            &translate(w * 0.5, h * 0.5),
+            &multiply(&linear(cos, -sin, sin, cos), &translate(-w * 0.5, -h * 0.5)),
+        );
+
+        let orientation = self.orientation();
+        let unflip = if orientation.flip_horizontal {
+            multiply(&translate(w, 0.0), &linear(-1.0, 0.0, 0.0, 1.0))
+        } else {
+            translate(0.0, 0.0)
+        };
+
+        let unrotate = match orientation.quarter_turns % 4 {
+            1 => multiply(&translate(0.0, sh), &linear(0.0, 1.0, -1.0, 0.0)),
+            2 => multiply(&translate(sw, sh), &linear(-1.0, 0.0, 0.0, -1.0)),
+            3 => multiply(&translate(sw, 0.0), &linear(0.0, -1.0, 1.0, 0.0)),
+            _ => translate(0.0, 0.0),
+        };
+
+        multiply(&unrotate, &multiply(&unflip, &multiply(&unstraighten, &uncrop)))
+    }
+
+    /// Where an output pixel position comes from in the source, in pixels
+    pub fn source_point(&self, source: [u32; 2], full_frame: bool, p: [f32; 2]) -> [f32; 2] {
+        let m = self.source_transform(source, full_frame);
+        [
+            m[0][0] * p[0] + m[0][1] * p[1] + m[0][2],
+            m[1][0] * p[0] + m[1][1] * p[1] + m[1][2],
+        ]
+    }
+
+    /// Where a source pixel position ends up in the output, in pixels
+    pub fn output_point(&self, source: [u32; 2], full_frame: bool, p: [f32; 2]) -> [f32; 2] {
+        let m = self.source_transform(source, full_frame);
+        // Rotations and flips only, so the determinant is never zero
+        let det = m[0][0] * m[1][1] - m[0][1] * m[1][0];
+        let [x, y] = [p[0] - m[0][2], p[1] - m[1][2]];
+        [
+            (m[1][1] * x - m[0][1] * y) / det,
+            (m[0][0] * y - m[1][0] * x) / det,
+        ]
+    }
+
+    /// `source_point` as the two rows the shaders take
+    pub fn source_rows(&self, source: [u32; 2], full_frame: bool) -> [[f32; 4]; 2] {
+        let m = self.source_transform(source, full_frame);
+        [[m[0][0], m[0][1], m[0][2], 0.0], [m[1][0], m[1][1], m[1][2], 0.0]]
+    }
+
+    pub fn uniform(&self, source: [u32; 2], full_frame: bool, lens: &LensCoefficients) -> GeometryUniform {
+        let [sw, sh] = [source[0] as f32, source[1] as f32];
+        let [row_x, row_y] = self.source_rows(source, full_frame);
+        GeometryUniform {
+            row_x,
+            row_y,
+            lens_center: [sw * 0.5, sh * 0.5, 2.0 / sw.min(sh), 2.0 / sw.hypot(sh)],
+            distortion: [lens.distortion[1], lens.distortion[2], lens.distortion[3], lens.distortion[4]],
+            lens: [lens.distortion[0], lens.ca_red, lens.ca_blue, lens.scale],
+            vignetting: [lens.vignetting[0], lens.vignetting[1], lens.vignetting[2], 0.0],
+        }
+    }
+
+    /// Crop shape as width over height in pixels, None when it's free
+    pub fn aspect_ratio(&self, source: [u32; 2]) -> Option<f32> {
+        let [w, h] = self.oriented_size(source);
+        let long_over_short = match self.aspect {
+            ASPECT_ORIGINAL => w.max(h) / w.min(h),
+            index => ASPECT_PRESETS.get(index)?.1?,
+        };
+        Some(if self.aspect_portrait { 1.0 / long_over_short } else { long_over_short })
+    }
+
+    /// Back to the frame as shot, keeping any orientation override
+    pub fn reset(&mut self) {
+        *self = Geometry {
+            source_orientation: self.source_orientation,
+            orientation_override: self.orientation_override,
+            ..Geometry::default()
+        };
+    }
+
+    /// Turns the frame a quarter, taking the crop with it
+    pub fn rotate(&mut self, clockwise: bool) {
+        // Turns happen before flips, so with one flip active a turn goes the other way
+        let turn_clockwise = clockwise != (self.flip_horizontal != self.flip_vertical);
+        self.quarter_turns = if turn_clockwise {
+            (self.quarter_turns + 1) % 4
+        } else {
+            (self.quarter_turns + 3) % 4
+        };
+
+        let c = self.crop;
+        self.crop = if clockwise {
+            CropRect { x: 1.0 - c.y - c.h, y: c.x, w: c.h, h: c.w }
+        } else {
+            CropRect { x: c.y, y: 1.0 - c.x - c.w, w: c.h, h: c.w }
+        };
+        self.aspect_portrait = !self.aspect_portrait;
+    }
+
+    /// Mirrors the frame left to right, taking the crop with it
+    pub fn flip_horizontally(&mut self) {
+        self.flip_horizontal = !self.flip_horizontal;
+        self.crop.x = 1.0 - self.crop.x - self.crop.w;
+        self.straighten = -self.straighten;
+    }
+
+    /// Mirrors the frame top to bottom, taking the crop with it
+    pub fn flip_vertically(&mut self) {
+        self.flip_vertical = !self.flip_vertical;
+        self.crop.y = 1.0 - self.crop.y - self.crop.h;
+        self.straighten = -self.straighten;
+    }
+
+    /// Largest scale (up to 1) of `crop` about its centre that keeps every corner on the
+    /// straightened picture, so no empty corners end up in the output
+    fn fit_scale(&self, crop: &CropRect, source: [u32; 2]) -> f32 {
+        let [w, h] = self.oriented_size(source);
+        let (sin, cos) = self.straighten.to_radians().sin_cos();
+        // Into the picture's own axes, relative to the frame centre, in pixels
+        let rotate = |x: f32, y: f32| [cos * x - sin * y, sin * x + cos * y];
+
+        let [cx, cy] = crop.center();
+        let centre = rotate((cx - 0.5) * w, (cy - 0.5) * h);
+        let half = [w * 0.5, h * 0.5];
+
+        let mut scale: f32 = 1.0;
+        for (dx, dy) in [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)] {
+            let corner = rotate(dx * crop.w * w * 0.5, dy * crop.h * h * 0.5);
+            for axis in 0..2 {
+                if corner[axis] > 1e-6 {
+                    scale = scale.min((half[axis] - centre[axis]) / corner[axis]);
+                } else if corner[axis] < -1e-6 {
+                    scale = scale.min((-half[axis] - centre[axis]) / corner[axis]);
+                }
+            }
+        }
+        scale
+    }
+
+    /// Whether `crop` lies entirely on the straightened picture
+    pub fn crop_fits(&self, crop: &CropRect, source: [u32; 2]) -> bool {
+        self.fit_scale(crop, source) >= 0.9999
+    }
+
+    /// Shrinks the crop about its centre until it fits the straightened picture.
+    /// If even the centre is off the picture, the crop moves back to the middle first.
+    pub fn fit_crop(&mut self, source: [u32; 2]) {
+        if self.fit_scale(&self.crop, source) <= 0.0 {
+            self.crop.x = 0.5 - self.crop.w * 0.5;
+            self.crop.y = 0.5 - self.crop.h * 0.5;
+        }
+        let scale = self.fit_scale(&self.crop, source).clamp(0.0, 1.0);
+        if scale < 1.0 {
+            let [cx, cy] = self.crop.center();
+            self.crop.w *= scale;
+            self.crop.h *= scale;
+            self.crop.x = cx - self.crop.w * 0.5;
+            self.crop.y = cy - self.crop.h * 0.5;
+        }
+    }
+
+    /// Reshapes the crop to the chosen aspect ratio about its centre, then fits it
+    pub fn apply_aspect(&mut self, source: [u32; 2]) {
+        let Some(ratio) = self.aspect_ratio(source) else {
+            return;
+        };
+        let [w, h] = self.oriented_size(source);
+        let [cx, cy] = self.crop.center();
+
+        // Keep the longer side, in pixels, and derive the other from the ratio
+        let mut crop_w = self.crop.w * w;
+        let mut crop_h = self.crop.h * h;
+        if ratio >= 1.0 {
+            crop_h = crop_w / ratio;
+        } else {
+            crop_w = crop_h * ratio;
+        }
+        let shrink = (w / crop_w).min(h / crop_h).min(1.0);
+        crop_w *= shrink;
+        crop_h *= shrink;
+
+        self.crop.w = crop_w / w;
+        self.crop.h = crop_h / h;
+        self.crop.x = (cx - self.crop.w * 0.5).clamp(0.0, 1.0 - self.crop.w);
+        self.crop.y = (cy - self.crop.h * 0.5).clamp(0.0, 1.0 - self.crop.h);
+        self.fit_crop(source);
+    }
+
+    /// Sets the straighten angle and shrinks the crop to keep the corners filled
+    pub fn set_straighten(&mut self, degrees: f32, source: [u32; 2]) {
+        self.straighten = degrees.clamp(-MAX_STRAIGHTEN, MAX_STRAIGHTEN);
+        self.fit_crop(source);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: [u32; 2] = [600, 400];
+
+    fn assert_near(a: [f32; 2], b: [f32; 2]) {
+        assert!((a[0] - b[0]).abs() < 1e-3 && (a[1] - b[1]).abs() < 1e-3, "{a:?} != {b:?}");
+    }
+
+    /// Every combination of turns and flips, some straightened and cropped
+    fn framings() -> Vec<Geometry> {
+        let mut framings = Vec::new();
+        for quarter_turns in 0..4 {
+            for (flip_horizontal, flip_vertical) in [(false, false), (true, false), (false, true), (true, true)] {
+                for (straighten, crop) in [
+                    (0.0, CropRect::FULL),
+                    (7.5, CropRect { x: 0.1, y: 0.2, w: 0.6, h: 0.5 }),
+                ] {
+                    framings.push(Geometry { quarter_turns, flip_horizontal, flip_vertical, straighten, crop, ..Geometry::default() });
+                }
+            }
+        }
+        framings
+    }
+
+    #[test]
+    fn source_and_output_points_round_trip() {
+        for geometry in framings() {
+            for full_frame in [false, true] {
+                for p in [[0.0, 0.0], [12.5, 300.0], [599.0, 1.0], [250.0, 199.5]] {
+                    let source = geometry.source_point(SOURCE, full_frame, p);
+                    assert_near(geometry.output_point(SOURCE, full_frame, source), p);
+                    let output = geometry.output_point(SOURCE, full_frame, p);
+                    assert_near(geometry.source_point(SOURCE, full_frame, output), p);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn quarter_turn_puts_the_bottom_left_corner_top_left() {
+        let geometry = Geometry { quarter_turns: 1, ..Geometry::default() };
+        assert_eq!(geometry.output_size(SOURCE, false), [400, 600]);
+        assert_near(geometry.source_point(SOURCE, false, [0.0, 0.0]), [0.0, 400.0]);
+        assert_near(geometry.output_point(SOURCE, false, [600.0, 0.0]), [400.0, 600.0]);
+    }
+
+    #[test]
+    fn crop_offsets_the_output() {
+        let geometry = Geometry { crop: CropRect { x: 0.25, y: 0.5, w: 0.5, h: 0.5 }, ..Geometry::default() };
+        assert_near(geometry.source_point(SOURCE, false, [0.0, 0.0]), [150.0, 200.0]);
+        assert_near(geometry.source_point(SOURCE, true, [0.0, 0.0]), [0.0, 0.0]);
+    }
+
+    #[test]
+    fn exif_orientations_cover_every_turn_and_mirror() {
+        let orientations: Vec<Orientation> = (1..=8).map(Orientation::from_exif).collect();
+        for (i, a) in orientations.iter().enumerate() {
+            assert!(orientations[i + 1..].iter().all(|b| b != a), "{a:?} appears twice");
+            assert_eq!(a.to_exif(), i as u8 + 1);
+        }
+        assert_eq!(Orientation::from_exif(6), Orientation { quarter_turns: 1, flip_horizontal: false });
+        assert_eq!(Orientation::from_exif(8), Orientation { quarter_turns: 3, flip_horizontal: false });
+        assert_eq!(Orientation::from_exif(0), Orientation::default());
+        assert_eq!(Orientation::from_exif(9), Orientation::default());
+    }
+
+    #[test]
+    fn libraw_flips_match_exif() {
+        assert_eq!(Orientation::from_libraw_flip(0), Orientation::from_exif(1));
+        assert_eq!(Orientation::from_libraw_flip(3), Orientation::from_exif(3));
+        assert_eq!(Orientation::from_libraw_flip(5), Orientation::from_exif(8));
+        assert_eq!(Orientation::from_libraw_flip(6), Orientation::from_exif(6));
+        assert_eq!(Orientation::from_libraw_flip(-1), Orientation::default());
+    }
+}
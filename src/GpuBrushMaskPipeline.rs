@@ -0,0 +1,384 @@
+use eframe::wgpu;
+use eframe::wgpu::util::DeviceExt;
+use eframe::wgpu::{BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, ComputePipeline, ComputePipelineDescriptor, PipelineLayoutDescriptor, ShaderModuleDescriptor, ShaderStages, StorageTextureAccess, TextureFormat, TextureSampleType, TextureViewDimension};
+use crate::BrushMask::{mask_size, BrushMaskData, MASK_LAYERS};
+use crate::ProcessingGraph::{Intermediate, IntermediatePool};
+use std::sync::Mutex;
+
+/// Box radius of the guided filter window, in mask pixels
+const GUIDE_RADIUS: i32 = 4;
+
+// Mirrors `BlurUniform` in box_blur.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BlurUniform {
+    direction: [i32; 2],
+    radius: i32,
+    _pad: i32,
+}
+
+pub(crate) fn texture_entry(binding: u32, view_dimension: TextureViewDimension) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::Texture {
+            sample_type: TextureSampleType::Float { filterable: false },
+            view_dimension,
+            multisampled: false,
+        },
+        count: None,
+    }
+}
+
+pub(crate) fn storage_texture_entry(binding: u32, format: TextureFormat, view_dimension: TextureViewDimension) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::StorageTexture {
+            access: StorageTextureAccess::WriteOnly,
+            format,
+            view_dimension,
+        },
+        count: None,
+    }
+}
+
+pub(crate) fn buffer_entry(binding: u32, ty: wgpu::BufferBindingType) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::Buffer {
+            ty,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+pub(crate) fn compute_pipeline(
+    device: &wgpu::Device,
+    cache: Option<&wgpu::PipelineCache>,
+    label: &str,
+    source: &'static str,
+    entry_point: &str,
+    bind_group_layout: &wgpu::BindGroupLayout,
+) -> ComputePipeline {
+    let shader = device.create_shader_module(ShaderModuleDescriptor {
+        label: Some(label),
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+    });
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some(label),
+        bind_group_layouts: &[bind_group_layout],
+        push_constant_ranges: &[],
+    });
+    device.create_compute_pipeline(&ComputePipelineDescriptor {
+        label: Some(label),
+        layout: Some(&pipeline_layout),
+        module: &shader,
+        entry_point: Some(entry_point),
+        compilation_options: Default::default(),
+        cache,
+    })
+}
+
+/// Brush mask rasterisation and guided filter edge refinement
+pub struct GpuBrushMaskPipeline {
+    pub brush_pipeline: ComputePipeline,
+    pub brush_layout: wgpu::BindGroupLayout,
+    pub blur_pipeline: ComputePipeline,
+    pub blur_layout: wgpu::BindGroupLayout,
+    pub coefficients_pipeline: ComputePipeline,
+    pub coefficients_layout: wgpu::BindGroupLayout,
+}
+
+impl GpuBrushMaskPipeline {
+    pub fn new(device: &wgpu::Device, cache: Option<&wgpu::PipelineCache>) -> Self {
+        let brush_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Brush Mask Bind Group Layout"),
+            entries: &[
+                buffer_entry(0, wgpu::BufferBindingType::Uniform),
+                buffer_entry(1, wgpu::BufferBindingType::Storage { read_only: true }),
+                buffer_entry(2, wgpu::BufferBindingType::Storage { read_only: true }),
+                // guide source, the geometry pass output
+                texture_entry(3, TextureViewDimension::D2),
+                storage_texture_entry(4, TextureFormat::Rgba8Unorm, TextureViewDimension::D2Array),
+                storage_texture_entry(5, TextureFormat::Rgba16Float, TextureViewDimension::D2Array),
+                storage_texture_entry(6, TextureFormat::Rgba16Float, TextureViewDimension::D2Array),
+            ],
+        });
+
+        let blur_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Box Blur Bind Group Layout"),
+            entries: &[
+                texture_entry(0, TextureViewDimension::D2Array),
+                storage_texture_entry(1, TextureFormat::Rgba16Float, TextureViewDimension::D2Array),
+                buffer_entry(2, wgpu::BufferBindingType::Uniform),
+            ],
+        });
+
+        let coefficients_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Guided Filter Bind Group Layout"),
+            entries: &[
+                texture_entry(0, TextureViewDimension::D2Array),
+                texture_entry(1, TextureViewDimension::D2Array),
+                texture_entry(2, TextureViewDimension::D2Array),
+                storage_texture_entry(3, TextureFormat::Rgba16Float, TextureViewDimension::D2Array),
+                storage_texture_entry(4, TextureFormat::Rgba16Float, TextureViewDimension::D2Array),
+            ],
+        });
+
+        Self {
+            brush_pipeline: compute_pipeline(device, cache, "Brush Mask Pipeline", include_str!("shaders/brush.wgsl"), "brush_main", &brush_layout),
+            brush_layout,
+            blur_pipeline: compute_pipeline(device, cache, "Box Blur Pipeline", include_str!("shaders/box_blur.wgsl"), "blur_main", &blur_layout),
+            blur_layout,
+            coefficients_pipeline: compute_pipeline(
+                device,
+                cache,
+                "Guided Filter Pipeline",
+                include_str!("shaders/guided_filter.wgsl"),
+                "coefficients_main",
+                &coefficients_layout,
+            ),
+            coefficients_layout,
+        }
+    }
+}
+
+/// Brush mask textures for one image, sized by `mask_size`, and the passes that fill them
+pub struct BrushMaskResources {
+    brush_pipeline: ComputePipeline,
+    brush_layout: wgpu::BindGroupLayout,
+    blur_pipeline: ComputePipeline,
+    coefficients_pipeline: ComputePipeline,
+
+    uniform_buffer: wgpu::Buffer,
+    guide_source_view: wgpu::TextureView,
+    guide_view: wgpu::TextureView,
+    product_view: wgpu::TextureView,
+    /// Pairs of across and down passes, with how many layers each covers
+    blur_steps: Vec<(wgpu::BindGroup, wgpu::BindGroup, u32)>,
+    refine_blur_steps: Vec<(wgpu::BindGroup, wgpu::BindGroup, u32)>,
+    coefficients_bind_group: wgpu::BindGroup,
+
+    /// Painted coverage, one local adjustment slot per channel
+    pub mask_view: wgpu::TextureView,
+    /// Blurred guided filter coefficients, the refined mask is a * I + b
+    pub refined_a_view: wgpu::TextureView,
+    pub refined_b_view: wgpu::TextureView,
+    /// Bilinear, the masks are smaller than the image
+    pub sampler: wgpu::Sampler,
+
+    size: [u32; 2],
+    /// Strokes the textures currently hold
+    last: Mutex<Option<BrushMaskData>>,
+    /// Stroke bind group and whether to refine, waiting for the next `encode`
+    pending: Mutex<Option<(wgpu::BindGroup, bool)>>,
+}
+
+impl BrushMaskResources {
+    pub fn new(
+        device: &wgpu::Device,
+        pool: &mut IntermediatePool,
+        pipeline: &GpuBrushMaskPipeline,
+        guide_source_view: &wgpu::TextureView,
+        output: [u32; 2],
+    ) -> Self {
+        let size = mask_size(output);
+        let array_view = |texture: wgpu::Texture| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(TextureViewDimension::D2Array),
+                ..Default::default()
+            })
+        };
+        let shape = |format: TextureFormat, layers: u32| Intermediate::new(size, format).layers(layers);
+
+        // Masks and refined coefficients are read every frame, the rest only while the
+        // masks are redrawn
+        let output = |label: &str, format: TextureFormat| {
+            array_view(pool.output(label, shape(format, MASK_LAYERS), wgpu::TextureUsages::empty()))
+        };
+        let mask_view = output("Brush Mask Texture", TextureFormat::Rgba8Unorm);
+        let refined_a_view = output("Brush Refined A Texture", TextureFormat::Rgba16Float);
+        let refined_b_view = output("Brush Refined B Texture", TextureFormat::Rgba16Float);
+
+        let mut scratch = pool.stage();
+        let mut texture = |format: TextureFormat, layers: u32| array_view(scratch.texture(shape(format, layers)));
+        let guide_view = texture(TextureFormat::Rgba16Float, 1);
+        let product_view = texture(TextureFormat::Rgba16Float, MASK_LAYERS);
+        let mean_guide_view = texture(TextureFormat::Rgba16Float, 1);
+        let mean_mask_view = texture(TextureFormat::Rgba16Float, MASK_LAYERS);
+        let mean_product_view = texture(TextureFormat::Rgba16Float, MASK_LAYERS);
+        let blur_temp_view = texture(TextureFormat::Rgba16Float, MASK_LAYERS);
+        let a_view = texture(TextureFormat::Rgba16Float, MASK_LAYERS);
+        let b_view = texture(TextureFormat::Rgba16Float, MASK_LAYERS);
+
+        let blur_buffer = |direction: [i32; 2]| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Box Blur Buffer"),
+                contents: bytemuck::bytes_of(&BlurUniform { direction, radius: GUIDE_RADIUS, _pad: 0 }),
+                usage: wgpu::BufferUsages::UNIFORM,
+            })
+        };
+        let across = blur_buffer([1, 0]);
+        let down = blur_buffer([0, 1]);
+
+        let blur_bind_group = |input: &wgpu::TextureView, output: &wgpu::TextureView, uniform: &wgpu::Buffer| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Box Blur Bind Group"),
+                layout: &pipeline.blur_layout,
+                entries: &[
+                    wgpu::BindGroupEntry { binding: 0, resource: BindingResource::TextureView(input) },
+                    wgpu::BindGroupEntry { binding: 1, resource: BindingResource::TextureView(output) },
+                    wgpu::BindGroupEntry { binding: 2, resource: uniform.as_entire_binding() },
+                ],
+            })
+        };
+        // Every blur goes across into the shared temp texture, then down into its result
+        let blur_step = |input: &wgpu::TextureView, output: &wgpu::TextureView, layers: u32| {
+            (blur_bind_group(input, &blur_temp_view, &across), blur_bind_group(&blur_temp_view, output, &down), layers)
+        };
+
+        let blur_steps = vec![
+            blur_step(&guide_view, &mean_guide_view, 1),
+            blur_step(&mask_view, &mean_mask_view, MASK_LAYERS),
+            blur_step(&product_view, &mean_product_view, MASK_LAYERS),
+        ];
+        let refine_blur_steps = vec![
+            blur_step(&a_view, &refined_a_view, MASK_LAYERS),
+            blur_step(&b_view, &refined_b_view, MASK_LAYERS),
+        ];
+
+        let coefficients_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Guided Filter Bind Group"),
+            layout: &pipeline.coefficients_layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: BindingResource::TextureView(&mean_guide_view) },
+                wgpu::BindGroupEntry { binding: 1, resource: BindingResource::TextureView(&mean_mask_view) },
+                wgpu::BindGroupEntry { binding: 2, resource: BindingResource::TextureView(&mean_product_view) },
+                wgpu::BindGroupEntry { binding: 3, resource: BindingResource::TextureView(&a_view) },
+                wgpu::BindGroupEntry { binding: 4, resource: BindingResource::TextureView(&b_view) },
+            ],
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Brush Mask Buffer"),
+            size: size_of::<crate::BrushMask::BrushUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Brush Mask Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            brush_pipeline: pipeline.brush_pipeline.clone(),
+            brush_layout: pipeline.brush_layout.clone(),
+            blur_pipeline: pipeline.blur_pipeline.clone(),
+            coefficients_pipeline: pipeline.coefficients_pipeline.clone(),
+            uniform_buffer,
+            guide_source_view: guide_source_view.clone(),
+            guide_view,
+            product_view,
+            blur_steps,
+            refine_blur_steps,
+            coefficients_bind_group,
+            mask_view,
+            refined_a_view,
+            refined_b_view,
+            sampler,
+            size,
+            last: Mutex::new(None),
+            pending: Mutex::new(None),
+        }
+    }
+
+    /// Uploads the strokes if they've changed since the masks were last drawn
+    pub fn update(&self, device: &wgpu::Device, queue: &wgpu::Queue, data: &BrushMaskData) {
+        let mut last = self.last.lock().unwrap();
+        if last.as_ref() == Some(data) {
+            return;
+        }
+
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&data.uniform));
+        let strokes = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Brush Strokes Buffer"),
+            contents: bytemuck::cast_slice(&data.strokes),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let points = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Brush Points Buffer"),
+            contents: bytemuck::cast_slice(&data.points),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Brush Mask Bind Group"),
+            layout: &self.brush_layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: self.uniform_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: strokes.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 2, resource: points.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 3, resource: BindingResource::TextureView(&self.guide_source_view) },
+                wgpu::BindGroupEntry { binding: 4, resource: BindingResource::TextureView(&self.mask_view) },
+                wgpu::BindGroupEntry { binding: 5, resource: BindingResource::TextureView(&self.guide_view) },
+                wgpu::BindGroupEntry { binding: 6, resource: BindingResource::TextureView(&self.product_view) },
+            ],
+        });
+
+        *self.pending.lock().unwrap() = Some((bind_group, data.refine));
+        *last = Some(data.clone());
+    }
+
+    /// The geometry pass output the masks are refined against
+    pub fn guide_source_view(&self) -> &wgpu::TextureView {
+        &self.guide_source_view
+    }
+
+    /// Redraws the masks if `update` left new strokes. The guide comes from the geometry
+    /// pass output.
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder) {
+        let Some((bind_group, refine)) = self.pending.lock().unwrap().take() else {
+            return;
+        };
+        let (gx, gy) = (self.size[0].div_ceil(16), self.size[1].div_ceil(16));
+        let mut pass = encoder.begin_compute_pass(&Default::default());
+
+        // --- RASTERISE ---
+        pass.set_pipeline(&self.brush_pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.dispatch_workgroups(gx, gy, MASK_LAYERS);
+
+        if !refine {
+            return;
+        }
+
+        // --- GUIDED FILTER ---
+        // Window means of the guide, the mask and their product, then the per window
+        // coefficients, then the means of those
+        pass.set_pipeline(&self.blur_pipeline);
+        for (across, down, layers) in &self.blur_steps {
+            pass.set_bind_group(0, across, &[]);
+            pass.dispatch_workgroups(gx, gy, *layers);
+            pass.set_bind_group(0, down, &[]);
+            pass.dispatch_workgroups(gx, gy, *layers);
+        }
+
+        pass.set_pipeline(&self.coefficients_pipeline);
+        pass.set_bind_group(0, &self.coefficients_bind_group, &[]);
+        pass.dispatch_workgroups(gx, gy, MASK_LAYERS);
+
+        pass.set_pipeline(&self.blur_pipeline);
+        for (across, down, layers) in &self.refine_blur_steps {
+            pass.set_bind_group(0, across, &[]);
+            pass.dispatch_workgroups(gx, gy, *layers);
+            pass.set_bind_group(0, down, &[]);
+            pass.dispatch_workgroups(gx, gy, *layers);
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use eframe::wgpu;
+use eframe::wgpu::{BindGroupLayoutDescriptor, BindingResource, ComputePipeline, TextureFormat, TextureViewDimension};
+use crate::GpuBrushMaskPipeline::{buffer_entry, compute_pipeline, storage_texture_entry, texture_entry};
+use crate::NoiseReduction::{DenoiseUniform, NoiseReduction, DENOISE_LEVELS};
+use crate::ProcessingGraph::{GraphStage, Intermediate, IntermediatePool, Stage, StageFrame};
+use std::sync::Mutex;
+
+/// Edge-avoiding wavelet noise reduction, see denoise.wgsl
+pub struct GpuDenoisePipeline {
+    pub convert_pipeline: ComputePipeline,
+    pub atrous_pipeline: ComputePipeline,
+    pub smooth_layout: wgpu::BindGroupLayout,
+    pub resolve_pipeline: ComputePipeline,
+    pub resolve_layout: wgpu::BindGroupLayout,
+}
+
+impl GpuDenoisePipeline {
+    pub fn new(device: &wgpu::Device, cache: Option<&wgpu::PipelineCache>) -> Self {
+        let source = include_str!("shaders/denoise.wgsl");
+        let smooth_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Denoise Bind Group Layout"),
+            entries: &[
+                texture_entry(0, TextureViewDimension::D2),
+                storage_texture_entry(2, TextureFormat::Rgba16Float, TextureViewDimension::D2),
+                buffer_entry(3, wgpu::BufferBindingType::Uniform),
+            ],
+        });
+        let resolve_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Denoise Resolve Bind Group Layout"),
+            entries: &[
+                texture_entry(0, TextureViewDimension::D2),
+                texture_entry(1, TextureViewDimension::D2),
+                buffer_entry(3, wgpu::BufferBindingType::Uniform),
+                storage_texture_entry(4, TextureFormat::Rgba32Float, TextureViewDimension::D2),
+            ],
+        });
+
+        Self {
+            convert_pipeline: compute_pipeline(device, cache, "Denoise Convert Pipeline", source, "convert_main", &smooth_layout),
+            atrous_pipeline: compute_pipeline(device, cache, "Denoise Pipeline", source, "atrous_main", &smooth_layout),
+            smooth_layout,
+            resolve_pipeline: compute_pipeline(device, cache, "Denoise Resolve Pipeline", source, "resolve_main", &resolve_layout),
+            resolve_layout,
+        }
+    }
+}
+
+/// The denoised framed image and the passes that make it
+pub struct DenoiseResources {
+    convert_pipeline: ComputePipeline,
+    atrous_pipeline: ComputePipeline,
+    resolve_pipeline: ComputePipeline,
+    smooth_layout: wgpu::BindGroupLayout,
+    resolve_layout: wgpu::BindGroupLayout,
+
+    /// One per smoothing pass, the last one's detail settings also serve the resolve
+    uniform_buffers: Vec<wgpu::Buffer>,
+    ping_view: wgpu::TextureView,
+    /// Where the last smoothing pass leaves its result
+    smoothed_view: wgpu::TextureView,
+    convert_bind_group: wgpu::BindGroup,
+    level_bind_groups: Vec<wgpu::BindGroup>,
+    resolve_bind_group: wgpu::BindGroup,
+
+    denoised_view: wgpu::TextureView,
+
+    size: [u32; 2],
+    /// Settings the uniforms currently hold
+    last: Mutex<Option<NoiseReduction>>,
+}
+
+impl DenoiseResources {
+    pub fn new(
+        device: &wgpu::Device,
+        pool: &mut IntermediatePool,
+        pipeline: &GpuDenoisePipeline,
+        input: &wgpu::TextureView,
+        output: [u32; 2],
+    ) -> Self {
+        let mut scratch = pool.stage();
+        let ping_view = scratch.texture(Intermediate::new(output, TextureFormat::Rgba16Float)).create_view(&Default::default());
+        let pong_view = scratch.texture(Intermediate::new(output, TextureFormat::Rgba16Float)).create_view(&Default::default());
+        let denoised_view = pool
+            .output("Denoised Texture", Intermediate::new(output, TextureFormat::Rgba32Float), wgpu::TextureUsages::empty())
+            .create_view(&Default::default());
+
+        let uniform_buffers: Vec<wgpu::Buffer> = (0..DENOISE_LEVELS)
+            .map(|_| {
+                device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("Denoise Buffer"),
+                    size: size_of::<DenoiseUniform>() as u64,
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                })
+            })
+            .collect();
+
+        // Each pass reads what the last one wrote
+        let level_bind_groups = uniform_buffers
+            .iter()
+            .enumerate()
+            .map(|(level, uniform)| {
+                if level.is_multiple_of(2) {
+                    smooth_bind_group(device, &pipeline.smooth_layout, &ping_view, &pong_view, uniform)
+                } else {
+                    smooth_bind_group(device, &pipeline.smooth_layout, &pong_view, &ping_view, uniform)
+                }
+            })
+            .collect();
+        let smoothed_view = if DENOISE_LEVELS.is_multiple_of(2) { ping_view.clone() } else { pong_view.clone() };
+
+        let convert_bind_group = smooth_bind_group(device, &pipeline.smooth_layout, input, &ping_view, &uniform_buffers[0]);
+        let resolve_bind_group =
+            resolve_bind_group(device, &pipeline.resolve_layout, &smoothed_view, input, &uniform_buffers[DENOISE_LEVELS - 1], &denoised_view);
+
+        Self {
+            convert_pipeline: pipeline.convert_pipeline.clone(),
+            atrous_pipeline: pipeline.atrous_pipeline.clone(),
+            resolve_pipeline: pipeline.resolve_pipeline.clone(),
+            smooth_layout: pipeline.smooth_layout.clone(),
+            resolve_layout: pipeline.resolve_layout.clone(),
+            uniform_buffers,
+            ping_view,
+            smoothed_view,
+            convert_bind_group,
+            level_bind_groups,
+            resolve_bind_group,
+            denoised_view,
+            size: output,
+            last: Mutex::new(None),
+        }
+    }
+
+    /// Uploads the settings if they've changed since the last frame
+    fn update(&self, queue: &wgpu::Queue, settings: &NoiseReduction) {
+        let mut last = self.last.lock().unwrap();
+        if last.as_ref() == Some(settings) {
+            return;
+        }
+        for (buffer, uniform) in self.uniform_buffers.iter().zip(settings.uniforms()) {
+            queue.write_buffer(buffer, 0, bytemuck::bytes_of(&uniform));
+        }
+        *last = Some(*settings);
+    }
+}
+
+fn smooth_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    input: &wgpu::TextureView,
+    output: &wgpu::TextureView,
+    uniform: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Denoise Bind Group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry { binding: 0, resource: BindingResource::TextureView(input) },
+            wgpu::BindGroupEntry { binding: 2, resource: BindingResource::TextureView(output) },
+            wgpu::BindGroupEntry { binding: 3, resource: uniform.as_entire_binding() },
+        ],
+    })
+}
+
+/// The smoothed image with the detail the settings keep put back from the original
+fn resolve_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    smoothed: &wgpu::TextureView,
+    original: &wgpu::TextureView,
+    uniform: &wgpu::Buffer,
+    output: &wgpu::TextureView,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Denoise Resolve Bind Group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry { binding: 0, resource: BindingResource::TextureView(smoothed) },
+            wgpu::BindGroupEntry { binding: 1, resource: BindingResource::TextureView(original) },
+            wgpu::BindGroupEntry { binding: 3, resource: uniform.as_entire_binding() },
+            wgpu::BindGroupEntry { binding: 4, resource: BindingResource::TextureView(output) },
+        ],
+    })
+}
+
+impl GraphStage for DenoiseResources {
+    fn stage(&self) -> Stage {
+        Stage::NoiseReduction
+    }
+
+    fn output(&self) -> &wgpu::TextureView {
+        &self.denoised_view
+    }
+
+    fn is_active(&self, frame: &StageFrame) -> bool {
+        frame.noise_reduction.is_active()
+    }
+
+    fn bind(&mut self, device: &wgpu::Device, input: &wgpu::TextureView) {
+        self.convert_bind_group = smooth_bind_group(device, &self.smooth_layout, input, &self.ping_view, &self.uniform_buffers[0]);
+        self.resolve_bind_group = resolve_bind_group(
+            device,
+            &self.resolve_layout,
+            &self.smoothed_view,
+            input,
+            &self.uniform_buffers[DENOISE_LEVELS - 1],
+            &self.denoised_view,
+        );
+    }
+
+    fn encode(&self, encoder: &mut wgpu::CommandEncoder, frame: &StageFrame) {
+        self.update(frame.queue, frame.noise_reduction);
+
+        let (gx, gy) = (self.size[0].div_ceil(16), self.size[1].div_ceil(16));
+        let mut pass = encoder.begin_compute_pass(&Default::default());
+
+        pass.set_pipeline(&self.convert_pipeline);
+        pass.set_bind_group(0, &self.convert_bind_group, &[]);
+        pass.dispatch_workgroups(gx, gy, 1);
+
+        pass.set_pipeline(&self.atrous_pipeline);
+        for bind_group in &self.level_bind_groups {
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.dispatch_workgroups(gx, gy, 1);
+        }
+
+        pass.set_pipeline(&self.resolve_pipeline);
+        pass.set_bind_group(0, &self.resolve_bind_group, &[]);
+        pass.dispatch_workgroups(gx, gy, 1);
+    }
+}
@@ -0,0 +1,72 @@
+use eframe::wgpu;
+use eframe::wgpu::{BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, ComputePipeline, ComputePipelineDescriptor, PipelineLayoutDescriptor, ShaderModuleDescriptor, ShaderStages, TextureSampleType, TextureViewDimension};
+
+/// 256 bins each for red, green, blue and luma
+pub const HISTOGRAM_BINS: usize = 1024;
+
+// Pixels covered by one workgroup along each axis, see TILE in histogram.wgsl
+pub const HISTOGRAM_WORKGROUP_PIXELS: u32 = 64;
+
+pub struct GpuHistogramPipeline {
+    pub pipeline: ComputePipeline,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+}
+
+impl GpuHistogramPipeline {
+    pub fn new(device: &wgpu::Device, cache: Option<&wgpu::PipelineCache>) -> Self {
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Histogram Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                include_str!("shaders/histogram.wgsl").into()
+            ),
+        });
+
+        let bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("Histogram Bind Group Layout"),
+                entries: &[
+                    // processed texture
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: false },
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    // bins
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+
+        let pipeline_layout =
+            device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("Histogram Pipeline Layout"),
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[],
+            });
+
+        let pipeline =
+            device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some("Histogram Pipeline"),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point: Some("histogram_main"),
+                compilation_options: Default::default(),
+                cache,
+            });
+
+        Self { pipeline, bind_group_layout }
+    }
+}
@@ -3,21 +3,23 @@ use eframe::wgpu::{BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType,
 
 pub struct GpuImageComputePipeline {
     pub pipeline: ComputePipeline,
+    /// The input texture, in a group of its own, see `ComputeStage`
+    pub input_layout: wgpu::BindGroupLayout,
     pub bind_group_layout: wgpu::BindGroupLayout,
 }
 
 impl GpuImageComputePipeline {
-    pub fn new(device: &wgpu::Device) -> Self {
+    pub fn new(device: &wgpu::Device, cache: Option<&wgpu::PipelineCache>) -> Self {
         let shader = device.create_shader_module(ShaderModuleDescriptor {
             label: Some("Image Compute Shader"),
             source: wgpu::ShaderSource::Wgsl(
-                include_str!("shaders/compute.wgsl").into()
+                concat!(include_str!("shaders/common.wgsl"), include_str!("shaders/compute.wgsl")).into()
             ),
         });
 
-        let bind_group_layout =
+        let input_layout =
             device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-                label: Some("Compute Bind Group Layout"),
+                label: Some("Compute Input Bind Group Layout"),
                 entries: &[
                     // input texture
                     BindGroupLayoutEntry {
@@ -30,6 +32,13 @@ impl GpuImageComputePipeline {
                         },
                         count: None,
                     },
+                ],
+            });
+
+        let bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("Compute Bind Group Layout"),
+                entries: &[
                     // output texture
                     BindGroupLayoutEntry {
                         binding: 1,
@@ -52,13 +61,68 @@ impl GpuImageComputePipeline {
                         },
                         count: None,
                     },
+                    // working texture (unclamped, linear)
+                    BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::StorageTexture {
+                            access: wgpu::StorageTextureAccess::WriteOnly,
+                            format: TextureFormat::Rgba16Float,
+                            view_dimension: TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                    // original texture (before/after reference)
+                    BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::StorageTexture {
+                            access: wgpu::StorageTextureAccess::WriteOnly,
+                            format: TextureFormat::Rgba8Unorm,
+                            view_dimension: TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                    // framed image before noise reduction
+                    BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: false },
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    // adjustment stack
+                    BindGroupLayoutEntry {
+                        binding: 6,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // mask overlay of the selected local adjustment
+                    BindGroupLayoutEntry {
+                        binding: 7,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: false },
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
                 ],
             });
 
         let pipeline_layout =
             device.create_pipeline_layout(&PipelineLayoutDescriptor {
                 label: Some("Compute Pipeline Layout"),
-                bind_group_layouts: &[&bind_group_layout],
+                bind_group_layouts: &[&input_layout, &bind_group_layout],
                 push_constant_ranges: &[],
             });
 
@@ -69,9 +133,9 @@ impl GpuImageComputePipeline {
                 module: &shader,
                 entry_point: Some("shader_main"),
                 compilation_options: Default::default(),
-                cache: None,
+                cache,
             });
 
-        Self { pipeline, bind_group_layout }
+        Self { pipeline, input_layout, bind_group_layout }
     }
 }
\ No newline at end of file
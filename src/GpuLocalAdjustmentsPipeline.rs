@@ -0,0 +1,293 @@
+use eframe::wgpu;
+use eframe::wgpu::{BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, ComputePipeline, ComputePipelineDescriptor, PipelineLayoutDescriptor, ShaderModuleDescriptor, ShaderStages, TextureFormat, TextureSampleType, TextureViewDimension};
+use crate::GpuBrushMaskPipeline::{buffer_entry, storage_texture_entry, texture_entry, BrushMaskResources};
+use crate::LocalAdjustments::LocalAdjustmentsUniform;
+use crate::ProcessingGraph::{input_bind_group, GraphStage, Intermediate, IntermediatePool, Stage, StageFrame};
+
+/// Brush masks and their guided filter refinement, read bilinearly at full size
+fn brush_entry(binding: u32) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::Texture {
+            sample_type: TextureSampleType::Float { filterable: true },
+            view_dimension: TextureViewDimension::D2Array,
+            multisampled: false,
+        },
+        count: None,
+    }
+}
+
+/// Masked local adjustment layers, see local_adjustments.wgsl. The input is group 0 like a
+/// `ComputeStage`'s.
+pub struct GpuLocalAdjustmentsPipeline {
+    pub pipeline: ComputePipeline,
+    pub input_layout: wgpu::BindGroupLayout,
+    pub layout: wgpu::BindGroupLayout,
+    /// Range mask eyedropper, one pixel through `display_color`
+    pub pick_pipeline: ComputePipeline,
+    pub pick_layout: wgpu::BindGroupLayout,
+}
+
+impl GpuLocalAdjustmentsPipeline {
+    pub fn new(device: &wgpu::Device, cache: Option<&wgpu::PipelineCache>) -> Self {
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Local Adjustments Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                concat!(include_str!("shaders/common.wgsl"), include_str!("shaders/local_adjustments.wgsl")).into()
+            ),
+        });
+
+        let input_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Local Adjustments Input Bind Group Layout"),
+            entries: &[texture_entry(0, TextureViewDimension::D2)],
+        });
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Local Adjustments Bind Group Layout"),
+            entries: &[
+                storage_texture_entry(1, TextureFormat::Rgba16Float, TextureViewDimension::D2),
+                // mask overlay
+                storage_texture_entry(2, TextureFormat::R32Float, TextureViewDimension::D2),
+                // image controls, then the layers
+                buffer_entry(3, wgpu::BufferBindingType::Uniform),
+                buffer_entry(4, wgpu::BufferBindingType::Uniform),
+                brush_entry(5),
+                brush_entry(6),
+                brush_entry(7),
+                BindGroupLayoutEntry {
+                    binding: 8,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                // brush guide, the geometry pass output
+                texture_entry(9, TextureViewDimension::D2),
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Local Adjustments Pipeline Layout"),
+            bind_group_layouts: &[&input_layout, &layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("Local Adjustments Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("local_main"),
+            compilation_options: Default::default(),
+            cache,
+        });
+
+        let pick_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Range Pick Bind Group Layout"),
+            entries: &[
+                buffer_entry(3, wgpu::BufferBindingType::Uniform),
+                buffer_entry(10, wgpu::BufferBindingType::Uniform),
+                buffer_entry(11, wgpu::BufferBindingType::Storage { read_only: false }),
+            ],
+        });
+        let pick_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Range Pick Pipeline Layout"),
+            bind_group_layouts: &[&input_layout, &pick_layout],
+            push_constant_ranges: &[],
+        });
+        let pick_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("Range Pick Pipeline"),
+            layout: Some(&pick_pipeline_layout),
+            module: &shader,
+            entry_point: Some("pick_main"),
+            compilation_options: Default::default(),
+            cache,
+        });
+
+        Self { pipeline, input_layout, layout, pick_pipeline, pick_layout }
+    }
+}
+
+/// The layers' output for one image, the mask overlay and the brush masks they read
+pub struct LocalAdjustmentsResources {
+    pipeline: ComputePipeline,
+    input_layout: wgpu::BindGroupLayout,
+    input_bind_group: wgpu::BindGroup,
+    bind_group: wgpu::BindGroup,
+    brush_masks: BrushMaskResources,
+
+    uniform_buffer: wgpu::Buffer,
+    adjusted_view: wgpu::TextureView,
+    /// Coverage of the layer being edited, the colour pass tints it
+    pub overlay_view: wgpu::TextureView,
+    size: [u32; 2],
+}
+
+impl LocalAdjustmentsResources {
+    /// `settings_buffer` holds the `ImageControls`. The brush masks' guide is the guide for
+    /// refined masks here too.
+    pub fn new(
+        device: &wgpu::Device,
+        pool: &mut IntermediatePool,
+        pipeline: &GpuLocalAdjustmentsPipeline,
+        brush_masks: BrushMaskResources,
+        input: &wgpu::TextureView,
+        settings_buffer: &wgpu::Buffer,
+        output: [u32; 2],
+    ) -> Self {
+        let adjusted_view = pool
+            .output("Local Adjustments Texture", Intermediate::new(output, TextureFormat::Rgba16Float), wgpu::TextureUsages::empty())
+            .create_view(&Default::default());
+        let overlay_view = pool
+            .output("Mask Overlay Texture", Intermediate::new(output, TextureFormat::R32Float), wgpu::TextureUsages::empty())
+            .create_view(&Default::default());
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Local Adjustments Buffer"),
+            size: size_of::<LocalAdjustmentsUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Local Adjustments Bind Group"),
+            layout: &pipeline.layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 1, resource: BindingResource::TextureView(&adjusted_view) },
+                wgpu::BindGroupEntry { binding: 2, resource: BindingResource::TextureView(&overlay_view) },
+                wgpu::BindGroupEntry { binding: 3, resource: settings_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 4, resource: uniform_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 5, resource: BindingResource::TextureView(&brush_masks.mask_view) },
+                wgpu::BindGroupEntry { binding: 6, resource: BindingResource::TextureView(&brush_masks.refined_a_view) },
+                wgpu::BindGroupEntry { binding: 7, resource: BindingResource::TextureView(&brush_masks.refined_b_view) },
+                wgpu::BindGroupEntry { binding: 8, resource: BindingResource::Sampler(&brush_masks.sampler) },
+                wgpu::BindGroupEntry { binding: 9, resource: BindingResource::TextureView(brush_masks.guide_source_view()) },
+            ],
+        });
+
+        Self {
+            pipeline: pipeline.pipeline.clone(),
+            input_layout: pipeline.input_layout.clone(),
+            input_bind_group: input_bind_group(device, &pipeline.input_layout, input),
+            bind_group,
+            brush_masks,
+            uniform_buffer,
+            adjusted_view,
+            overlay_view,
+            size: output,
+        }
+    }
+}
+
+/// Reads back the colour range masks see at a pixel, so the eyedropper picks a range that
+/// matches where it was clicked
+pub struct RangePicker {
+    pipeline: ComputePipeline,
+    input_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    coords_buffer: wgpu::Buffer,
+    color_buffer: wgpu::Buffer,
+}
+
+impl RangePicker {
+    /// `settings_buffer` holds the `ImageControls` the layers are drawn with
+    pub fn new(device: &wgpu::Device, pipeline: &GpuLocalAdjustmentsPipeline, settings_buffer: &wgpu::Buffer) -> Self {
+        let coords_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Range Pick Coords Buffer"),
+            size: size_of::<[u32; 4]>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let color_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Range Pick Colour Buffer"),
+            size: size_of::<[f32; 4]>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Range Pick Bind Group"),
+            layout: &pipeline.pick_layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 3, resource: settings_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 10, resource: coords_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 11, resource: color_buffer.as_entire_binding() },
+            ],
+        });
+
+        Self {
+            pipeline: pipeline.pick_pipeline.clone(),
+            input_layout: pipeline.input_layout.clone(),
+            bind_group,
+            coords_buffer,
+            color_buffer,
+        }
+    }
+
+    /// The display encoded colour of `input` at a pixel, waiting on the GPU for it. `input`
+    /// is what the local adjustments stage reads.
+    pub fn pick(&self, device: &wgpu::Device, queue: &wgpu::Queue, input: &wgpu::TextureView, x: u32, y: u32) -> Option<[f32; 3]> {
+        queue.write_buffer(&self.coords_buffer, 0, bytemuck::bytes_of(&[x, y, 0, 0]));
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Range Pick Readback Buffer"),
+            size: self.color_buffer.size(),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Range Pick Encoder"),
+        });
+        {
+            let mut pass = encoder.begin_compute_pass(&Default::default());
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &input_bind_group(device, &self.input_layout, input), &[]);
+            pass.set_bind_group(1, &self.bind_group, &[]);
+            pass.dispatch_workgroups(1, 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(&self.color_buffer, 0, &readback, 0, self.color_buffer.size());
+        queue.submit(Some(encoder.finish()));
+
+        let slice = readback.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            sender.send(result).ok();
+        });
+        device.poll(wgpu::PollType::wait_indefinitely()).ok()?;
+        receiver.recv().ok()?.ok()?;
+
+        let data = slice.get_mapped_range();
+        let [r, g, b, _]: [f32; 4] = bytemuck::pod_read_unaligned(&data[..16]);
+        Some([r, g, b])
+    }
+}
+
+impl GraphStage for LocalAdjustmentsResources {
+    fn stage(&self) -> Stage {
+        Stage::LocalAdjustments
+    }
+
+    fn output(&self) -> &wgpu::TextureView {
+        &self.adjusted_view
+    }
+
+    /// Runs for the overlay too, so a layer with nothing set yet still shows its mask
+    fn is_active(&self, frame: &StageFrame) -> bool {
+        let [layers, overlay, ..] = frame.local_adjustments.info;
+        layers > 0 || overlay > 0
+    }
+
+    fn bind(&mut self, device: &wgpu::Device, input: &wgpu::TextureView) {
+        self.input_bind_group = input_bind_group(device, &self.input_layout, input);
+    }
+
+    /// Redraws the brush masks if the strokes changed, then applies the layers. Strokes
+    /// changed while the stage was left out are drawn the next time it runs.
+    fn encode(&self, encoder: &mut wgpu::CommandEncoder, frame: &StageFrame) {
+        frame.queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(frame.local_adjustments));
+        self.brush_masks.update(frame.device, frame.queue, frame.brush_masks);
+        self.brush_masks.encode(encoder);
+
+        let mut pass = encoder.begin_compute_pass(&Default::default());
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.input_bind_group, &[]);
+        pass.set_bind_group(1, &self.bind_group, &[]);
+        pass.dispatch_workgroups(self.size[0].div_ceil(16), self.size[1].div_ceil(16), 1);
+    }
+}
@@ -0,0 +1,233 @@
+use eframe::wgpu;
+use eframe::wgpu::util::DeviceExt;
+use eframe::wgpu::{BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, ComputePipeline, ShaderStages, TextureFormat, TextureSampleType, TextureViewDimension};
+use crate::GpuBrushMaskPipeline::{buffer_entry, compute_pipeline, storage_texture_entry, texture_entry};
+use crate::LocalContrast::{pyramid_levels, LocalContrastUniform};
+use crate::ProcessingGraph::{GraphStage, Intermediate, IntermediatePool, Stage, StageFrame};
+
+/// Builds the local contrast pyramid, see pyramid.wgsl, then applies clarity, texture and
+/// dehaze from it, see local_contrast.wgsl
+#[derive(Clone)]
+pub struct GpuLocalContrastPipeline {
+    pub color_pipeline: ComputePipeline,
+    pub down_pipeline: ComputePipeline,
+    pub layout: wgpu::BindGroupLayout,
+    pub apply_pipeline: ComputePipeline,
+    pub apply_layout: wgpu::BindGroupLayout,
+    /// Bilinear, for the smooth pyramid reads
+    pub sampler: wgpu::Sampler,
+}
+
+impl GpuLocalContrastPipeline {
+    pub fn new(device: &wgpu::Device, cache: Option<&wgpu::PipelineCache>) -> Self {
+        let source = include_str!("shaders/pyramid.wgsl");
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Local Contrast Pyramid Bind Group Layout"),
+            entries: &[
+                texture_entry(0, TextureViewDimension::D2),
+                storage_texture_entry(1, TextureFormat::Rgba16Float, TextureViewDimension::D2),
+                buffer_entry(2, wgpu::BufferBindingType::Uniform),
+            ],
+        });
+        let apply_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Local Contrast Bind Group Layout"),
+            entries: &[
+                texture_entry(0, TextureViewDimension::D2),
+                // the pyramid, read smoothly between texels
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                storage_texture_entry(3, TextureFormat::Rgba16Float, TextureViewDimension::D2),
+                buffer_entry(4, wgpu::BufferBindingType::Uniform),
+                buffer_entry(5, wgpu::BufferBindingType::Uniform),
+            ],
+        });
+        let apply_source = concat!(include_str!("shaders/common.wgsl"), include_str!("shaders/local_contrast.wgsl"));
+        Self {
+            color_pipeline: compute_pipeline(device, cache, "Local Contrast Pyramid Pipeline", source, "pyramid_color_main", &layout),
+            down_pipeline: compute_pipeline(device, cache, "Local Contrast Downsample Pipeline", source, "pyramid_down_main", &layout),
+            layout,
+            apply_pipeline: compute_pipeline(device, cache, "Local Contrast Pipeline", apply_source, "local_contrast_main", &apply_layout),
+            apply_layout,
+            sampler: device.create_sampler(&wgpu::SamplerDescriptor {
+                label: Some("Local Contrast Sampler"),
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                ..Default::default()
+            }),
+        }
+    }
+
+    fn apply_bind_group(
+        &self,
+        device: &wgpu::Device,
+        input: &wgpu::TextureView,
+        pyramid: &wgpu::TextureView,
+        output: &wgpu::TextureView,
+        uniform: &wgpu::Buffer,
+        settings: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Local Contrast Bind Group"),
+            layout: &self.apply_layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: BindingResource::TextureView(input) },
+                wgpu::BindGroupEntry { binding: 1, resource: BindingResource::TextureView(pyramid) },
+                wgpu::BindGroupEntry { binding: 2, resource: BindingResource::Sampler(&self.sampler) },
+                wgpu::BindGroupEntry { binding: 3, resource: BindingResource::TextureView(output) },
+                wgpu::BindGroupEntry { binding: 4, resource: uniform.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 5, resource: settings.as_entire_binding() },
+            ],
+        })
+    }
+}
+
+/// The pyramid for one image, its build passes, one per level, and the pass that reads it
+pub struct LocalContrastResources {
+    pipeline: GpuLocalContrastPipeline,
+    /// Bind group and size of each level, first level first
+    levels: Vec<(wgpu::BindGroup, [u32; 2])>,
+    apply_bind_group: wgpu::BindGroup,
+
+    level_views: Vec<wgpu::TextureView>,
+    /// Every level, bilinear filterable for the smooth reads in local_contrast.wgsl
+    pyramid_view: wgpu::TextureView,
+    /// Airlight and which levels to read, fixed for the image
+    uniform_buffer: wgpu::Buffer,
+    settings_buffer: wgpu::Buffer,
+    contrasted_view: wgpu::TextureView,
+    size: [u32; 2],
+}
+
+impl LocalContrastResources {
+    /// `settings_buffer` holds the `ImageControls`
+    pub fn new(
+        device: &wgpu::Device,
+        pool: &mut IntermediatePool,
+        pipeline: &GpuLocalContrastPipeline,
+        input: &wgpu::TextureView,
+        settings_buffer: &wgpu::Buffer,
+        output: [u32; 2],
+        airlight: [f32; 3],
+    ) -> Self {
+        let count = pyramid_levels(output);
+        let texture = pool.stage().texture(
+            Intermediate::new([output[0].div_ceil(2), output[1].div_ceil(2)], TextureFormat::Rgba16Float).mip_levels(count),
+        );
+        let level_views: Vec<wgpu::TextureView> = (0..count)
+            .map(|level| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Local Contrast Pyramid Level"),
+                    base_mip_level: level,
+                    mip_level_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        let contrasted_view = pool
+            .output("Local Contrast Texture", Intermediate::new(output, TextureFormat::Rgba16Float), wgpu::TextureUsages::empty())
+            .create_view(&Default::default());
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Local Contrast Buffer"),
+            contents: bytemuck::bytes_of(&LocalContrastUniform::new(airlight, output)),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        // Each level reads the one before it, the first reads the picture
+        let levels = (0..count as usize)
+            .map(|level| {
+                let level_input = if level == 0 { input } else { &level_views[level - 1] };
+                let bind_group = level_bind_group(device, &pipeline.layout, level_input, &level_views[level], &uniform_buffer);
+                let size = texture.size().mip_level_size(level as u32, wgpu::TextureDimension::D2);
+                (bind_group, [size.width, size.height])
+            })
+            .collect();
+
+        let pyramid_view = texture.create_view(&Default::default());
+
+        Self {
+            pipeline: pipeline.clone(),
+            levels,
+            apply_bind_group: pipeline.apply_bind_group(device, input, &pyramid_view, &contrasted_view, &uniform_buffer, settings_buffer),
+            level_views,
+            pyramid_view,
+            uniform_buffer,
+            settings_buffer: settings_buffer.clone(),
+            contrasted_view,
+            size: output,
+        }
+    }
+}
+
+fn level_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    input: &wgpu::TextureView,
+    output: &wgpu::TextureView,
+    uniform: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Local Contrast Pyramid Bind Group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry { binding: 0, resource: BindingResource::TextureView(input) },
+            wgpu::BindGroupEntry { binding: 1, resource: BindingResource::TextureView(output) },
+            wgpu::BindGroupEntry { binding: 2, resource: uniform.as_entire_binding() },
+        ],
+    })
+}
+
+impl GraphStage for LocalContrastResources {
+    fn stage(&self) -> Stage {
+        Stage::LocalContrast
+    }
+
+    fn output(&self) -> &wgpu::TextureView {
+        &self.contrasted_view
+    }
+
+    fn is_active(&self, frame: &StageFrame) -> bool {
+        frame.controls.local_contrast_active()
+    }
+
+    /// The first pyramid level and the pass applying it read the input
+    fn bind(&mut self, device: &wgpu::Device, input: &wgpu::TextureView) {
+        self.levels[0].0 = level_bind_group(device, &self.pipeline.layout, input, &self.level_views[0], &self.uniform_buffer);
+        self.apply_bind_group = self.pipeline.apply_bind_group(
+            device,
+            input,
+            &self.pyramid_view,
+            &self.contrasted_view,
+            &self.uniform_buffer,
+            &self.settings_buffer,
+        );
+    }
+
+    /// Rebuilds the pyramid, then applies it
+    fn encode(&self, encoder: &mut wgpu::CommandEncoder, _frame: &StageFrame) {
+        let mut pass = encoder.begin_compute_pass(&Default::default());
+        for (level, (bind_group, size)) in self.levels.iter().enumerate() {
+            pass.set_pipeline(if level == 0 { &self.pipeline.color_pipeline } else { &self.pipeline.down_pipeline });
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.dispatch_workgroups(size[0].div_ceil(16), size[1].div_ceil(16), 1);
+        }
+
+        pass.set_pipeline(&self.pipeline.apply_pipeline);
+        pass.set_bind_group(0, &self.apply_bind_group, &[]);
+        pass.dispatch_workgroups(self.size[0].div_ceil(16), self.size[1].div_ceil(16), 1);
+    }
+}
@@ -0,0 +1,109 @@
+use eframe::egui_wgpu::RenderState;
+use eframe::wgpu;
+use crate::GpuBrushMaskPipeline::GpuBrushMaskPipeline;
+use crate::GpuDenoisePipeline::GpuDenoisePipeline;
+use crate::GpuGeometryPipeline::GpuGeometryPipeline;
+use crate::GpuHistogramPipeline::GpuHistogramPipeline;
+use crate::GpuImageComputePipeline::GpuImageComputePipeline;
+use crate::GpuImageRenderPipeline::GpuImageRenderPipeline;
+use crate::GpuLocalContrastPipeline::GpuLocalContrastPipeline;
+use crate::GpuLocalAdjustmentsPipeline::GpuLocalAdjustmentsPipeline;
+use crate::GpuScopesPipeline::GpuScopesPipeline;
+use crate::GpuSharpenPipeline::GpuSharpenPipeline;
+use std::path::PathBuf;
+
+/// Every pipeline and bind group layout the app uses. None of them depend on the image, so
+/// they're built once at startup and each load only makes its own textures and bind groups.
+pub struct GpuPipelines {
+    pub render: GpuImageRenderPipeline,
+    pub compute: GpuImageComputePipeline,
+    pub geometry: GpuGeometryPipeline,
+    pub histogram: GpuHistogramPipeline,
+    pub scopes: GpuScopesPipeline,
+    pub brush_mask: GpuBrushMaskPipeline,
+    pub sharpen: GpuSharpenPipeline,
+    pub denoise: GpuDenoisePipeline,
+    pub local_contrast: GpuLocalContrastPipeline,
+    pub local_adjustments: GpuLocalAdjustmentsPipeline,
+}
+
+/// Where compiled pipelines are kept between runs, the platform's cache directory
+fn cache_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .or_else(|| std::env::var_os("LOCALAPPDATA").map(PathBuf::from))?;
+    Some(base.join("film-emulator"))
+}
+
+/// The driver's compiled pipelines from the last run, so startup doesn't compile every
+/// shader again. Only some backends (Vulkan) have one, and the device has to have been
+/// asked for `PIPELINE_CACHE`, see main.
+struct DiskPipelineCache {
+    cache: wgpu::PipelineCache,
+    path: PathBuf,
+}
+
+impl DiskPipelineCache {
+    fn open(render_state: &RenderState) -> Option<Self> {
+        if !render_state.device.features().contains(wgpu::Features::PIPELINE_CACHE) {
+            return None;
+        }
+        let key = wgpu::util::pipeline_cache_key(&render_state.adapter.get_info())?;
+        let path = cache_dir()?.join(key);
+        let data = std::fs::read(&path).ok();
+        // SAFETY: the data is only ever what `get_data` gave us for this adapter, and with
+        // `fallback` set wgpu starts an empty cache if the driver rejects it
+        let cache = unsafe {
+            render_state.device.create_pipeline_cache(&wgpu::PipelineCacheDescriptor {
+                label: Some("Pipeline Cache"),
+                data: data.as_deref(),
+                fallback: true,
+            })
+        };
+        Some(Self { cache, path })
+    }
+
+    /// Writes the cache out through a temporary file, so a crash can't leave half of one
+    /// to be read next time
+    fn save(&self) -> std::io::Result<()> {
+        let Some(data) = self.cache.get_data() else {
+            return Ok(());
+        };
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let temp = self.path.with_extension("tmp");
+        std::fs::write(&temp, data)?;
+        std::fs::rename(&temp, &self.path)
+    }
+}
+
+impl GpuPipelines {
+    pub fn new(render_state: &RenderState) -> Self {
+        let device = &render_state.device;
+        let disk_cache = DiskPipelineCache::open(render_state);
+        let cache = disk_cache.as_ref().map(|c| &c.cache);
+
+        let pipelines = Self {
+            render: GpuImageRenderPipeline::new(device, cache),
+            compute: GpuImageComputePipeline::new(device, cache),
+            geometry: GpuGeometryPipeline::new(device, cache),
+            histogram: GpuHistogramPipeline::new(device, cache),
+            scopes: GpuScopesPipeline::new(device, cache),
+            brush_mask: GpuBrushMaskPipeline::new(device, cache),
+            sharpen: GpuSharpenPipeline::new(device, cache),
+            denoise: GpuDenoisePipeline::new(device, cache),
+            local_contrast: GpuLocalContrastPipeline::new(device, cache),
+            local_adjustments: GpuLocalAdjustmentsPipeline::new(device, cache),
+        };
+
+        // Everything is compiled by now, nothing is added to the cache later
+        if let Some(disk_cache) = &disk_cache
+            && let Err(err) = disk_cache.save()
+        {
+            println!("Failed to save pipeline cache to {:?}: {}", disk_cache.path, err);
+        }
+        pipelines
+    }
+}
@@ -0,0 +1,73 @@
+use std::sync::{Arc, Mutex};
+use eframe::wgpu;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ReadbackState {
+    Idle,
+    Mapping,
+    Ready,
+}
+
+/// A MAP_READ buffer for getting results off the GPU without stalling the frame.
+/// Copy into `buffer`, submit, call `map`, then call `take` on later frames until it
+/// returns the data. Nothing new should be copied in until it's idle again.
+pub struct GpuReadback {
+    pub buffer: wgpu::Buffer,
+    state: Arc<Mutex<ReadbackState>>,
+}
+
+impl GpuReadback {
+    pub fn new(device: &wgpu::Device, label: &str, size: u64) -> Self {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            buffer,
+            state: Arc::new(Mutex::new(ReadbackState::Idle)),
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        *self.state.lock().unwrap() == ReadbackState::Idle
+    }
+
+    /// Starts mapping. Call after the submit that copies into `buffer`.
+    pub fn map(&self) {
+        *self.state.lock().unwrap() = ReadbackState::Mapping;
+        let state = self.state.clone();
+        self.buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+            *state.lock().unwrap() = if result.is_ok() {
+                ReadbackState::Ready
+            } else {
+                ReadbackState::Idle
+            };
+        });
+    }
+
+    /// The buffer contents once the map has completed, None while it's still in flight
+    pub fn take(&self) -> Option<Vec<u8>> {
+        let mut state = self.state.lock().unwrap();
+        if *state != ReadbackState::Ready {
+            return None;
+        }
+
+        let data = self.buffer.slice(..).get_mapped_range().to_vec();
+        self.buffer.unmap();
+        *state = ReadbackState::Idle;
+        Some(data)
+    }
+
+    /// `take` for buffers of u32 counters
+    pub fn take_u32(&self) -> Option<Vec<u32>> {
+        let data = self.take()?;
+        Some(
+            data.chunks_exact(4)
+                .map(|b| u32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+                .collect(),
+        )
+    }
+}
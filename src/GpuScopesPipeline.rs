@@ -0,0 +1,94 @@
+use eframe::wgpu;
+use eframe::wgpu::{BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, ComputePipeline, ComputePipelineDescriptor, PipelineLayoutDescriptor, ShaderModuleDescriptor, ShaderStages, TextureSampleType, TextureViewDimension};
+
+/// Waveform and vectorscope grids are SCOPE_SIZE x SCOPE_SIZE
+pub const SCOPE_SIZE: usize = 256;
+
+/// Luma, red, green and blue waveform planes
+pub const WAVEFORM_BINS: usize = 4 * SCOPE_SIZE * SCOPE_SIZE;
+pub const VECTORSCOPE_BINS: usize = SCOPE_SIZE * SCOPE_SIZE;
+
+// Must match MAX_SAMPLES_ACROSS in scopes.wgsl
+const MAX_SAMPLES_ACROSS: u32 = 1024;
+
+pub struct GpuScopesPipeline {
+    pub waveform_pipeline: ComputePipeline,
+    pub vectorscope_pipeline: ComputePipeline,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+}
+
+/// Workgroups needed to cover the subsampled image, see `sample_step` in scopes.wgsl
+pub fn scope_workgroups(width: u32, height: u32) -> (u32, u32) {
+    let step = width.div_ceil(MAX_SAMPLES_ACROSS).max(1);
+    (width.div_ceil(step).div_ceil(16), height.div_ceil(step).div_ceil(16))
+}
+
+fn storage_entry(binding: u32) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: false },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+impl GpuScopesPipeline {
+    pub fn new(device: &wgpu::Device, cache: Option<&wgpu::PipelineCache>) -> Self {
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Scopes Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                include_str!("shaders/scopes.wgsl").into()
+            ),
+        });
+
+        let bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("Scopes Bind Group Layout"),
+                entries: &[
+                    // processed texture
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: false },
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    // waveform bins
+                    storage_entry(1),
+                    // vectorscope bins
+                    storage_entry(2),
+                ],
+            });
+
+        let pipeline_layout =
+            device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("Scopes Pipeline Layout"),
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[],
+            });
+
+        let create = |label: &str, entry_point: &str| {
+            device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                cache,
+            })
+        };
+
+        Self {
+            waveform_pipeline: create("Waveform Pipeline", "waveform_main"),
+            vectorscope_pipeline: create("Vectorscope Pipeline", "vectorscope_main"),
+            bind_group_layout,
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use eframe::wgpu;
+use eframe::wgpu::{BindGroupLayoutDescriptor, BindingResource, ComputePipeline, TextureFormat, TextureViewDimension};
+use crate::GpuBrushMaskPipeline::{buffer_entry, compute_pipeline, storage_texture_entry, texture_entry};
+use crate::ProcessingGraph::{GraphStage, Intermediate, IntermediatePool, Stage, StageFrame};
+
+/// Largest blur radius (sigma) the sharpening slider offers, in pixels
+pub const MAX_SHARPEN_RADIUS: f32 = 3.0;
+
+// Mirrors `GaussianUniform` in gaussian_blur.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct GaussianUniform {
+    direction: [i32; 2],
+    radius: i32,
+    from_color: u32,
+    sigma: [f32; 4],
+}
+
+/// Capture sharpening: a separable Gaussian blur of the luma, then the unsharp mask against it
+#[derive(Clone)]
+pub struct GpuSharpenPipeline {
+    pub pipeline: ComputePipeline,
+    pub layout: wgpu::BindGroupLayout,
+    pub apply_pipeline: ComputePipeline,
+    pub apply_layout: wgpu::BindGroupLayout,
+}
+
+impl GpuSharpenPipeline {
+    pub fn new(device: &wgpu::Device, cache: Option<&wgpu::PipelineCache>) -> Self {
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Gaussian Blur Bind Group Layout"),
+            entries: &[
+                texture_entry(0, TextureViewDimension::D2),
+                storage_texture_entry(1, TextureFormat::R32Float, TextureViewDimension::D2),
+                buffer_entry(2, wgpu::BufferBindingType::Uniform),
+            ],
+        });
+        let apply_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Sharpen Bind Group Layout"),
+            entries: &[
+                texture_entry(0, TextureViewDimension::D2),
+                texture_entry(1, TextureViewDimension::D2),
+                storage_texture_entry(2, TextureFormat::Rgba16Float, TextureViewDimension::D2),
+                buffer_entry(3, wgpu::BufferBindingType::Uniform),
+            ],
+        });
+        let source = concat!(include_str!("shaders/common.wgsl"), include_str!("shaders/sharpen.wgsl"));
+        Self {
+            pipeline: compute_pipeline(device, cache, "Gaussian Blur Pipeline", include_str!("shaders/gaussian_blur.wgsl"), "gaussian_main", &layout),
+            layout,
+            apply_pipeline: compute_pipeline(device, cache, "Sharpen Pipeline", source, "sharpen_main", &apply_layout),
+            apply_layout,
+        }
+    }
+
+    fn blur_bind_group(
+        &self,
+        device: &wgpu::Device,
+        input: &wgpu::TextureView,
+        output: &wgpu::TextureView,
+        uniform: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Gaussian Blur Bind Group"),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: BindingResource::TextureView(input) },
+                wgpu::BindGroupEntry { binding: 1, resource: BindingResource::TextureView(output) },
+                wgpu::BindGroupEntry { binding: 2, resource: uniform.as_entire_binding() },
+            ],
+        })
+    }
+
+    fn apply_bind_group(
+        &self,
+        device: &wgpu::Device,
+        input: &wgpu::TextureView,
+        blurred: &wgpu::TextureView,
+        output: &wgpu::TextureView,
+        settings: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Sharpen Bind Group"),
+            layout: &self.apply_layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: BindingResource::TextureView(input) },
+                wgpu::BindGroupEntry { binding: 1, resource: BindingResource::TextureView(blurred) },
+                wgpu::BindGroupEntry { binding: 2, resource: BindingResource::TextureView(output) },
+                wgpu::BindGroupEntry { binding: 3, resource: settings.as_entire_binding() },
+            ],
+        })
+    }
+}
+
+/// The blurred luma, the sharpened image and the passes that make them, sized to the
+/// framed image
+pub struct SharpenResources {
+    pipeline: GpuSharpenPipeline,
+
+    across_buffer: wgpu::Buffer,
+    down_buffer: wgpu::Buffer,
+    settings_buffer: wgpu::Buffer,
+    temp_view: wgpu::TextureView,
+    /// Blurred luma, what the unsharp mask subtracts to find the detail
+    blurred_view: wgpu::TextureView,
+    sharpened_view: wgpu::TextureView,
+
+    /// Across straight from the input into temp, then down into the blurred luma
+    across_bind_group: wgpu::BindGroup,
+    down_bind_group: wgpu::BindGroup,
+    apply_bind_group: wgpu::BindGroup,
+    size: [u32; 2],
+}
+
+impl SharpenResources {
+    /// `settings_buffer` holds the `ImageControls`
+    pub fn new(
+        device: &wgpu::Device,
+        pool: &mut IntermediatePool,
+        pipeline: &GpuSharpenPipeline,
+        input: &wgpu::TextureView,
+        settings_buffer: &wgpu::Buffer,
+        output: [u32; 2],
+    ) -> Self {
+        let shape = Intermediate::new(output, TextureFormat::R32Float);
+        let mut scratch = pool.stage();
+        let temp_view = scratch.texture(shape).create_view(&Default::default());
+        let blurred_view = scratch.texture(shape).create_view(&Default::default());
+        let sharpened_view = pool
+            .output("Sharpened Texture", Intermediate::new(output, TextureFormat::Rgba16Float), wgpu::TextureUsages::empty())
+            .create_view(&Default::default());
+
+        let uniform_buffer = || {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Gaussian Blur Buffer"),
+                size: size_of::<GaussianUniform>() as u64,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        };
+        let across_buffer = uniform_buffer();
+        let down_buffer = uniform_buffer();
+
+        Self {
+            across_bind_group: pipeline.blur_bind_group(device, input, &temp_view, &across_buffer),
+            down_bind_group: pipeline.blur_bind_group(device, &temp_view, &blurred_view, &down_buffer),
+            apply_bind_group: pipeline.apply_bind_group(device, input, &blurred_view, &sharpened_view, settings_buffer),
+            pipeline: pipeline.clone(),
+            across_buffer,
+            down_buffer,
+            settings_buffer: settings_buffer.clone(),
+            temp_view,
+            blurred_view,
+            sharpened_view,
+            size: output,
+        }
+    }
+
+    /// Blur radius (sigma) in pixels for the next pass
+    fn update(&self, queue: &wgpu::Queue, sigma: f32) {
+        let sigma = sigma.clamp(0.3, MAX_SHARPEN_RADIUS);
+        let radius = (sigma * 3.0).ceil() as i32;
+        for (buffer, direction, from_color) in [(&self.across_buffer, [1, 0], 1), (&self.down_buffer, [0, 1], 0)] {
+            let uniform = GaussianUniform { direction, radius, from_color, sigma: [sigma, 0.0, 0.0, 0.0] };
+            queue.write_buffer(buffer, 0, bytemuck::bytes_of(&uniform));
+        }
+    }
+}
+
+impl GraphStage for SharpenResources {
+    fn stage(&self) -> Stage {
+        Stage::Sharpening
+    }
+
+    fn output(&self) -> &wgpu::TextureView {
+        &self.sharpened_view
+    }
+
+    fn is_active(&self, frame: &StageFrame) -> bool {
+        frame.controls.sharpen_radius().is_some()
+    }
+
+    /// Alt-dragging a slider shows what sharpening does on its own
+    fn isolates(&self, frame: &StageFrame) -> bool {
+        frame.controls.sharpen_previewing()
+    }
+
+    fn bind(&mut self, device: &wgpu::Device, input: &wgpu::TextureView) {
+        self.across_bind_group = self.pipeline.blur_bind_group(device, input, &self.temp_view, &self.across_buffer);
+        self.apply_bind_group =
+            self.pipeline.apply_bind_group(device, input, &self.blurred_view, &self.sharpened_view, &self.settings_buffer);
+    }
+
+    fn encode(&self, encoder: &mut wgpu::CommandEncoder, frame: &StageFrame) {
+        let Some(radius) = frame.controls.sharpen_radius() else {
+            return;
+        };
+        self.update(frame.queue, radius);
+        let (gx, gy) = (self.size[0].div_ceil(16), self.size[1].div_ceil(16));
+        let mut pass = encoder.begin_compute_pass(&Default::default());
+        pass.set_pipeline(&self.pipeline.pipeline);
+        pass.set_bind_group(0, &self.across_bind_group, &[]);
+        pass.dispatch_workgroups(gx, gy, 1);
+        pass.set_bind_group(0, &self.down_bind_group, &[]);
+        pass.dispatch_workgroups(gx, gy, 1);
+
+        pass.set_pipeline(&self.pipeline.apply_pipeline);
+        pass.set_bind_group(0, &self.apply_bind_group, &[]);
+        pass.dispatch_workgroups(gx, gy, 1);
+    }
+}
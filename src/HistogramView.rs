@@ -0,0 +1,147 @@
+use egui::{Align2, Color32, FontId, Mesh, Rect, Sense, Vec2};
+use crate::ImageControls::{ImageControls, ToneControl};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HistogramMode {
+    Rgb,
+    Luma,
+}
+
+/// Histogram panel fed from the GPU histogram readback. The plot is split into
+/// shadows / exposure / highlights thirds, dragging sideways in one adjusts that slider.
+pub struct HistogramView {
+    bins: Vec<u32>,
+    pub mode: HistogramMode,
+    dragging: Option<ToneControl>,
+}
+
+impl Default for HistogramView {
+    fn default() -> Self {
+        Self {
+            bins: Vec::new(),
+            mode: HistogramMode::Rgb,
+            dragging: None,
+        }
+    }
+}
+
+fn region_at(fraction: f32) -> ToneControl {
+    if fraction < 1.0 / 3.0 {
+        ToneControl::Shadows
+    } else if fraction < 2.0 / 3.0 {
+        ToneControl::Exposure
+    } else {
+        ToneControl::Highlights
+    }
+}
+
+fn region_label(control: ToneControl) -> &'static str {
+    match control {
+        ToneControl::Shadows => "Shadows",
+        ToneControl::Exposure => "Exposure",
+        ToneControl::Highlights => "Highlights",
+    }
+}
+
+/// One bar per bin, scaled so `peak` fills the plot
+fn channel_mesh(rect: Rect, bins: &[u32], peak: f32, color: Color32) -> Mesh {
+    let mut mesh = Mesh::default();
+    let bar_width = rect.width() / bins.len() as f32;
+    for (i, &count) in bins.iter().enumerate() {
+        let height = (count as f32 / peak).min(1.0) * rect.height();
+        if height <= 0.0 {
+            continue;
+        }
+        let x = rect.left() + i as f32 * bar_width;
+        let bar = Rect::from_min_max(
+            egui::pos2(x, rect.bottom() - height),
+            egui::pos2(x + bar_width, rect.bottom()),
+        );
+        mesh.add_colored_rect(bar, color);
+    }
+    mesh
+}
+
+impl HistogramView {
+    /// 256 red, 256 green, 256 blue then 256 luma counts
+    pub fn set_bins(&mut self, bins: Vec<u32>) {
+        self.bins = bins;
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, controls: &mut ImageControls) {
+        let size = Vec2::new(ui.available_width(), 110.0);
+        let (rect, response) = ui.allocate_exact_size(size, Sense::click_and_drag());
+        let painter = ui.painter_at(rect);
+
+        painter.rect_filled(rect, 2.0, Color32::from_gray(24));
+
+        if self.bins.len() == 1024 {
+            let channels: Vec<(&[u32], Color32)> = match self.mode {
+                HistogramMode::Rgb => vec![
+                    (&self.bins[0..256], Color32::from_rgba_unmultiplied(255, 60, 60, 110)),
+                    (&self.bins[256..512], Color32::from_rgba_unmultiplied(60, 255, 60, 110)),
+                    (&self.bins[512..768], Color32::from_rgba_unmultiplied(60, 100, 255, 110)),
+                ],
+                HistogramMode::Luma => vec![
+                    (&self.bins[768..1024], Color32::from_gray(200)),
+                ],
+            };
+
+            // Ignore the end bins when scaling, clipped pixels would flatten everything else
+            let peak = channels
+                .iter()
+                .flat_map(|(bins, _)| bins[1..255].iter())
+                .copied()
+                .max()
+                .unwrap_or(1)
+                .max(1) as f32;
+
+            for (bins, color) in channels {
+                painter.add(channel_mesh(rect, bins, peak, color));
+            }
+        }
+
+        // Region hover / drag feedback
+        let fraction_at = |x: f32| ((x - rect.left()) / rect.width()).clamp(0.0, 0.999);
+        if response.drag_started() {
+            self.dragging = response.interact_pointer_pos().map(|p| region_at(fraction_at(p.x)));
+        }
+        if response.dragged()
+            && let Some(control) = self.dragging
+        {
+            controls.nudge_tone_control(control, response.drag_delta().x / rect.width());
+        }
+        if response.drag_stopped() {
+            self.dragging = None;
+        }
+
+        let active = self.dragging.or_else(|| {
+            response.hover_pos().map(|p| region_at(fraction_at(p.x)))
+        });
+        if let Some(control) = active {
+            let third = rect.width() / 3.0;
+            let index = match control {
+                ToneControl::Shadows => 0.0,
+                ToneControl::Exposure => 1.0,
+                ToneControl::Highlights => 2.0,
+            };
+            let region = Rect::from_min_size(
+                egui::pos2(rect.left() + third * index, rect.top()),
+                Vec2::new(third, rect.height()),
+            );
+            painter.rect_filled(region, 0.0, Color32::from_white_alpha(12));
+            painter.text(
+                region.center_top() + Vec2::new(0.0, 4.0),
+                Align2::CENTER_TOP,
+                region_label(control),
+                FontId::proportional(11.0),
+                Color32::from_gray(220),
+            );
+        }
+
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.mode, HistogramMode::Rgb, "RGB");
+            ui.selectable_value(&mut self.mode, HistogramMode::Luma, "Luma");
+        });
+    }
+}
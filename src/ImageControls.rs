@@ -1,7 +1,21 @@
 use crate::View;
+use crate::ColorWheel::color_wheel;
+use crate::GpuSharpenPipeline::MAX_SHARPEN_RADIUS;
+use crate::ProcessingGraph::{Stage, StageBypass};
 
+pub const GRADING_WHEELS: u32 = 0;
+pub const GRADING_SPLIT_TONE: u32 = 1;
+
+// Must match the sharpening previews in sharpen.wgsl
+const SHARPEN_PREVIEW_NONE: u32 = 0;
+const SHARPEN_PREVIEW_MASK: u32 = 1;
+const SHARPEN_PREVIEW_LUMA: u32 = 2;
+
+// Mirrors `ImageControls` in common.wgsl, so field order and padding must follow WGSL
+// uniform layout rules (vec4 fields start on a 16 byte boundary, size rounds up to 16).
 #[repr(C)]
-#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 pub struct ImageControls {
     exposure: f32,
     contrast: f32,
@@ -9,6 +23,152 @@ pub struct ImageControls {
     brightness: f32,
     highlights: f32,
     shadows: f32,
+    vibrance: f32,
+    #[serde(skip)]
+    _pad0: f32,
+
+    // Colour wheels: hue (degrees), saturation (0-1), luminance offset, unused
+    shadow_wheel: [f32; 4],
+    midtone_wheel: [f32; 4],
+    highlight_wheel: [f32; 4],
+    grading_balance: f32,
+    grading_mode: u32,
+
+    split_shadow_hue: f32,
+    split_shadow_saturation: f32,
+    split_highlight_hue: f32,
+    split_highlight_saturation: f32,
+    split_balance: f32,
+    #[serde(skip)]
+    _pad1: f32,
+
+    // HSL mixer, one value per band in HSL_BAND_NAMES order
+    hsl_hue: [[f32; 4]; 2],        // hue shift in degrees
+    hsl_saturation: [[f32; 4]; 2], // -1 (grey) to +1
+    hsl_luminance: [[f32; 4]; 2],  // -1 (darker) to +1
+
+    // Black & white: channel mixer weights (red, green, blue, unused)
+    bw_mixer: [f32; 4],
+    bw_enabled: u32,
+    bw_filter: u32, // index into BW_FILTER_NAMES
+    bw_toning: u32, // index into BW_TONING_NAMES
+    bw_toning_amount: f32,
+
+    // Highlight roll-off / display transform
+    tone_mapper: u32,  // index into TONE_MAPPER_NAMES
+    white_point: f32,  // scene-linear value that maps to display white
+
+    // Creative vignette, measured on the cropped frame
+    vignette_amount: f32,     // stops at the corners, negative darkens
+    vignette_midpoint: f32,   // 0 (centre) to 1, where the falloff starts
+    vignette_roundness: f32,  // -1 (follows the frame) to +1 (circle)
+    vignette_feather: f32,    // 0 (hard edge) to 1
+    vignette_highlights: f32, // 0 to 1, how much bright areas resist darkening
+
+    // Capture sharpening, an unsharp mask on the luma before any tone work
+    sharpen_amount: f32,  // 0 (off) to 3
+    sharpen_radius: f32,  // blur sigma in pixels, up to MAX_SHARPEN_RADIUS
+    sharpen_detail: f32,  // 0 (no halos past the local range) to 1 (full overshoot)
+    sharpen_masking: f32, // 0 (everywhere) to 1 (strong edges only)
+    #[serde(skip)]
+    sharpen_preview: u32, // SHARPEN_PREVIEW_*, set while Alt-dragging a slider
+
+    // Local contrast, all -1 to +1 with 0 off. Read from the pyramid in pyramid.wgsl.
+    texture: f32, // fine detail
+    clarity: f32, // midtone contrast over larger shapes
+    dehaze: f32,  // dark channel prior haze removal, negative adds haze
+    #[serde(skip)]
+    mask_overlay: u32, // 1 while the selected local adjustment's mask is tinted
+}
+
+// Must match the tone mapping modes in common.wgsl
+pub const TONE_MAPPER_NAMES: [&str; 4] = ["Clip", "Filmic", "ACES", "AgX"];
+
+// Approximate spectral sensitivity of classic stocks, expressed as red/green/blue mixer weights
+pub const BW_STOCKS: [(&str, [f32; 3]); 7] = [
+    ("Neutral (Rec.601)", [0.299, 0.587, 0.114]),
+    ("Kodak Tri-X 400", [0.27, 0.55, 0.18]),
+    ("Ilford HP5 Plus", [0.25, 0.57, 0.18]),
+    ("Kodak T-Max 100", [0.28, 0.56, 0.16]),
+    ("Ilford Delta 100", [0.24, 0.58, 0.18]),
+    ("Ilford SFX 200 (extended red)", [0.55, 0.35, 0.10]),
+    ("Ilford Ortho Plus (orthochromatic)", [0.0, 0.55, 0.45]),
+];
+
+// Must match BW_FILTERS in compute.wgsl
+pub const BW_FILTER_NAMES: [&str; 6] = ["None", "Yellow", "Orange", "Red", "Green", "Blue"];
+
+// Must match the toning modes in compute.wgsl
+pub const BW_TONING_NAMES: [&str; 4] = ["None", "Selenium", "Sepia", "Cyanotype"];
+
+pub const HSL_BAND_NAMES: [&str; 8] = [
+    "Reds", "Oranges", "Yellows", "Greens", "Aquas", "Blues", "Purples", "Magentas",
+];
+
+// Must match HSL_BAND_CENTERS in compute.wgsl
+const HSL_BAND_CENTERS: [f32; 8] = [0.0, 30.0, 60.0, 120.0, 180.0, 240.0, 270.0, 300.0];
+
+/// Main tone sliders that can be dragged directly from the histogram
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ToneControl {
+    Shadows,
+    Exposure,
+    Highlights,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HslProperty {
+    Hue,
+    Saturation,
+    Luminance,
+}
+
+/// UI state for the HSL mixer that doesn't belong in the GPU uniform
+pub struct HslMixerState {
+    pub property: HslProperty,
+    /// Target adjustment tool: drag up/down on the image to adjust the band under the cursor
+    pub targeting: bool,
+    /// Hue picked at the start of the current target drag
+    pub target_hue: Option<f32>,
+}
+
+impl Default for HslMixerState {
+    fn default() -> Self {
+        Self {
+            property: HslProperty::Hue,
+            targeting: false,
+            target_hue: None,
+        }
+    }
+}
+
+/// The two mixer bands a hue falls between and how far it is towards the upper one.
+/// Same smooth falloff as `hue_band_blend` in compute.wgsl.
+fn hue_band_blend(hue: f32) -> (usize, usize, f32) {
+    let hue = hue.rem_euclid(360.0);
+    let lower = HSL_BAND_CENTERS.iter().rposition(|&c| hue >= c).unwrap_or(7);
+    let upper = (lower + 1) % 8;
+    let span = if upper == 0 { 360.0 - HSL_BAND_CENTERS[lower] } else { HSL_BAND_CENTERS[upper] - HSL_BAND_CENTERS[lower] };
+    let t = ((hue - HSL_BAND_CENTERS[lower]) / span).clamp(0.0, 1.0);
+    (lower, upper, t * t * (3.0 - 2.0 * t))
+}
+
+/// HSV hue in degrees of an RGB colour, None for neutral greys
+pub fn rgb_to_hue(rgb: [f32; 3]) -> Option<f32> {
+    let [r, g, b] = rgb;
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+    if delta < 1e-4 {
+        return None;
+    }
+    let h = if max == r {
+        ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        (b - r) / delta + 2.0
+    } else {
+        (r - g) / delta + 4.0
+    };
+    Some(h * 60.0)
 }
 
 
@@ -21,10 +181,171 @@ impl Default for ImageControls {
             brightness: 0.0,
             highlights: 0.0,
             shadows: 0.0,
+            vibrance: 0.0,
+            _pad0: 0.0,
+            shadow_wheel: [0.0; 4],
+            midtone_wheel: [0.0; 4],
+            highlight_wheel: [0.0; 4],
+            grading_balance: 0.0,
+            grading_mode: GRADING_WHEELS,
+            split_shadow_hue: 200.0,
+            split_shadow_saturation: 0.0,
+            split_highlight_hue: 40.0,
+            split_highlight_saturation: 0.0,
+            split_balance: 0.0,
+            _pad1: 0.0,
+            hsl_hue: [[0.0; 4]; 2],
+            hsl_saturation: [[0.0; 4]; 2],
+            hsl_luminance: [[0.0; 4]; 2],
+            bw_mixer: [0.27, 0.55, 0.18, 0.0],
+            bw_enabled: 0,
+            bw_filter: 0,
+            bw_toning: 0,
+            bw_toning_amount: 0.5,
+            tone_mapper: 0,
+            white_point: 1.0,
+            vignette_amount: 0.0,
+            vignette_midpoint: 0.5,
+            vignette_roundness: 0.0,
+            vignette_feather: 0.5,
+            vignette_highlights: 0.0,
+            sharpen_amount: 0.0,
+            sharpen_radius: 1.0,
+            sharpen_detail: 0.25,
+            sharpen_masking: 0.0,
+            sharpen_preview: SHARPEN_PREVIEW_NONE,
+            texture: 0.0,
+            clarity: 0.0,
+            dehaze: 0.0,
+            mask_overlay: 0,
         }
     }
 }
 
+impl ImageControls {
+    /// These controls with any menu choice that isn't one put back to its default, for
+    /// controls read from a file
+    pub fn validated(self) -> Self {
+        let default = Self::default();
+        let in_range = |value: u32, count: usize, fallback: u32| if (value as usize) < count { value } else { fallback };
+        Self {
+            tone_mapper: in_range(self.tone_mapper, TONE_MAPPER_NAMES.len(), default.tone_mapper),
+            bw_filter: in_range(self.bw_filter, BW_FILTER_NAMES.len(), default.bw_filter),
+            bw_toning: in_range(self.bw_toning, BW_TONING_NAMES.len(), default.bw_toning),
+            grading_mode: in_range(self.grading_mode, GRADING_SPLIT_TONE as usize + 1, default.grading_mode),
+            ..self
+        }
+    }
+
+    /// Whether anything reads the local contrast pyramid
+    pub fn local_contrast_active(&self) -> bool {
+        self.texture != 0.0 || self.clarity != 0.0 || self.dehaze != 0.0
+    }
+
+    /// These controls with the previews of bypassed stages off. The graph skips the stages
+    /// themselves, this is only what the colour pass shows of them.
+    pub fn bypassing(&self, bypass: &StageBypass) -> Self {
+        let mut controls = *self;
+        if bypass.is_bypassed(Stage::Sharpening) {
+            controls.sharpen_preview = SHARPEN_PREVIEW_NONE;
+        }
+        if bypass.is_bypassed(Stage::LocalAdjustments) {
+            controls.mask_overlay = 0;
+        }
+        controls
+    }
+
+    /// These controls with the selected local adjustment's mask tinted or not
+    pub fn showing_mask(&self, shown: bool) -> Self {
+        Self { mask_overlay: shown as u32, ..*self }
+    }
+
+    /// Whether the sharpening stage's output is what's on screen, on its own
+    pub fn sharpen_previewing(&self) -> bool {
+        self.sharpen_preview != SHARPEN_PREVIEW_NONE
+    }
+
+    /// Blur radius the sharpening passes need, None when nothing reads their result
+    pub fn sharpen_radius(&self) -> Option<f32> {
+        (self.sharpen_amount > 0.0 || self.sharpen_preview != SHARPEN_PREVIEW_NONE).then_some(self.sharpen_radius)
+    }
+
+    /// Moves one of the tone sliders by a fraction of its range
+    pub fn nudge_tone_control(&mut self, control: ToneControl, fraction: f32) {
+        let (value, min, max) = match control {
+            ToneControl::Shadows => (&mut self.shadows, -0.5, 0.5),
+            ToneControl::Exposure => (&mut self.exposure, -3.0, 3.0),
+            ToneControl::Highlights => (&mut self.highlights, -1.0, 1.0),
+        };
+        *value = (*value + fraction * (max - min)).clamp(min, max);
+    }
+
+    fn hsl_band(&mut self, property: HslProperty, band: usize) -> &mut f32 {
+        let bands = match property {
+            HslProperty::Hue => &mut self.hsl_hue,
+            HslProperty::Saturation => &mut self.hsl_saturation,
+            HslProperty::Luminance => &mut self.hsl_luminance,
+        };
+        &mut bands[band / 4][band % 4]
+    }
+
+    /// Nudges the mixer bands covering `hue`, split between them the same way the shader
+    /// blends them. Used by the target adjustment tool.
+    pub fn adjust_hsl_for_hue(&mut self, property: HslProperty, hue: f32, amount: f32) {
+        let (lower, upper, t) = hue_band_blend(hue);
+        let (min, max) = hsl_range(property);
+        for (band, weight) in [(lower, 1.0 - t), (upper, t)] {
+            let value = self.hsl_band(property, band);
+            *value = (*value + amount * weight).clamp(min, max);
+        }
+    }
+
+    pub fn hsl_ui(&mut self, ui: &mut egui::Ui, state: &mut HslMixerState) {
+        ui.separator();
+        ui.heading("HSL Mixer");
+
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut state.property, HslProperty::Hue, "Hue");
+            ui.selectable_value(&mut state.property, HslProperty::Saturation, "Saturation");
+            ui.selectable_value(&mut state.property, HslProperty::Luminance, "Luminance");
+        });
+
+        let (min, max) = hsl_range(state.property);
+        for (band, name) in HSL_BAND_NAMES.iter().enumerate() {
+            ui.add(egui::Slider::new(self.hsl_band(state.property, band), min..=max).text(*name));
+        }
+
+        ui.toggle_value(&mut state.targeting, "Target Adjustment")
+            .on_hover_text("Drag up or down on the image to adjust the colours under the cursor");
+    }
+}
+
+fn hsl_range(property: HslProperty) -> (f32, f32) {
+    match property {
+        HslProperty::Hue => (-30.0, 30.0),
+        HslProperty::Saturation | HslProperty::Luminance => (-1.0, 1.0),
+    }
+}
+
+fn index_combo(ui: &mut egui::Ui, label: &str, value: &mut u32, names: &[&str]) {
+    egui::ComboBox::from_label(label)
+        .selected_text(names.get(*value as usize).copied().unwrap_or_default())
+        .show_ui(ui, |ui| {
+            for (i, name) in names.iter().enumerate() {
+                ui.selectable_value(value, i as u32, *name);
+            }
+        });
+}
+
+fn grading_wheel(ui: &mut egui::Ui, label: &str, wheel: &mut [f32; 4]) {
+    let [hue, saturation, luminance, _] = wheel;
+    ui.vertical(|ui| {
+        ui.label(label);
+        color_wheel(ui, hue, saturation, 90.0);
+        ui.add(egui::Slider::new(luminance, -0.5..=0.5).show_value(false));
+    });
+}
+
 impl View for ImageControls {
     fn ui(&mut self, ui: &mut egui::Ui) {
         ui.heading("Image Controls");
@@ -32,8 +353,110 @@ impl View for ImageControls {
         ui.add(egui::Slider::new(&mut self.exposure, -3.0..=3.0).text("Exposure"));
         ui.add(egui::Slider::new(&mut self.contrast, 0.0..=2.0).text("Contrast"));
         ui.add(egui::Slider::new(&mut self.saturation, 0.0..=3.0).text("Saturation"));
+        ui.add(egui::Slider::new(&mut self.vibrance, -1.0..=1.0).text("Vibrance"));
         ui.add(egui::Slider::new(&mut self.brightness, -0.5..=0.5).text("Brightness"));
         ui.add(egui::Slider::new(&mut self.highlights, -1.0..=1.0).text("Highlights"));
         ui.add(egui::Slider::new(&mut self.shadows, -0.5..=0.5).text("Shadows"));
+        ui.add(egui::Slider::new(&mut self.texture, -1.0..=1.0).text("Texture"))
+            .on_hover_text("Fine detail like skin, foliage and fabric");
+        ui.add(egui::Slider::new(&mut self.clarity, -1.0..=1.0).text("Clarity"))
+            .on_hover_text("Midtone contrast over larger shapes, edges are left alone");
+        ui.add(egui::Slider::new(&mut self.dehaze, -1.0..=1.0).text("Dehaze"))
+            .on_hover_text("Removes haze and mist, or adds it when negative");
+
+        ui.separator();
+        ui.heading("Tone Mapping");
+
+        index_combo(ui, "Highlight Roll-off", &mut self.tone_mapper, &TONE_MAPPER_NAMES);
+        ui.add(
+            egui::Slider::new(&mut self.white_point, 1.0..=16.0)
+                .logarithmic(true)
+                .text("White Point"),
+        )
+        .on_hover_text("Scene brightness that maps to pure white. Raise it to pull back RAW highlights.");
+
+        ui.separator();
+        ui.heading("Sharpening");
+
+        // Holding Alt while dragging shows what the slider does in black and white
+        let alt = ui.input(|i| i.modifiers.alt);
+        let mut preview = SHARPEN_PREVIEW_NONE;
+        let mut slider = |ui: &mut egui::Ui, value: &mut f32, range, text, shows| {
+            let response = ui.add(egui::Slider::new(value, range).text(text));
+            if alt && response.dragged() {
+                preview = shows;
+            }
+            response
+        };
+        slider(ui, &mut self.sharpen_amount, 0.0..=3.0, "Amount", SHARPEN_PREVIEW_LUMA);
+        slider(ui, &mut self.sharpen_radius, 0.5..=MAX_SHARPEN_RADIUS, "Radius", SHARPEN_PREVIEW_LUMA)
+            .on_hover_text("Size of the detail that gets sharpened, in pixels");
+        slider(ui, &mut self.sharpen_detail, 0.0..=1.0, "Detail", SHARPEN_PREVIEW_LUMA)
+            .on_hover_text("How far edges may overshoot their surroundings. Low keeps halos away.");
+        slider(ui, &mut self.sharpen_masking, 0.0..=1.0, "Masking", SHARPEN_PREVIEW_MASK)
+            .on_hover_text("Keeps sharpening to the edges and out of skies and skin. Alt-drag to see the mask.");
+        self.sharpen_preview = preview;
+
+        ui.separator();
+        ui.heading("Vignette");
+
+        ui.add(egui::Slider::new(&mut self.vignette_amount, -2.0..=2.0).text("Amount"));
+        ui.add_enabled_ui(self.vignette_amount != 0.0, |ui| {
+            ui.add(egui::Slider::new(&mut self.vignette_midpoint, 0.0..=1.0).text("Midpoint"));
+            ui.add(egui::Slider::new(&mut self.vignette_roundness, -1.0..=1.0).text("Roundness"));
+            ui.add(egui::Slider::new(&mut self.vignette_feather, 0.0..=1.0).text("Feather"));
+            ui.add(egui::Slider::new(&mut self.vignette_highlights, 0.0..=1.0).text("Highlight Protection"))
+                .on_hover_text("Keeps bright areas like lamps and skies from being dragged down with the corners");
+        });
+
+        ui.separator();
+        ui.heading("Black & White");
+
+        let mut bw_enabled = self.bw_enabled != 0;
+        ui.checkbox(&mut bw_enabled, "Convert to black & white");
+        self.bw_enabled = bw_enabled as u32;
+
+        if bw_enabled {
+            egui::ComboBox::from_label("Film Stock")
+                .selected_text("Apply preset")
+                .show_ui(ui, |ui| {
+                    for (name, weights) in BW_STOCKS {
+                        if ui.selectable_label(false, name).clicked() {
+                            self.bw_mixer = [weights[0], weights[1], weights[2], 0.0];
+                        }
+                    }
+                });
+            ui.add(egui::Slider::new(&mut self.bw_mixer[0], -1.0..=2.0).text("Red"));
+            ui.add(egui::Slider::new(&mut self.bw_mixer[1], -1.0..=2.0).text("Green"));
+            ui.add(egui::Slider::new(&mut self.bw_mixer[2], -1.0..=2.0).text("Blue"));
+            index_combo(ui, "Lens Filter", &mut self.bw_filter, &BW_FILTER_NAMES);
+            index_combo(ui, "Toning", &mut self.bw_toning, &BW_TONING_NAMES);
+            if self.bw_toning != 0 {
+                ui.add(egui::Slider::new(&mut self.bw_toning_amount, 0.0..=1.0).text("Toning Amount"));
+            }
+        }
+
+        ui.separator();
+        ui.heading("Colour Grading");
+
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut self.grading_mode, GRADING_WHEELS, "Wheels");
+            ui.radio_value(&mut self.grading_mode, GRADING_SPLIT_TONE, "Split Toning");
+        });
+
+        if self.grading_mode == GRADING_WHEELS {
+            ui.horizontal(|ui| {
+                grading_wheel(ui, "Shadows", &mut self.shadow_wheel);
+                grading_wheel(ui, "Midtones", &mut self.midtone_wheel);
+                grading_wheel(ui, "Highlights", &mut self.highlight_wheel);
+            });
+            ui.add(egui::Slider::new(&mut self.grading_balance, -1.0..=1.0).text("Balance"));
+        } else {
+            ui.add(egui::Slider::new(&mut self.split_shadow_hue, 0.0..=360.0).text("Shadow Hue"));
+            ui.add(egui::Slider::new(&mut self.split_shadow_saturation, 0.0..=1.0).text("Shadow Saturation"));
+            ui.add(egui::Slider::new(&mut self.split_highlight_hue, 0.0..=360.0).text("Highlight Hue"));
+            ui.add(egui::Slider::new(&mut self.split_highlight_saturation, 0.0..=1.0).text("Highlight Saturation"));
+            ui.add(egui::Slider::new(&mut self.split_balance, -1.0..=1.0).text("Balance"));
+        }
     }
-}
\ No newline at end of file
+}
@@ -1,32 +1,97 @@
 use eframe::wgpu;
 use eframe::wgpu::{ComputePipeline, Device};
-use crate::ViewportUniform::ViewportUniform;
-use crate::ImageControls::ImageControls;
+use crate::ViewportUniform::{ViewOptions, ViewportUniform};
+use crate::Geometry::GeometryUniform;
+use crate::AdjustmentStack::AdjustmentStackUniform;
+use crate::SpotHealing::{SpotGpu, SPOT_LIST_HEADER};
+use crate::ProcessingGraph::{ProcessingGraph, Stage, StageBypass, StageFrame};
+use crate::GpuLocalAdjustmentsPipeline::RangePicker;
+use crate::GpuReadback::GpuReadback;
+use crate::GpuHistogramPipeline::HISTOGRAM_WORKGROUP_PIXELS;
+use crate::GpuScopesPipeline::scope_workgroups;
+use crate::PixelProbeView::{PixelSample, MAX_PROBE_POINTS, PROBE_INPUT_OFFSET, PROBE_OUTPUT_OFFSET, PROBE_SLOT_BYTES, PROBE_WORKING_OFFSET};
+use std::sync::Mutex;
 
 pub struct ImageRenderResources {
     pub render_pipeline: wgpu::RenderPipeline,
     pub render_bind_group: wgpu::BindGroup,
 
-    pub compute_pipeline: ComputePipeline,
-    pub compute_bind_group: wgpu::BindGroup,
+    /// Geometry, detail, local and colour stages, see `ProcessingGraph`
+    pub graph: ProcessingGraph,
+    pub range_picker: RangePicker,
+
+    pub geometry_buffer: wgpu::Buffer,
+    /// Spot count then `MAX_HEAL_SPOTS` spots, see `SpotHealing`
+    pub spot_list_buffer: wgpu::Buffer,
+    pub spot_points_buffer: wgpu::Buffer,
+
+    pub histogram_pipeline: ComputePipeline,
+    pub histogram_bind_group: wgpu::BindGroup,
+    pub histogram_buffer: wgpu::Buffer,
+    pub histogram_readback: GpuReadback,
+
+    pub waveform_pipeline: ComputePipeline,
+    pub vectorscope_pipeline: ComputePipeline,
+    pub scopes_bind_group: wgpu::BindGroup,
+    pub waveform_buffer: wgpu::Buffer,
+    pub vectorscope_buffer: wgpu::Buffer,
+    pub waveform_readback: GpuReadback,
+    pub vectorscope_readback: GpuReadback,
 
     pub settings_buffer: wgpu::Buffer,
+    pub adjustment_stack_buffer: wgpu::Buffer,
     pub viewport_buffer: wgpu::Buffer,
     
+    /// Decoded source, kept so the rest can be rebuilt when the geometry changes size
+    pub raw_texture: wgpu::Texture,
+    /// Source after crop, rotation and flips, the colour pipeline's input
+    pub geometry_texture: wgpu::Texture,
+    pub working_texture: wgpu::Texture,
     pub processed_texture: wgpu::Texture,
 
+    /// `PROBE_SLOT_BYTES` per point, see `PixelProbeView`
+    pub probe_readback: GpuReadback,
+    /// Points copied into the probe readback that's in flight
+    pub probe_points: Mutex<Vec<[u32; 2]>>,
+
     pub width: i32,
     pub height: i32
 }
 
 impl ImageRenderResources {
+    /// Crop, rotation and flips for the next `prepare`
+    pub fn write_geometry(&self, queue: &wgpu::Queue, geometry: &GeometryUniform) {
+        queue.write_buffer(&self.geometry_buffer, 0, bytemuck::bytes_of(geometry));
+    }
+
+    /// Heal spots for the next `prepare`, already cut down to what the buffers hold
+    pub fn write_spots(&self, queue: &wgpu::Queue, spots: &[SpotGpu], points: &[[f32; 2]]) {
+        queue.write_buffer(&self.spot_list_buffer, 0, bytemuck::bytes_of(&[spots.len() as u32, 0, 0, 0]));
+        if !spots.is_empty() {
+            queue.write_buffer(&self.spot_list_buffer, SPOT_LIST_HEADER, bytemuck::cast_slice(spots));
+            queue.write_buffer(&self.spot_points_buffer, 0, bytemuck::cast_slice(points));
+        }
+    }
+
+    /// Stages to leave out from the next `prepare`
+    pub fn write_stage_bypass(&mut self, bypass: StageBypass) {
+        self.graph.set_bypass(bypass);
+    }
+
+    /// Order of the colour pass's adjustments for the next `prepare`
+    pub fn write_adjustment_stack(&self, queue: &wgpu::Queue, stack: &AdjustmentStackUniform) {
+        queue.write_buffer(&self.adjustment_stack_buffer, 0, bytemuck::bytes_of(stack));
+    }
+
+    /// The frame's settings are written on the way through the graph, see `StageFrame`
     pub fn prepare(
-        &self,
-        device: &Device,
-        queue: &wgpu::Queue,
-        controls: &ImageControls,
+        &mut self,
+        frame: &StageFrame,
         view_rect: egui::Rect,
+        view_options: &ViewOptions,
+        scopes_enabled: bool,
     ) {
+        let StageFrame { device, queue, controls, .. } = *frame;
         // --- COMPUTE PASS ---
         queue.write_buffer(
             &self.settings_buffer,
@@ -40,17 +105,65 @@ impl ImageRenderResources {
                 },
             );
 
-            {
-                let mut cpass = encoder.begin_compute_pass(&Default::default());
-                cpass.set_pipeline(&self.compute_pipeline);
-                cpass.set_bind_group(0, &self.compute_bind_group, &[]);
+            // --- PROCESSING GRAPH ---
+            self.graph.encode(&mut encoder, frame);
+
+            // --- HISTOGRAM PASS ---
+            // Only when the last result has been read, the readback buffer can't be
+            // written while it's mapped
+            let histogram_due = self.histogram_readback.is_idle();
+            if histogram_due {
+                encoder.clear_buffer(&self.histogram_buffer, 0, None);
+                {
+                    let mut hpass = encoder.begin_compute_pass(&Default::default());
+                    hpass.set_pipeline(&self.histogram_pipeline);
+                    hpass.set_bind_group(0, &self.histogram_bind_group, &[]);
+                    hpass.dispatch_workgroups(
+                        (self.width as u32).div_ceil(HISTOGRAM_WORKGROUP_PIXELS),
+                        (self.height as u32).div_ceil(HISTOGRAM_WORKGROUP_PIXELS),
+                        1,
+                    );
+                }
+                encoder.copy_buffer_to_buffer(
+                    &self.histogram_buffer,
+                    0,
+                    &self.histogram_readback.buffer,
+                    0,
+                    self.histogram_buffer.size(),
+                );
+            }
 
-                let gx = ((self.width + 15) / 16) as u32;
-                let gy = ((self.height + 15) / 16) as u32;
-                cpass.dispatch_workgroups(gx, gy, 1);
+            // --- SCOPES PASS ---
+            let scopes_due = scopes_enabled
+                && self.waveform_readback.is_idle()
+                && self.vectorscope_readback.is_idle();
+            if scopes_due {
+                encoder.clear_buffer(&self.waveform_buffer, 0, None);
+                encoder.clear_buffer(&self.vectorscope_buffer, 0, None);
+                {
+                    let (gx, gy) = scope_workgroups(self.width as u32, self.height as u32);
+                    let mut spass = encoder.begin_compute_pass(&Default::default());
+                    spass.set_bind_group(0, &self.scopes_bind_group, &[]);
+                    spass.set_pipeline(&self.waveform_pipeline);
+                    spass.dispatch_workgroups(gx, gy, 1);
+                    spass.set_pipeline(&self.vectorscope_pipeline);
+                    spass.dispatch_workgroups(gx, gy, 1);
+                }
+                encoder.copy_buffer_to_buffer(&self.waveform_buffer, 0, &self.waveform_readback.buffer, 0, self.waveform_buffer.size());
+                encoder.copy_buffer_to_buffer(&self.vectorscope_buffer, 0, &self.vectorscope_readback.buffer, 0, self.vectorscope_buffer.size());
             }
 
             queue.submit(Some(encoder.finish()));
+
+            if histogram_due {
+                self.histogram_readback.map();
+            }
+            if scopes_due {
+                self.waveform_readback.map();
+                self.vectorscope_readback.map();
+            }
+            // Let any finished readbacks fire their callbacks without blocking
+            device.poll(wgpu::PollType::Poll).ok();
         }
 
         // --- VIEWPORT UNIFORM UPDATE ---
@@ -63,6 +176,10 @@ impl ImageRenderResources {
             zoom: 0.5,
             _pad0: 0.0,
             pan: [0.0, 0.0],
+            overlay_flags: view_options.overlay_flags,
+            output_gamut: view_options.output_gamut,
+            compare_mode: view_options.compare_mode,
+            split_position: view_options.split_position,
         };
 
         queue.write_buffer(
@@ -72,6 +189,141 @@ impl ImageRenderResources {
         );
     }
 
+    /// Latest histogram bins if a readback has finished since the last call
+    pub fn take_histogram(&self) -> Option<Vec<u32>> {
+        self.histogram_readback.take_u32()
+    }
+
+    /// Copies the input, working and output texels at each point into the probe readback.
+    /// Skipped while the previous readings are still in flight, returns whether it was queued.
+    pub fn probe_pixels(&self, device: &Device, queue: &wgpu::Queue, probe_points: &[[u32; 2]]) -> bool {
+        if probe_points.is_empty() || !self.probe_readback.is_idle() {
+            return false;
+        }
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Pixel Probe Encoder"),
+        });
+        let points = &probe_points[..probe_points.len().min(MAX_PROBE_POINTS)];
+        for (i, &[x, y]) in points.iter().enumerate() {
+            let slot = i as u64 * PROBE_SLOT_BYTES;
+            for (texture, offset) in [
+                (&self.geometry_texture, PROBE_INPUT_OFFSET),
+                (&self.working_texture, PROBE_WORKING_OFFSET),
+                (&self.processed_texture, PROBE_OUTPUT_OFFSET),
+            ] {
+                encoder.copy_texture_to_buffer(
+                    wgpu::TexelCopyTextureInfo {
+                        texture,
+                        mip_level: 0,
+                        origin: wgpu::Origin3d { x, y, z: 0 },
+                        aspect: wgpu::TextureAspect::All,
+                    },
+                    wgpu::TexelCopyBufferInfo {
+                        buffer: &self.probe_readback.buffer,
+                        layout: wgpu::TexelCopyBufferLayout {
+                            offset: slot + offset,
+                            bytes_per_row: None,
+                            rows_per_image: None,
+                        },
+                    },
+                    wgpu::Extent3d {
+                        width: 1,
+                        height: 1,
+                        depth_or_array_layers: 1,
+                    },
+                );
+            }
+        }
+        *self.probe_points.lock().unwrap() = points.to_vec();
+
+        queue.submit(Some(encoder.finish()));
+        self.probe_readback.map();
+        device.poll(wgpu::PollType::Poll).ok();
+        true
+    }
+
+    /// Pixel probe readings if a readback has finished since the last call
+    pub fn take_probe(&self) -> Option<Vec<PixelSample>> {
+        let data = self.probe_readback.take()?;
+        let points = self.probe_points.lock().unwrap();
+        Some(
+            points
+                .iter()
+                .zip(data.chunks_exact(PROBE_SLOT_BYTES as usize))
+                .map(|(&coords, slot)| PixelSample::from_slot(coords, slot))
+                .collect(),
+        )
+    }
+
+    /// True while any scope, histogram or probe result is still on its way back from the GPU
+    pub fn readbacks_pending(&self) -> bool {
+        !self.probe_readback.is_idle()
+            || !self.histogram_readback.is_idle()
+            || !self.waveform_readback.is_idle()
+            || !self.vectorscope_readback.is_idle()
+    }
+
+    /// The colour the range masks compare against at a texel, what the local adjustments
+    /// stage reads through the output transform. Blocks like `read_processed_pixel`.
+    pub fn read_range_color(&self, device: &Device, queue: &wgpu::Queue, x: u32, y: u32) -> Option<[f32; 3]> {
+        self.range_picker.pick(device, queue, self.graph.input_of(Stage::LocalAdjustments), x, y)
+    }
+
+    /// Reads a single texel of the processed image back from the GPU. This blocks until
+    /// the copy has finished, so it's only meant for one-off picks, not every frame.
+    pub fn read_processed_pixel(
+        &self,
+        device: &Device,
+        queue: &wgpu::Queue,
+        x: u32,
+        y: u32,
+    ) -> Option<[u8; 4]> {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Pixel Readback Buffer"),
+            size: 4,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Pixel Readback Encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                texture: &self.processed_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x, y, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: None,
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+        queue.submit(Some(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            sender.send(result).ok();
+        });
+        device.poll(wgpu::PollType::wait_indefinitely()).ok()?;
+        receiver.recv().ok()?.ok()?;
+
+        let data = slice.get_mapped_range();
+        Some([data[0], data[1], data[2], data[3]])
+    }
+
     pub fn paint(&self, render_pass: &mut wgpu::RenderPass<'_>) {
         render_pass.set_pipeline(&self.render_pipeline);
         render_pass.set_bind_group(0, &self.render_bind_group, &[]);
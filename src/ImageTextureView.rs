@@ -1,10 +1,16 @@
 use eframe::egui_wgpu::Callback;
 use crate::ImagePaintCallback::ImagePaintCallback;
+use crate::ViewportUniform::{ViewOptions, COMPARE_OFF, COMPARE_ORIGINAL, COMPARE_SIDE_BY_SIDE, COMPARE_SPLIT,
+                             OUTPUT_GAMUT_NAMES, OVERLAY_GAMUT, OVERLAY_HIGHLIGHT_CLIPPING, OVERLAY_SHADOW_CLIPPING};
 
 pub struct ImageTextureView {
     pub last_rect: Option<egui::Rect>,
     pub image_width: f32,
     pub image_height: f32,
+    pub options: ViewOptions,
+    /// "Show original" is held down (toolbar button or backslash)
+    showing_original: bool,
+    dragging_divider: bool,
 }
 
 impl Default for ImageTextureView {
@@ -13,14 +19,163 @@ impl Default for ImageTextureView {
             last_rect: None,
             image_width: 1.0,
             image_height: 1.0,
+            options: ViewOptions::default(),
+            showing_original: false,
+            dragging_divider: false,
         }
     }
 }
 
 impl ImageTextureView {
-    pub fn ui(&mut self, ui: &mut egui::Ui) {
-        // Calculate desired size based on actual image aspect ratio
+    /// Options to render with this frame, with press-and-hold original applied
+    pub fn effective_options(&self) -> ViewOptions {
+        let mut options = self.options;
+        if self.showing_original {
+            options.compare_mode = COMPARE_ORIGINAL;
+        }
+        options
+    }
+
+    pub fn is_dragging_divider(&self) -> bool {
+        self.dragging_divider
+    }
+
+    /// Aspect ratio of what's drawn, side by side shows two copies of the image
+    fn display_aspect(&self) -> f32 {
         let image_aspect = self.image_width / self.image_height;
+        if self.effective_options().compare_mode == COMPARE_SIDE_BY_SIDE {
+            image_aspect * 2.0
+        } else {
+            image_aspect
+        }
+    }
+
+    /// Screen rect the image is drawn in. The paint callback fills `last_rect` and the vertex
+    /// shader letterboxes the image inside it, so mirror that here.
+    pub fn image_rect(&self) -> Option<egui::Rect> {
+        let rect = self.last_rect?;
+        let image_aspect = self.display_aspect();
+        let view_aspect = rect.width() / rect.height();
+
+        let size = if image_aspect > view_aspect {
+            egui::vec2(rect.width(), rect.width() / image_aspect)
+        } else {
+            egui::vec2(rect.height() * image_aspect, rect.height())
+        };
+        Some(egui::Rect::from_center_size(rect.center(), size))
+    }
+
+    /// Pixel coordinates under a screen position, None if it's outside the image
+    pub fn image_coords(&self, pos: egui::Pos2) -> Option<[u32; 2]> {
+        let rect = self.image_rect()?;
+        if !rect.contains(pos) {
+            return None;
+        }
+        let mut uv = (pos - rect.min) / rect.size();
+        if self.effective_options().compare_mode == COMPARE_SIDE_BY_SIDE {
+            // Either half maps onto the same image
+            uv.x = (uv.x * 2.0).fract();
+        }
+        let x = (uv.x * self.image_width).min(self.image_width - 1.0);
+        let y = (uv.y * self.image_height).min(self.image_height - 1.0);
+        Some([x as u32, y as u32])
+    }
+
+    /// Screen positions of an image pixel's centre, two of them in side by side
+    fn screen_positions(&self, [x, y]: [u32; 2]) -> Vec<egui::Pos2> {
+        let Some(rect) = self.image_rect() else {
+            return Vec::new();
+        };
+        let u = (x as f32 + 0.5) / self.image_width;
+        let v = (y as f32 + 0.5) / self.image_height;
+        let halves: &[f32] = if self.effective_options().compare_mode == COMPARE_SIDE_BY_SIDE {
+            &[0.0, 0.5]
+        } else {
+            &[0.0]
+        };
+        let width = rect.width() / halves.len() as f32;
+        halves
+            .iter()
+            .map(|offset| egui::pos2(rect.left() + rect.width() * offset + u * width, rect.top() + v * rect.height()))
+            .collect()
+    }
+
+    /// Numbered crosshairs for the pixel probe's pinned samples
+    pub fn paint_sample_markers(&self, ui: &egui::Ui, pinned: &[[u32; 2]]) {
+        let painter = ui.painter();
+        for (i, &coords) in pinned.iter().enumerate() {
+            for pos in self.screen_positions(coords) {
+                let stroke = egui::Stroke::new(1.5, egui::Color32::YELLOW);
+                painter.circle_stroke(pos, 5.0, egui::Stroke::new(3.0, egui::Color32::BLACK));
+                painter.circle_stroke(pos, 5.0, stroke);
+                painter.text(
+                    pos + egui::vec2(7.0, -7.0),
+                    egui::Align2::LEFT_BOTTOM,
+                    format!("{}", i + 1),
+                    egui::FontId::proportional(11.0),
+                    egui::Color32::YELLOW,
+                );
+            }
+        }
+    }
+
+    fn overlay_toggle(&mut self, ui: &mut egui::Ui, flag: u32, label: &str) {
+        let mut on = self.options.overlay_flags & flag != 0;
+        if ui.toggle_value(&mut on, label).changed() {
+            self.options.overlay_flags ^= flag;
+        }
+    }
+
+    fn toolbar_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            self.overlay_toggle(ui, OVERLAY_SHADOW_CLIPPING, "Shadow Clipping");
+            self.overlay_toggle(ui, OVERLAY_HIGHLIGHT_CLIPPING, "Highlight Clipping");
+            self.overlay_toggle(ui, OVERLAY_GAMUT, "Gamut Warning");
+            egui::ComboBox::from_id_salt("output_gamut")
+                .selected_text(OUTPUT_GAMUT_NAMES[self.options.output_gamut as usize])
+                .show_ui(ui, |ui| {
+                    for (i, name) in OUTPUT_GAMUT_NAMES.iter().enumerate() {
+                        ui.selectable_value(&mut self.options.output_gamut, i as u32, *name);
+                    }
+                });
+
+            ui.separator();
+
+            let compare_label = |mode: u32| match mode {
+                COMPARE_SPLIT => "Split",
+                COMPARE_SIDE_BY_SIDE => "Side by Side",
+                _ => "Compare Off",
+            };
+            egui::ComboBox::from_id_salt("compare_mode")
+                .selected_text(compare_label(self.options.compare_mode))
+                .show_ui(ui, |ui| {
+                    for mode in [COMPARE_OFF, COMPARE_SPLIT, COMPARE_SIDE_BY_SIDE] {
+                        ui.selectable_value(&mut self.options.compare_mode, mode, compare_label(mode));
+                    }
+                });
+
+            let hold = ui.button("Show Original")
+                .on_hover_text("Hold to see the unedited image (or hold \\)");
+            let key_held = ui.input(|i| i.key_down(egui::Key::Backslash)) && !ui.ctx().wants_keyboard_input();
+            self.showing_original = hold.is_pointer_button_down_on() || key_held;
+        });
+
+        // J toggles both clipping warnings, as in most raw editors
+        if ui.input(|i| i.key_pressed(egui::Key::J)) && !ui.ctx().wants_keyboard_input() {
+            let both = OVERLAY_SHADOW_CLIPPING | OVERLAY_HIGHLIGHT_CLIPPING;
+            if self.options.overlay_flags & both == both {
+                self.options.overlay_flags &= !both;
+            } else {
+                self.options.overlay_flags |= both;
+            }
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) -> egui::Response {
+        self.toolbar_ui(ui);
+
+        // Calculate desired size based on actual image aspect ratio
+        let image_aspect = self.display_aspect();
         let max_width = ui.available_width();
         let max_height = ui.available_height();
         
@@ -32,15 +187,42 @@ impl ImageTextureView {
             egui::vec2(max_width, max_width / image_aspect)
         };
 
-        let (rect, _response) = ui.allocate_at_least(desired_size, egui::Sense::hover());
+        let (rect, response) = ui.allocate_at_least(desired_size, egui::Sense::click_and_drag());
 
         self.last_rect = Some(rect);
 
+        // Split view divider can be dragged from anywhere close to it
+        if self.options.compare_mode == COMPARE_SPLIT
+            && let Some(image_rect) = self.image_rect()
+        {
+            let divider_x = image_rect.left() + image_rect.width() * self.options.split_position;
+            if response.drag_started() {
+                self.dragging_divider = response
+                    .interact_pointer_pos()
+                    .is_some_and(|p| (p.x - divider_x).abs() < 8.0);
+            }
+            if self.dragging_divider
+                && let Some(pos) = response.interact_pointer_pos()
+            {
+                self.options.split_position = ((pos.x - image_rect.left()) / image_rect.width()).clamp(0.0, 1.0);
+            }
+            if response.hovered()
+                && response.hover_pos().is_some_and(|p| (p.x - divider_x).abs() < 8.0)
+            {
+                ui.ctx().set_cursor_icon(egui::CursorIcon::ResizeHorizontal);
+            }
+        }
+        if response.drag_stopped() || !response.dragged() {
+            self.dragging_divider = false;
+        }
+
         let callback = Callback::new_paint_callback(
             rect,
             ImagePaintCallback,
         );
 
         ui.painter().add(callback);
+
+        response
     }
 }
\ No newline at end of file
@@ -0,0 +1,161 @@
+use crate::image_loader::LensInfo;
+use crate::Lensfun::{find_profile, database_present, LensProfile};
+
+/// Lens corrections applied in the geometry pass, before orientation and crop.
+/// Manual sliders add on top of the lensfun profile when one is in use.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct LensCorrection {
+    /// Radial distortion, positive corrects barrel and negative corrects pincushion
+    pub distortion: f32,
+    /// Higher-order term, mostly affects the corners
+    pub distortion_outer: f32,
+    /// Lateral chromatic aberration as a radius scale of red and blue against green
+    pub ca_red: f32,
+    pub ca_blue: f32,
+    /// Positive brightens the corners, negative darkens them like an old fast lens
+    pub vignetting: f32,
+    /// Scale up so distortion correction doesn't leave empty edges
+    pub fill_frame: bool,
+    pub use_profile: bool,
+    /// Looked up again on load, only the sliders are saved
+    #[serde(skip)]
+    pub profile: Option<LensProfile>,
+    /// What the file said about the lens, for the UI
+    #[serde(skip)]
+    pub lens_name: Option<String>,
+    /// Whether a lensfun database was installed when the image was loaded
+    #[serde(skip)]
+    pub database_found: bool,
+}
+
+impl Default for LensCorrection {
+    fn default() -> Self {
+        Self {
+            distortion: 0.0,
+            distortion_outer: 0.0,
+            ca_red: 1.0,
+            ca_blue: 1.0,
+            vignetting: 0.0,
+            fill_frame: true,
+            use_profile: true,
+            profile: None,
+            lens_name: None,
+            database_found: false,
+        }
+    }
+}
+
+/// Shader-side lens parameters, see GeometryUniform
+pub struct LensCoefficients {
+    /// Rd / Ru as a polynomial in Ru (half the shorter side = 1), constant term first
+    pub distortion: [f32; 5],
+    pub ca_red: f32,
+    pub ca_blue: f32,
+    /// lensfun "pa" vignetting (half the diagonal = 1), divided out
+    pub vignetting: [f32; 3],
+    /// Radius scale applied before distortion
+    pub scale: f32,
+}
+
+impl LensCorrection {
+    /// Corrections for a freshly loaded image, with the lensfun profile if one matches
+    pub fn for_lens(lens: Option<&LensInfo>) -> Self {
+        let lens_name = lens.map(|l| format!("{} {}", l.make, l.model).trim().to_string()).filter(|n| !n.is_empty());
+        Self {
+            profile: lens.and_then(find_profile),
+            lens_name,
+            database_found: database_present(),
+            ..Self::default()
+        }
+    }
+
+    /// Saved slider settings on top of the profile looked up for this image
+    pub fn restore(&mut self, saved: LensCorrection) {
+        *self = Self {
+            profile: self.profile.take(),
+            lens_name: self.lens_name.take(),
+            database_found: self.database_found,
+            ..saved
+        };
+    }
+
+    pub fn coefficients(&self, source: [u32; 2]) -> LensCoefficients {
+        let mut distortion = [1.0, 0.0, 0.0, 0.0, 0.0];
+        let mut ca = [self.ca_red, self.ca_blue];
+        let mut vignetting = [0.0; 3];
+
+        if let Some(profile) = self.profile.as_ref().filter(|_| self.use_profile) {
+            if let Some(model) = profile.distortion {
+                distortion = model.polynomial();
+            }
+            if let Some([red, blue]) = profile.tca {
+                ca = [ca[0] * red, ca[1] * blue];
+            }
+            if let Some(profile_vignetting) = profile.vignetting {
+                vignetting = profile_vignetting;
+            }
+        }
+
+        // Sliders are in the same terms as lensfun's poly5. Sampling further out than the
+        // output pixel undoes barrel, hence the sign flip so positive reads as "fix barrel".
+        distortion[2] -= self.distortion * 0.1;
+        distortion[4] -= self.distortion_outer * 0.1;
+        vignetting[0] -= self.vignetting * 0.5;
+
+        let scale = if self.fill_frame {
+            // Output corner, in units of half the shorter side
+            let [w, h] = [source[0] as f32, source[1] as f32];
+            let corner = w.hypot(h) / w.min(h);
+            let reach = |r: f32| {
+                let f = distortion[0] + r * (distortion[1] + r * (distortion[2] + r * (distortion[3] + r * distortion[4])));
+                f * ca[0].max(ca[1]).max(1.0)
+            };
+            // Shrink until the corner samples land back inside, a few rounds is plenty
+            let mut scale: f32 = 1.0;
+            for _ in 0..4 {
+                scale = (1.0 / reach(scale * corner)).min(1.0);
+            }
+            scale.max(0.5)
+        } else {
+            1.0
+        };
+
+        LensCoefficients {
+            distortion,
+            ca_red: ca[0],
+            ca_blue: ca[1],
+            vignetting,
+            scale,
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        match (&self.profile, &self.lens_name) {
+            (Some(profile), _) => {
+                ui.checkbox(&mut self.use_profile, format!("Profile: {}", profile.name));
+            }
+            (None, Some(name)) if self.database_found => {
+                ui.label(format!("No lensfun profile for {name}"));
+            }
+            (None, Some(name)) => {
+                ui.label(format!("Lens: {name}"))
+                    .on_hover_text("Install the lensfun database for automatic corrections");
+            }
+            (None, None) => {
+                ui.label("No lens information in this file");
+            }
+        }
+
+        ui.add(egui::Slider::new(&mut self.distortion, -1.0..=1.0).text("Distortion"));
+        ui.add(egui::Slider::new(&mut self.distortion_outer, -1.0..=1.0).text("Distortion (Corners)"));
+        ui.add(egui::Slider::new(&mut self.ca_red, 0.995..=1.005).text("Red / Cyan Fringe").fixed_decimals(4));
+        ui.add(egui::Slider::new(&mut self.ca_blue, 0.995..=1.005).text("Blue / Yellow Fringe").fixed_decimals(4));
+        ui.add(egui::Slider::new(&mut self.vignetting, -1.0..=1.0).text("Vignetting"));
+        ui.checkbox(&mut self.fill_frame, "Fill Frame");
+
+        if ui.button("Reset Lens").clicked() {
+            self.restore(Self::default());
+        }
+    }
+}
@@ -0,0 +1,353 @@
+use std::path::PathBuf;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use crate::image_loader::LensInfo;
+
+/// Radial distortion as lensfun models it, undistorted radius in, distorted radius out.
+/// Radii are relative to half the shorter image side.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DistortionModel {
+    /// Rd = Ru * (1 - k1 + k1 * Ru^2)
+    Poly3 { k1: f32 },
+    /// Rd = Ru * (1 + k1 * Ru^2 + k2 * Ru^4)
+    Poly5 { k1: f32, k2: f32 },
+    /// Rd = Ru * (a * Ru^3 + b * Ru^2 + c * Ru + 1 - a - b - c)
+    PtLens { a: f32, b: f32, c: f32 },
+}
+
+impl DistortionModel {
+    /// Coefficients of Rd / Ru as a polynomial in Ru, constant term first
+    pub fn polynomial(&self) -> [f32; 5] {
+        match *self {
+            DistortionModel::Poly3 { k1 } => [1.0 - k1, 0.0, k1, 0.0, 0.0],
+            DistortionModel::Poly5 { k1, k2 } => [1.0, 0.0, k1, 0.0, k2],
+            DistortionModel::PtLens { a, b, c } => [1.0 - a - b - c, c, b, a, 0.0],
+        }
+    }
+}
+
+/// Corrections for one lens at one focal length, straight from the lensfun database
+#[derive(Debug, Clone, PartialEq)]
+pub struct LensProfile {
+    pub name: String,
+    pub distortion: Option<DistortionModel>,
+    /// Red and blue radius scale against green
+    pub tca: Option<[f32; 2]>,
+    /// lensfun "pa" model k1..k3, radii relative to half the diagonal
+    pub vignetting: Option<[f32; 3]>,
+}
+
+#[derive(Default)]
+struct LensEntry {
+    maker: String,
+    models: Vec<String>,
+    distortion: Vec<(f32, DistortionModel)>,
+    tca: Vec<(f32, [f32; 2])>,
+    /// focal, aperture, distance, k1..k3
+    vignetting: Vec<(f32, f32, f32, [f32; 3])>,
+}
+
+/// Where lensfun keeps its XML files, user updates first
+fn database_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Some(home) = std::env::var_os("HOME") {
+        let home = PathBuf::from(home);
+        dirs.push(home.join(".local/share/lensfun/updates/version_1"));
+        dirs.push(home.join(".local/share/lensfun"));
+    }
+    for prefix in ["/usr/share", "/usr/local/share", "/opt/homebrew/share"] {
+        dirs.push(PathBuf::from(prefix).join("lensfun/version_1"));
+        dirs.push(PathBuf::from(prefix).join("lensfun"));
+    }
+    dirs
+}
+
+/// True if any lensfun XML files are installed
+pub fn database_present() -> bool {
+    database_dirs().iter().any(|dir| {
+        std::fs::read_dir(dir).is_ok_and(|mut entries| {
+            entries.any(|e| e.is_ok_and(|e| e.path().extension().is_some_and(|x| x == "xml")))
+        })
+    })
+}
+
+fn attribute(e: &BytesStart, name: &str) -> Option<f32> {
+    e.attributes()
+        .flatten()
+        .find(|a| a.key.as_ref() == name.as_bytes())?
+        .unescape_value()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+fn attribute_text(e: &BytesStart, name: &str) -> Option<String> {
+    let value = e.attributes().flatten().find(|a| a.key.as_ref() == name.as_bytes())?;
+    Some(value.unescape_value().ok()?.into_owned())
+}
+
+/// Reads the `<calibration>` children of a `<lens>` element
+fn read_calibration(e: &BytesStart, lens: &mut LensEntry) {
+    let Some(focal) = attribute(e, "focal") else {
+        return;
+    };
+    match e.name().as_ref() {
+        b"distortion" => {
+            let model = match attribute_text(e, "model").as_deref() {
+                Some("poly3") => Some(DistortionModel::Poly3 { k1: attribute(e, "k1").unwrap_or(0.0) }),
+                Some("poly5") => Some(DistortionModel::Poly5 {
+                    k1: attribute(e, "k1").unwrap_or(0.0),
+                    k2: attribute(e, "k2").unwrap_or(0.0),
+                }),
+                Some("ptlens") => Some(DistortionModel::PtLens {
+                    a: attribute(e, "a").unwrap_or(0.0),
+                    b: attribute(e, "b").unwrap_or(0.0),
+                    c: attribute(e, "c").unwrap_or(0.0),
+                }),
+                _ => None,
+            };
+            if let Some(model) = model {
+                lens.distortion.push((focal, model));
+            }
+        }
+        b"tca" => {
+            // Only the linear terms are used, "poly3" keeps them in vr / vb
+            let scales = match attribute_text(e, "model").as_deref() {
+                Some("linear") => Some([attribute(e, "kr").unwrap_or(1.0), attribute(e, "kb").unwrap_or(1.0)]),
+                Some("poly3") => Some([attribute(e, "vr").unwrap_or(1.0), attribute(e, "vb").unwrap_or(1.0)]),
+                _ => None,
+            };
+            if let Some(scales) = scales {
+                lens.tca.push((focal, scales));
+            }
+        }
+        b"vignetting" if attribute_text(e, "model").as_deref() == Some("pa") => {
+            lens.vignetting.push((
+                focal,
+                attribute(e, "aperture").unwrap_or(0.0),
+                attribute(e, "distance").unwrap_or(0.0),
+                [
+                    attribute(e, "k1").unwrap_or(0.0),
+                    attribute(e, "k2").unwrap_or(0.0),
+                    attribute(e, "k3").unwrap_or(0.0),
+                ],
+            ));
+        }
+        _ => {}
+    }
+}
+
+fn read_lenses(xml: &str) -> Vec<LensEntry> {
+    let mut reader = Reader::from_str(xml);
+    let mut lenses = Vec::new();
+    let mut current: Option<LensEntry> = None;
+    let mut text_target: Option<&'static str> = None;
+
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) => match e.name().as_ref() {
+                b"lens" => current = Some(LensEntry::default()),
+                b"maker" => text_target = Some("maker"),
+                b"model" => text_target = Some("model"),
+                _ => {
+                    if let Some(lens) = &mut current {
+                        read_calibration(&e, lens);
+                    }
+                }
+            },
+            Ok(Event::Empty(e)) => {
+                if let Some(lens) = &mut current {
+                    read_calibration(&e, lens);
+                }
+            }
+            Ok(Event::Text(t)) => {
+                if let (Some(target), Some(lens)) = (text_target, &mut current) {
+                    let text = t.unescape().map(|t| t.trim().to_string()).unwrap_or_default();
+                    match target {
+                        "maker" if lens.maker.is_empty() => lens.maker = text,
+                        "model" => lens.models.push(text),
+                        _ => {}
+                    }
+                }
+            }
+            Ok(Event::End(e)) => {
+                text_target = None;
+                if e.name().as_ref() == b"lens"
+                    && let Some(lens) = current.take()
+                {
+                    lenses.push(lens);
+                }
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+    }
+    lenses
+}
+
+/// Lowercase words with punctuation dropped and a break wherever letters run into digits,
+/// so "EF50mm f/1.8" and "EF 50mm F1.8" line up
+fn words(name: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    for c in name.to_lowercase().chars() {
+        let breaks = !(c.is_alphanumeric() || c == '.')
+            || (c.is_ascii_digit() && word.chars().last().is_some_and(|l| l.is_alphabetic()));
+        if breaks && !word.is_empty() {
+            words.push(std::mem::take(&mut word));
+        }
+        if c.is_alphanumeric() || c == '.' {
+            word.push(c);
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+/// Share of the database model's words that also appear in the EXIF name. The maker is
+/// left out since EXIF lens names often don't repeat it.
+fn match_score(exif_model: &str, maker: &str, model: &str) -> f32 {
+    let exif_words = words(exif_model);
+    let maker_words = words(maker);
+    let model_words: Vec<String> = words(model).into_iter().filter(|w| !maker_words.contains(w)).collect();
+    if model_words.is_empty() {
+        return 0.0;
+    }
+    let found = model_words.iter().filter(|w| exif_words.contains(w)).count();
+    found as f32 / model_words.len() as f32
+}
+
+fn nearest<T: Copy>(entries: &[(f32, T)], focal: f32) -> Option<T> {
+    entries
+        .iter()
+        .min_by(|a, b| (a.0 - focal).abs().total_cmp(&(b.0 - focal).abs()))
+        .map(|e| e.1)
+}
+
+/// Looks the lens up in the local lensfun database and picks the calibration nearest the
+/// shot's focal length (and aperture, for vignetting). None without a database or a match.
+pub fn find_profile(info: &LensInfo) -> Option<LensProfile> {
+    if info.model.is_empty() {
+        return None;
+    }
+
+    let mut best: Option<(f32, LensEntry, String)> = None;
+    for dir in database_dirs() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_none_or(|x| x != "xml") {
+                continue;
+            }
+            let Ok(xml) = std::fs::read_to_string(&path) else {
+                continue;
+            };
+            for lens in read_lenses(&xml) {
+                let Some((score, model)) = lens
+                    .models
+                    .iter()
+                    .map(|m| (match_score(&info.model, &lens.maker, m), m.clone()))
+                    .max_by(|a, b| a.0.total_cmp(&b.0))
+                else {
+                    continue;
+                };
+                if score > best.as_ref().map_or(0.8, |b| b.0) {
+                    best = Some((score, lens, model));
+                }
+            }
+        }
+        // User updates come first and take priority over the system copy
+        if best.as_ref().is_some_and(|b| b.0 >= 1.0) {
+            break;
+        }
+    }
+
+    let (_, lens, model) = best?;
+    let focal = info.focal_length;
+    let vignetting = lens
+        .vignetting
+        .iter()
+        .min_by(|a, b| {
+            let key = |v: &(f32, f32, f32, [f32; 3])| ((v.0 - focal).abs(), (v.1 - info.aperture).abs(), -v.2);
+            let (ka, kb) = (key(a), key(b));
+            ka.0.total_cmp(&kb.0).then(ka.1.total_cmp(&kb.1)).then(ka.2.total_cmp(&kb.2))
+        })
+        .map(|v| v.3);
+
+    Some(LensProfile {
+        name: if model.to_lowercase().starts_with(&lens.maker.to_lowercase()) {
+            model
+        } else {
+            format!("{} {}", lens.maker, model).trim().to_string()
+        },
+        distortion: nearest(&lens.distortion, focal),
+        tca: nearest(&lens.tca, focal),
+        vignetting,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XML: &str = r#"<lensdatabase>
+    <lens>
+        <maker>Canon</maker>
+        <model>Canon EF 50mm f/1.8 STM</model>
+        <mount>Canon EF</mount>
+        <calibration>
+            <distortion model="ptlens" focal="50" a="0.01" b="-0.02" c="0.03" />
+            <tca model="linear" focal="50" kr="1.0002" kb="0.9998" />
+            <vignetting model="pa" focal="50" aperture="1.8" distance="10" k1="-0.5" k2="0.1" k3="-0.05" />
+        </calibration>
+    </lens>
+    <lens>
+        <maker>Sigma</maker>
+        <model>30mm F1.4 DC HSM</model>
+        <calibration>
+            <distortion model="poly3" focal="30" k1="-0.01" />
+            <tca model="poly3" focal="30" vr="1.0001" vb="0.9999" />
+            <vignetting model="acm" focal="30" aperture="1.4" distance="10" k1="-0.3" />
+        </calibration>
+    </lens>
+</lensdatabase>"#;
+
+    #[test]
+    fn reads_lenses_and_their_calibrations() {
+        let lenses = read_lenses(XML);
+        assert_eq!(lenses.len(), 2);
+
+        let canon = &lenses[0];
+        assert_eq!(canon.maker, "Canon");
+        assert_eq!(canon.models, ["Canon EF 50mm f/1.8 STM"]);
+        assert_eq!(canon.distortion, [(50.0, DistortionModel::PtLens { a: 0.01, b: -0.02, c: 0.03 })]);
+        assert_eq!(canon.tca, [(50.0, [1.0002, 0.9998])]);
+        assert_eq!(canon.vignetting, [(50.0, 1.8, 10.0, [-0.5, 0.1, -0.05])]);
+
+        let sigma = &lenses[1];
+        assert_eq!(sigma.distortion, [(30.0, DistortionModel::Poly3 { k1: -0.01 })]);
+        assert_eq!(sigma.tca, [(30.0, [1.0001, 0.9999])]);
+        // Only the "pa" vignetting model is understood
+        assert!(sigma.vignetting.is_empty());
+    }
+
+    #[test]
+    fn exif_names_match_database_models() {
+        assert_eq!(words("EF50mm f/1.8"), ["ef", "50mm", "f", "1.8"]);
+        assert_eq!(match_score("EF50mm f/1.8 STM", "Canon", "Canon EF 50mm f/1.8 STM"), 1.0);
+        assert!(match_score("EF-S18-55mm f/3.5-5.6 IS STM", "Canon", "Canon EF 50mm f/1.8 STM") < 0.8);
+    }
+
+    #[test]
+    fn normalised_models_keep_the_short_edge() {
+        // Poly3 and PtLens are normalised so the edge of the shorter side stays put
+        for model in [DistortionModel::Poly3 { k1: 0.2 }, DistortionModel::PtLens { a: 0.01, b: -0.02, c: 0.03 }] {
+            let sum: f32 = model.polynomial().iter().sum();
+            assert!((sum - 1.0).abs() < 1e-6, "{model:?}");
+        }
+    }
+}
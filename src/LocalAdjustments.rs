@@ -0,0 +1,216 @@
+use serde::{Deserialize, Serialize};
+use crate::BrushMask::BrushStroke;
+use crate::Geometry::Geometry;
+use crate::ImageControls::rgb_to_hue;
+
+/// Layers the local adjustments shader has room for, must match local_adjustments.wgsl
+pub const MAX_LOCAL_ADJUSTMENTS: usize = 8;
+
+// Must match the mask kinds in local_adjustments.wgsl
+pub const MASK_LINEAR: u32 = 0;
+pub const MASK_RADIAL: u32 = 1;
+pub const MASK_BRUSH: u32 = 2;
+pub const MASK_FULL: u32 = 3;
+
+// Range mask bits in the layer flags, must match local_adjustments.wgsl
+pub const RANGE_LUMINANCE: u32 = 1;
+pub const RANGE_COLOR: u32 = 2;
+
+/// Where a local adjustment applies. Positions are in source pixels divided by the
+/// source's longer side, so masks stay on the picture through crops and turns and don't
+/// depend on its resolution.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MaskShape {
+    /// Full effect up to `start`, fading out to nothing at `end`
+    Linear { start: [f32; 2], end: [f32; 2] },
+    /// Full effect inside an ellipse with semi-axes `radius`, its first axis turned by
+    /// `angle` radians. `feather` is the share of the radius that fades out.
+    Radial { center: [f32; 2], radius: [f32; 2], angle: f32, feather: f32 },
+    /// Painted strokes in the order they were made. `refine` snaps the painted edge to
+    /// edges in the picture.
+    Brush { strokes: Vec<BrushStroke>, refine: bool },
+    /// The whole frame, for layers scoped by their range mask alone
+    Full,
+}
+
+impl MaskShape {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MaskShape::Linear { .. } => "Graduated",
+            MaskShape::Radial { .. } => "Radial",
+            MaskShape::Brush { .. } => "Brush",
+            MaskShape::Full => "Range",
+        }
+    }
+}
+
+/// Limits a layer to parts of the picture by their content, intersected with its shape.
+/// Judged on the display-referred colour after the global exposure, so it follows the
+/// global settings as they change.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RangeMask {
+    pub luminance: bool,
+    /// Luma from 0 (black) to 1 (white) with full effect
+    pub luminance_range: [f32; 2],
+    /// How far past either end the effect fades out, in luma
+    pub luminance_falloff: f32,
+    pub color: bool,
+    /// Centre of the hue range in degrees and how far either side of it has full effect
+    pub hue: f32,
+    pub hue_width: f32,
+    /// Centre of the saturation range and how far either side of it has full effect
+    pub saturation: f32,
+    pub saturation_width: f32,
+    /// Softness of the colour range edges, 0 (hard) to 1
+    pub color_falloff: f32,
+}
+
+impl Default for RangeMask {
+    fn default() -> Self {
+        Self {
+            luminance: false,
+            luminance_range: [0.0, 1.0],
+            luminance_falloff: 0.1,
+            color: false,
+            hue: 0.0,
+            hue_width: 20.0,
+            saturation: 0.5,
+            saturation_width: 0.5,
+            color_falloff: 0.5,
+        }
+    }
+}
+
+impl RangeMask {
+    /// Centres the colour range on a picked display colour
+    pub fn pick_color(&mut self, rgb: [f32; 3]) {
+        let max = rgb[0].max(rgb[1]).max(rgb[2]);
+        let min = rgb[0].min(rgb[1]).min(rgb[2]);
+        if let Some(hue) = rgb_to_hue(rgb) {
+            self.hue = hue;
+        }
+        self.saturation = if max > 1e-5 { (max - min) / max } else { 0.0 };
+        self.color = true;
+    }
+
+    fn flags(&self) -> u32 {
+        (if self.luminance { RANGE_LUMINANCE } else { 0 }) | (if self.color { RANGE_COLOR } else { 0 })
+    }
+}
+
+/// A set of adjustments scoped by a mask, added on top of the global controls
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LocalAdjustment {
+    pub enabled: bool,
+    pub invert: bool,
+    pub shape: MaskShape,
+    pub range: RangeMask,
+    /// Stops
+    pub exposure: f32,
+    pub contrast: f32,
+    pub saturation: f32,
+    /// Negative cools, positive warms
+    pub temperature: f32,
+}
+
+impl Default for LocalAdjustment {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            invert: false,
+            shape: MaskShape::Radial { center: [0.5, 0.5], radius: [0.2, 0.2], angle: 0.0, feather: 0.5 },
+            range: RangeMask::default(),
+            exposure: 0.0,
+            contrast: 0.0,
+            saturation: 0.0,
+            temperature: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LocalAdjustments {
+    pub layers: Vec<LocalAdjustment>,
+}
+
+// Mirrors `LocalLayer` in local_adjustments.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LocalLayerUniform {
+    pub shape: [f32; 4],  // linear: start, end. radial: centre, radius. Source pixels.
+    pub params: [f32; 4], // radial: cos and sin of the angle, feather, unused
+    pub flags: [u32; 4],  // mask kind, invert, brush: refine, range mask bits
+    pub adjust: [f32; 4], // exposure, contrast, saturation, temperature
+    pub luminance: [f32; 4], // range: low, high, falloff, colour falloff
+    pub color: [f32; 4],     // range: hue, hue width (degrees), saturation, saturation width
+}
+
+// Mirrors `LocalAdjustments` in local_adjustments.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LocalAdjustmentsUniform {
+    /// Output pixel -> source pixel, like the geometry pass
+    pub to_source_x: [f32; 4],
+    pub to_source_y: [f32; 4],
+    /// Layer count, layer to show as an overlay plus one (0 for none), unused, unused
+    pub info: [u32; 4],
+    pub layers: [LocalLayerUniform; MAX_LOCAL_ADJUSTMENTS],
+}
+
+impl LocalAdjustments {
+    /// Enabled layers with the shader slot each one gets, as (slot, index, layer)
+    pub fn slots(&self) -> impl Iterator<Item = (usize, usize, &LocalAdjustment)> {
+        self.layers
+            .iter()
+            .enumerate()
+            .filter(|(_, l)| l.enabled)
+            .take(MAX_LOCAL_ADJUSTMENTS)
+            .enumerate()
+            .map(|(slot, (index, layer))| (slot, index, layer))
+    }
+
+    /// Enabled layers for the compute shader. `overlay` is the index of a layer whose mask
+    /// should be tinted over the output.
+    pub fn uniform(&self, geometry: &Geometry, source: [u32; 2], full_frame: bool, overlay: Option<usize>) -> LocalAdjustmentsUniform {
+        let unit = source[0].max(source[1]) as f32;
+        let [to_source_x, to_source_y] = geometry.source_rows(source, full_frame);
+        let mut uniform = LocalAdjustmentsUniform {
+            to_source_x,
+            to_source_y,
+            info: [0; 4],
+            layers: [bytemuck::Zeroable::zeroed(); MAX_LOCAL_ADJUSTMENTS],
+        };
+
+        for (slot, index, layer) in self.slots() {
+            let (kind, shape, params, refine) = match &layer.shape {
+                MaskShape::Linear { start, end } => (MASK_LINEAR, [start[0], start[1], end[0], end[1]], [0.0; 4], false),
+                MaskShape::Radial { center, radius, angle, feather } => (
+                    MASK_RADIAL,
+                    [center[0], center[1], radius[0], radius[1]],
+                    [angle.cos(), angle.sin(), *feather, 0.0],
+                    false,
+                ),
+                // Rasterised into the brush mask texture, see BrushMask
+                MaskShape::Brush { refine, .. } => (MASK_BRUSH, [0.0; 4], [0.0; 4], *refine),
+                MaskShape::Full => (MASK_FULL, [0.0; 4], [0.0; 4], false),
+            };
+            let range = &layer.range;
+            uniform.layers[slot] = LocalLayerUniform {
+                shape: shape.map(|v| v * unit),
+                params,
+                flags: [kind, layer.invert as u32, refine as u32, range.flags()],
+                adjust: [layer.exposure, layer.contrast, layer.saturation, layer.temperature],
+                luminance: [range.luminance_range[0], range.luminance_range[1], range.luminance_falloff, range.color_falloff],
+                color: [range.hue, range.hue_width, range.saturation, range.saturation_width],
+            };
+            uniform.info[0] = slot as u32 + 1;
+            if overlay == Some(index) {
+                uniform.info[1] = slot as u32 + 1;
+            }
+        }
+        uniform
+    }
+}
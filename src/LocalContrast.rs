@@ -0,0 +1,100 @@
+/// Levels in the local contrast pyramid, the first at half size. Enough for clarity on a
+/// large RAW, smaller images just stop early.
+pub const PYRAMID_LEVELS: u32 = 7;
+
+/// Longest side of the copy the airlight is estimated from
+const AIRLIGHT_RESOLUTION: u32 = 512;
+
+// Mirrors `LocalContrastUniform` in local_contrast.wgsl and pyramid.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LocalContrastUniform {
+    pub airlight: [f32; 4], // haze colour, gamma encoded, unused
+    pub levels: [f32; 4],   // clarity level, dehaze level, pyramid levels, unused
+}
+
+/// Pyramid levels that exist for an image this size, the first level is half of it
+pub fn pyramid_levels(output: [u32; 2]) -> u32 {
+    let smallest = output[0].div_ceil(2).min(output[1].div_ceil(2)).max(1);
+    PYRAMID_LEVELS.min(smallest.ilog2() + 1)
+}
+
+/// Pyramid level whose texels are about `fraction` of the longest side. Level k texels
+/// cover 2^(k+1) pixels.
+fn level_for(output: [u32; 2], fraction: f32) -> f32 {
+    let pixels = output[0].max(output[1]) as f32 * fraction;
+    (pixels.max(2.0).log2().round() - 1.0).clamp(0.0, (pyramid_levels(output) - 1) as f32)
+}
+
+impl LocalContrastUniform {
+    pub fn new(airlight: [f32; 3], output: [u32; 2]) -> Self {
+        Self {
+            airlight: [airlight[0], airlight[1], airlight[2], 0.0].map(|c| c.powf(1.0 / 2.2)),
+            // Clarity works on shapes about a hundredth of the frame, dehaze looks at patches
+            // about twice that, like the dark channel patch of He et al.
+            levels: [level_for(output, 0.01), level_for(output, 0.02), pyramid_levels(output) as f32, 0.0],
+        }
+    }
+}
+
+/// Colour of the haze for dehaze, in linear light. Dark channel prior: haze-free
+/// areas nearly always have one dark channel, so the pixels whose darkest channel is
+/// brightest are mostly haze. The brightest of the top 0.1% of those stands in for it.
+pub fn estimate_airlight(pixels: &[[f32; 3]], width: u32, height: u32) -> [f32; 3] {
+    let step = width.max(height).div_ceil(AIRLIGHT_RESOLUTION).max(1);
+    let mut samples: Vec<[f32; 3]> = (0..height)
+        .step_by(step as usize)
+        .flat_map(|y| (0..width).step_by(step as usize).map(move |x| (y * width + x) as usize))
+        .map(|i| pixels[i])
+        .collect();
+    if samples.is_empty() {
+        return [1.0; 3];
+    }
+    let dark = |p: &[f32; 3]| p[0].min(p[1]).min(p[2]);
+    samples.sort_by(|a, b| dark(b).total_cmp(&dark(a)));
+    let luma = |p: &[f32; 3]| 0.299 * p[0] + 0.587 * p[1] + 0.114 * p[2];
+    let brightest = samples[..samples.len().div_ceil(1000)]
+        .iter()
+        .max_by(|a, b| luma(a).total_cmp(&luma(b)))
+        .copied()
+        .unwrap_or([1.0; 3]);
+    // Haze is never black, and a blown highlight shouldn't make it brighter than white
+    brightest.map(|c| c.clamp(0.05, 1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn airlight_is_the_haze_not_the_brightest_colour() {
+        let (width, height) = (100, 100);
+        let haze = [0.7, 0.75, 0.8];
+        // Saturated colours have a dark channel however bright they are, the haze doesn't
+        let pixels: Vec<[f32; 3]> = (0..width * height)
+            .map(|i| match i % 50 {
+                0 => haze,
+                1..=20 => [1.0, 1.0, 0.0],
+                _ => [0.1, 0.4, 0.05],
+            })
+            .collect();
+        assert_eq!(estimate_airlight(&pixels, width, height), haze);
+    }
+
+    #[test]
+    fn airlight_stays_between_dim_and_white() {
+        assert_eq!(estimate_airlight(&[[0.0; 3]; 16], 4, 4), [0.05; 3]);
+        assert_eq!(estimate_airlight(&[[3.0, 0.5, 2.0]; 16], 4, 4), [1.0, 0.5, 1.0]);
+        assert_eq!(estimate_airlight(&[], 0, 0), [1.0; 3]);
+    }
+
+    #[test]
+    fn large_images_are_sampled() {
+        // Only every other row and column is read, the haze on odd ones is never seen
+        let (width, height) = (AIRLIGHT_RESOLUTION * 2, 4);
+        let pixels: Vec<[f32; 3]> = (0..width * height)
+            .map(|i| if (i % width) % 2 == 1 { [0.9; 3] } else { [0.2, 0.3, 0.4] })
+            .collect();
+        assert_eq!(estimate_airlight(&pixels, width, height), [0.2, 0.3, 0.4]);
+    }
+}
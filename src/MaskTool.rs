@@ -0,0 +1,486 @@
+use egui::{Color32, Pos2, Rect, Shape, Stroke, Vec2};
+use crate::BrushMask::{BrushSettings, BrushStroke};
+use crate::Geometry::Geometry;
+use crate::LocalAdjustments::{LocalAdjustment, LocalAdjustments, MaskShape, RangeMask, MAX_LOCAL_ADJUSTMENTS};
+
+/// Pointer distance in points at which a mask handle can be grabbed
+const HANDLE_REACH: f32 = 10.0;
+
+/// Points around a radial mask outline
+const ELLIPSE_SEGMENTS: usize = 64;
+
+/// A new point is added to a brush stroke once the pointer has moved this share of the radius
+const STROKE_SPACING: f32 = 0.25;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum MaskHandle {
+    /// Centre of a radial mask or the middle of a graduated one
+    Move,
+    Start,
+    End,
+    RadiusX,
+    RadiusY,
+}
+
+#[derive(Debug, Clone)]
+struct MaskDrag {
+    handle: MaskHandle,
+    start_shape: MaskShape,
+    start_pos: [f32; 2],
+}
+
+/// Screen position <-> mask coordinates for the frame the viewer is showing
+pub(crate) struct FrameMapping<'a> {
+    geometry: &'a Geometry,
+    source: [u32; 2],
+    full_frame: bool,
+    pub frame: Rect,
+    pub output: [f32; 2],
+    pub unit: f32,
+}
+
+impl<'a> FrameMapping<'a> {
+    pub fn new(geometry: &'a Geometry, source: [u32; 2], full_frame: bool, frame: Rect) -> Self {
+        let [w, h] = geometry.output_size(source, full_frame);
+        Self {
+            geometry,
+            source,
+            full_frame,
+            frame,
+            output: [w as f32, h as f32],
+            unit: source[0].max(source[1]) as f32,
+        }
+    }
+
+    pub fn to_screen(&self, p: [f32; 2]) -> Pos2 {
+        let [x, y] = self.geometry.output_point(self.source, self.full_frame, [p[0] * self.unit, p[1] * self.unit]);
+        self.frame.min + Vec2::new(x / self.output[0] * self.frame.width(), y / self.output[1] * self.frame.height())
+    }
+
+    pub fn to_mask(&self, pos: Pos2) -> [f32; 2] {
+        let rel = pos - self.frame.min;
+        self.output_to_mask([rel.x / self.frame.width() * self.output[0], rel.y / self.frame.height() * self.output[1]])
+    }
+
+    /// From output pixels, for placing new masks upright in the current frame
+    fn output_to_mask(&self, p: [f32; 2]) -> [f32; 2] {
+        let [x, y] = self.geometry.source_point(self.source, self.full_frame, p);
+        [x / self.unit, y / self.unit]
+    }
+}
+
+pub(crate) fn add(a: [f32; 2], b: [f32; 2]) -> [f32; 2] {
+    [a[0] + b[0], a[1] + b[1]]
+}
+
+pub(crate) fn sub(a: [f32; 2], b: [f32; 2]) -> [f32; 2] {
+    [a[0] - b[0], a[1] - b[1]]
+}
+
+/// Point on a radial mask's outline, `t` in radians around it and `scale` of its radius
+fn ellipse_point(center: [f32; 2], radius: [f32; 2], angle: f32, t: f32, scale: f32) -> [f32; 2] {
+    let (sin, cos) = angle.sin_cos();
+    let (x, y) = (radius[0] * scale * t.cos(), radius[1] * scale * t.sin());
+    add(center, [cos * x - sin * y, sin * x + cos * y])
+}
+
+/// The shape's pin, where it's grabbed to move it and picked to select it. Brush masks
+/// are pinned at the start of their first stroke, an empty one has nowhere to pin.
+fn pin(shape: &MaskShape) -> Option<[f32; 2]> {
+    match shape {
+        MaskShape::Linear { start, end } => Some([(start[0] + end[0]) * 0.5, (start[1] + end[1]) * 0.5]),
+        MaskShape::Radial { center, .. } => Some(*center),
+        MaskShape::Brush { strokes, .. } => strokes.iter().find_map(|s| s.points.first().copied()),
+        MaskShape::Full => None,
+    }
+}
+
+/// Brush masks have no handles, dragging on the viewer paints them instead
+fn handles(shape: &MaskShape) -> Vec<(MaskHandle, [f32; 2])> {
+    match *shape {
+        MaskShape::Linear { start, end } => vec![
+            (MaskHandle::Move, [(start[0] + end[0]) * 0.5, (start[1] + end[1]) * 0.5]),
+            (MaskHandle::Start, start),
+            (MaskHandle::End, end),
+        ],
+        MaskShape::Radial { center, radius, angle, .. } => vec![
+            (MaskHandle::Move, center),
+            (MaskHandle::RadiusX, ellipse_point(center, radius, angle, 0.0, 1.0)),
+            (MaskHandle::RadiusY, ellipse_point(center, radius, angle, std::f32::consts::FRAC_PI_2, 1.0)),
+        ],
+        MaskShape::Brush { .. } | MaskShape::Full => Vec::new(),
+    }
+}
+
+/// Shape after dragging `handle` from `from` to `to`, both in mask coordinates
+fn dragged_shape(start: &MaskShape, handle: MaskHandle, from: [f32; 2], to: [f32; 2]) -> MaskShape {
+    let delta = sub(to, from);
+    match (start.clone(), handle) {
+        (MaskShape::Linear { start, end }, MaskHandle::Move) => MaskShape::Linear { start: add(start, delta), end: add(end, delta) },
+        (MaskShape::Linear { end, .. }, MaskHandle::Start) => MaskShape::Linear { start: to, end },
+        (MaskShape::Linear { start, .. }, MaskHandle::End) => MaskShape::Linear { start, end: to },
+        (MaskShape::Radial { center, radius, angle, feather }, _) => {
+            // Axis handles set that radius and turn the ellipse to follow the pointer
+            let [dx, dy] = sub(to, center);
+            let length = dx.hypot(dy).max(1e-3);
+            match handle {
+                MaskHandle::RadiusX => MaskShape::Radial { center, radius: [length, radius[1]], angle: dy.atan2(dx), feather },
+                MaskHandle::RadiusY => MaskShape::Radial {
+                    center,
+                    radius: [radius[0], length],
+                    angle: dy.atan2(dx) - std::f32::consts::FRAC_PI_2,
+                    feather,
+                },
+                _ => MaskShape::Radial { center: add(center, delta), radius, angle, feather },
+            }
+        }
+        (MaskShape::Brush { mut strokes, refine }, MaskHandle::Move) => {
+            for p in strokes.iter_mut().flat_map(|s| s.points.iter_mut()) {
+                *p = add(*p, delta);
+            }
+            MaskShape::Brush { strokes, refine }
+        }
+        (shape, _) => shape,
+    }
+}
+
+/// Graduated, radial and brush masks for local adjustments, edited with handles on the
+/// viewer or painted onto it
+#[derive(Default)]
+pub struct MaskTool {
+    pub active: bool,
+    pub selected: Option<usize>,
+    /// Tint the selected mask over the image
+    pub show_overlay: bool,
+    pub brush: BrushSettings,
+    /// The next click on the viewer picks the selected layer's colour range
+    pub picking_color: bool,
+    drag: Option<MaskDrag>,
+    /// A stroke is being painted into the selected layer
+    painting: bool,
+}
+
+impl MaskTool {
+    /// Layer whose mask the compute shader should tint, if any. Hidden while picking a
+    /// colour, the eyedropper reads the processed image.
+    pub fn overlay(&self) -> Option<usize> {
+        self.selected.filter(|_| self.active && self.show_overlay && !self.picking_color)
+    }
+
+    /// Local adjustments section for the side panel
+    pub fn panel_ui(&mut self, ui: &mut egui::Ui, local: &mut LocalAdjustments, geometry: &Geometry, source: [u32; 2], full_frame: bool) {
+        let [w, h] = geometry.output_size(source, full_frame);
+        let (w, h) = (w as f32, h as f32);
+        let mapping = FrameMapping::new(geometry, source, full_frame, Rect::from_min_size(Pos2::ZERO, Vec2::new(w, h)));
+
+        ui.horizontal(|ui| {
+            if ui.selectable_label(self.active, "Edit Masks").clicked() {
+                self.active = !self.active;
+            }
+            let room = local.layers.len() < MAX_LOCAL_ADJUSTMENTS;
+            let mut added = None;
+            if ui.add_enabled(room, egui::Button::new("+ Graduated")).clicked() {
+                // Sky-style: full strength at the top fading out by the middle
+                added = Some(MaskShape::Linear {
+                    start: mapping.output_to_mask([w * 0.5, h * 0.15]),
+                    end: mapping.output_to_mask([w * 0.5, h * 0.5]),
+                });
+            }
+            if ui.add_enabled(room, egui::Button::new("+ Radial")).clicked() {
+                let center = mapping.output_to_mask([w * 0.5, h * 0.5]);
+                // Line the ellipse up with the frame as it's shown
+                let [dx, dy] = sub(mapping.output_to_mask([w * 0.5 + 1.0, h * 0.5]), center);
+                let radius = w.min(h) * 0.25 / mapping.unit;
+                added = Some(MaskShape::Radial { center, radius: [radius, radius], angle: dy.atan2(dx), feather: 0.5 });
+            }
+            if ui.add_enabled(room, egui::Button::new("+ Brush")).clicked() {
+                added = Some(MaskShape::Brush { strokes: Vec::new(), refine: false });
+                self.show_overlay = true;
+            }
+            if ui.add_enabled(room, egui::Button::new("+ Range")).on_hover_text("Whole frame, limited by luminance or colour").clicked() {
+                added = Some(MaskShape::Full);
+                self.show_overlay = true;
+            }
+            if let Some(shape) = added {
+                // A frame-wide layer does nothing until it's narrowed down
+                let range = RangeMask { luminance: shape == MaskShape::Full, ..RangeMask::default() };
+                local.layers.push(LocalAdjustment { shape, range, ..LocalAdjustment::default() });
+                self.selected = Some(local.layers.len() - 1);
+                self.active = true;
+            }
+        });
+        ui.checkbox(&mut self.show_overlay, "Show Mask");
+
+        let mut removed = None;
+        for (i, layer) in local.layers.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut layer.enabled, "");
+                if ui.selectable_label(self.selected == Some(i), format!("{} {}", layer.shape.name(), i + 1)).clicked() {
+                    self.selected = if self.selected == Some(i) { None } else { Some(i) };
+                }
+                if ui.small_button("✕").clicked() {
+                    removed = Some(i);
+                }
+            });
+        }
+        if let Some(i) = removed {
+            local.layers.remove(i);
+            self.selected = match self.selected {
+                Some(s) if s == i => None,
+                Some(s) if s > i => Some(s - 1),
+                s => s,
+            };
+        }
+
+        if let Some(layer) = self.selected.and_then(|i| local.layers.get_mut(i)) {
+            ui.separator();
+            ui.add(egui::Slider::new(&mut layer.exposure, -3.0..=3.0).text("Exposure"));
+            ui.add(egui::Slider::new(&mut layer.contrast, -1.0..=1.0).text("Contrast"));
+            ui.add(egui::Slider::new(&mut layer.saturation, -1.0..=1.0).text("Saturation"));
+            ui.add(egui::Slider::new(&mut layer.temperature, -1.0..=1.0).text("Temperature"));
+            if let MaskShape::Radial { feather, .. } = &mut layer.shape {
+                ui.add(egui::Slider::new(feather, 0.0..=1.0).text("Feather"));
+            }
+            if layer.shape != MaskShape::Full {
+                ui.checkbox(&mut layer.invert, "Invert Mask");
+            }
+            self.range_ui(ui, &mut layer.range);
+            if let MaskShape::Brush { strokes, refine } = &mut layer.shape {
+                ui.separator();
+                ui.label("Brush (hold Alt to erase)");
+                ui.add(egui::Slider::new(&mut self.brush.radius, 0.002..=0.25).logarithmic(true).text("Size"));
+                ui.add(egui::Slider::new(&mut self.brush.feather, 0.0..=1.0).text("Feather"));
+                ui.add(egui::Slider::new(&mut self.brush.flow, 0.05..=1.0).text("Flow"));
+                ui.checkbox(&mut self.brush.erase, "Erase");
+                ui.checkbox(refine, "Refine Edges")
+                    .on_hover_text("Snap the painted edge to edges in the picture");
+                ui.horizontal(|ui| {
+                    if ui.add_enabled(!strokes.is_empty(), egui::Button::new("Undo Stroke")).clicked() {
+                        strokes.pop();
+                    }
+                    if ui.add_enabled(!strokes.is_empty(), egui::Button::new("Clear")).clicked() {
+                        strokes.clear();
+                    }
+                });
+            }
+        }
+    }
+
+    /// Luminance and colour limits for the selected layer
+    fn range_ui(&mut self, ui: &mut egui::Ui, range: &mut RangeMask) {
+        egui::CollapsingHeader::new("Range Mask").id_salt("local_range_mask").default_open(range.luminance || range.color).show(ui, |ui| {
+            ui.checkbox(&mut range.luminance, "Luminance");
+            ui.add_enabled_ui(range.luminance, |ui| {
+                let [low, high] = &mut range.luminance_range;
+                ui.add(egui::Slider::new(low, 0.0..=1.0).text("From"));
+                ui.add(egui::Slider::new(high, 0.0..=1.0).text("To"));
+                *high = high.max(*low);
+                ui.add(egui::Slider::new(&mut range.luminance_falloff, 0.0..=0.5).text("Falloff"));
+            });
+
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut range.color, "Colour");
+                if ui.selectable_label(self.picking_color, "Pick").on_hover_text("Click the image to pick the colour").clicked() {
+                    self.picking_color = !self.picking_color;
+                }
+            });
+            ui.add_enabled_ui(range.color, |ui| {
+                ui.add(egui::Slider::new(&mut range.hue, 0.0..=360.0).text("Hue"));
+                ui.add(egui::Slider::new(&mut range.hue_width, 0.0..=180.0).text("Hue Range"));
+                ui.add(egui::Slider::new(&mut range.saturation, 0.0..=1.0).text("Saturation"));
+                ui.add(egui::Slider::new(&mut range.saturation_width, 0.0..=1.0).text("Saturation Range"));
+                ui.add(egui::Slider::new(&mut range.color_falloff, 0.0..=1.0).text("Softness"));
+            });
+        });
+    }
+
+    /// Takes a colour picked on the viewer into the selected layer's range
+    pub fn pick_color(&mut self, local: &mut LocalAdjustments, rgb: [f32; 3]) {
+        if let Some(layer) = self.selected.and_then(|i| local.layers.get_mut(i)) {
+            layer.range.pick_color(rgb);
+        }
+        self.picking_color = false;
+    }
+
+    /// Mask handles and interaction on top of the viewer. `frame` is the screen rect of the
+    /// cropped image, the masks aren't edited while the crop tool is open.
+    pub fn ui(&mut self, ui: &egui::Ui, response: &egui::Response, frame: Rect, local: &mut LocalAdjustments, geometry: &Geometry, source: [u32; 2]) {
+        if !self.active {
+            return;
+        }
+        let mapping = FrameMapping::new(geometry, source, false, frame);
+        let pointer = response.interact_pointer_pos();
+        if self.picking_color && self.selected.is_some() {
+            // The click itself is read back by the caller, see pick_color
+            if response.hovered() {
+                ui.ctx().set_cursor_icon(egui::CursorIcon::Crosshair);
+            }
+            self.paint(ui, &mapping, local);
+            return;
+        }
+
+        // A selected brush layer takes every drag on the viewer as a stroke
+        if let Some(MaskShape::Brush { strokes, .. }) = self.selected.and_then(|i| local.layers.get_mut(i)).map(|l| &mut l.shape) {
+            if response.drag_started()
+                && let Some(pos) = pointer
+            {
+                let erase = self.brush.erase != ui.input(|i| i.modifiers.alt);
+                strokes.push(BrushStroke {
+                    points: vec![mapping.to_mask(pos)],
+                    radius: self.brush.radius,
+                    feather: self.brush.feather,
+                    flow: self.brush.flow,
+                    erase,
+                });
+                self.painting = true;
+            }
+            if self.painting
+                && response.dragged()
+                && let (Some(pos), Some(stroke)) = (pointer, strokes.last_mut())
+            {
+                let p = mapping.to_mask(pos);
+                let last = stroke.points.last().copied().unwrap_or(p);
+                let [dx, dy] = sub(p, last);
+                if dx.hypot(dy) > stroke.radius * STROKE_SPACING {
+                    stroke.points.push(p);
+                }
+            }
+            if response.drag_stopped() {
+                self.painting = false;
+            }
+            self.paint(ui, &mapping, local);
+            if let Some(pos) = response.hover_pos() {
+                self.paint_brush_cursor(ui, &mapping, pos);
+            }
+            return;
+        }
+        self.painting = false;
+
+        let handle_at = |pos: Pos2, shape: &MaskShape| {
+            handles(shape)
+                .into_iter()
+                .map(|(handle, p)| (handle, mapping.to_screen(p).distance(pos)))
+                .filter(|(_, d)| *d < HANDLE_REACH)
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(handle, _)| handle)
+        };
+
+        if response.drag_started()
+            && let Some(pos) = pointer
+        {
+            // The selected layer's handles win, otherwise grabbing another pin selects it
+            let selected = self.selected.and_then(|i| Some((i, handle_at(pos, &local.layers.get(i)?.shape)?)));
+            let picked = selected.or_else(|| {
+                local.layers.iter().enumerate().find_map(|(i, l)| {
+                    let pin = mapping.to_screen(pin(&l.shape)?);
+                    (pin.distance(pos) < HANDLE_REACH).then_some((i, MaskHandle::Move))
+                })
+            });
+            self.drag = picked.map(|(i, handle)| {
+                self.selected = Some(i);
+                MaskDrag { handle, start_shape: local.layers[i].shape.clone(), start_pos: mapping.to_mask(pos) }
+            });
+        }
+        if let (Some(drag), Some(pos), Some(layer)) = (&self.drag, pointer, self.selected.and_then(|i| local.layers.get_mut(i)))
+            && response.dragged()
+        {
+            layer.shape = dragged_shape(&drag.start_shape, drag.handle, drag.start_pos, mapping.to_mask(pos));
+        }
+        if response.drag_stopped() {
+            self.drag = None;
+        }
+        if response.clicked()
+            && let Some(pos) = pointer
+        {
+            self.selected = local
+                .layers
+                .iter()
+                .position(|l| pin(&l.shape).is_some_and(|p| mapping.to_screen(p).distance(pos) < HANDLE_REACH));
+        }
+
+        let hovering = self.drag.is_some()
+            || response.hover_pos().is_some_and(|pos| {
+                local.layers.iter().any(|l| pin(&l.shape).is_some_and(|p| mapping.to_screen(p).distance(pos) < HANDLE_REACH))
+                    || self.selected.and_then(|i| local.layers.get(i)).is_some_and(|l| handle_at(pos, &l.shape).is_some())
+            });
+        if hovering {
+            ui.ctx().set_cursor_icon(egui::CursorIcon::Grab);
+        }
+
+        self.paint(ui, &mapping, local);
+    }
+
+    fn paint(&self, ui: &egui::Ui, mapping: &FrameMapping, local: &LocalAdjustments) {
+        let painter = ui.painter_at(mapping.frame);
+        let outline = |points: Vec<Pos2>, closed: bool, width: f32| {
+            let shadow = Stroke::new(width + 1.5, Color32::from_black_alpha(160));
+            let line = Stroke::new(width, Color32::WHITE);
+            if closed {
+                painter.add(Shape::closed_line(points.clone(), shadow));
+                painter.add(Shape::closed_line(points, line));
+            } else {
+                painter.add(Shape::line(points.clone(), shadow));
+                painter.add(Shape::line(points, line));
+            }
+        };
+
+        for (i, layer) in local.layers.iter().enumerate() {
+            let selected = self.selected == Some(i);
+            let fill = if selected { Color32::from_rgb(255, 200, 0) } else { Color32::from_gray(200) };
+            if let Some(p) = pin(&layer.shape) {
+                painter.circle(mapping.to_screen(p), 5.0, fill, Stroke::new(1.5, Color32::BLACK));
+            }
+            if !selected {
+                continue;
+            }
+
+            match layer.shape {
+                MaskShape::Linear { start, end } => {
+                    let (s, e) = (mapping.to_screen(start), mapping.to_screen(end));
+                    // Lines across the frame, square to the gradient as it's shown
+                    let across = (e - s).normalized().rot90() * mapping.frame.size().length();
+                    outline(vec![s - across, s + across], false, 1.5);
+                    outline(vec![e - across, e + across], false, 1.0);
+                    outline(vec![s, e], false, 1.0);
+                }
+                MaskShape::Radial { center, radius, angle, feather } => {
+                    let ring = |scale: f32| {
+                        (0..ELLIPSE_SEGMENTS)
+                            .map(|k| {
+                                let t = k as f32 / ELLIPSE_SEGMENTS as f32 * std::f32::consts::TAU;
+                                mapping.to_screen(ellipse_point(center, radius, angle, t, scale))
+                            })
+                            .collect::<Vec<_>>()
+                    };
+                    outline(ring(1.0), true, 1.5);
+                    if feather > 0.0 {
+                        outline(ring(1.0 - feather), true, 1.0);
+                    }
+                }
+                // Shown by the mask overlay rather than an outline
+                MaskShape::Brush { .. } | MaskShape::Full => {}
+            }
+
+            for (handle, p) in handles(&layer.shape) {
+                if handle != MaskHandle::Move {
+                    painter.circle(mapping.to_screen(p), 4.0, Color32::WHITE, Stroke::new(1.5, Color32::BLACK));
+                }
+            }
+        }
+    }
+
+    /// Outer edge of the brush and where its feather starts, at the pointer
+    fn paint_brush_cursor(&self, ui: &egui::Ui, mapping: &FrameMapping, pos: Pos2) {
+        let painter = ui.painter_at(mapping.frame);
+        let radius = self.brush.radius * mapping.unit / mapping.output[0] * mapping.frame.width();
+        let erase = self.brush.erase != ui.input(|i| i.modifiers.alt);
+        let color = if erase { Color32::from_rgb(255, 120, 120) } else { Color32::WHITE };
+        for (r, width) in [(radius, 1.5), (radius * (1.0 - self.brush.feather), 1.0)] {
+            if r > 1.0 {
+                painter.circle_stroke(pos, r, Stroke::new(width + 1.5, Color32::from_black_alpha(160)));
+                painter.circle_stroke(pos, r, Stroke::new(width, color));
+            }
+        }
+        ui.ctx().set_cursor_icon(egui::CursorIcon::Crosshair);
+    }
+}
@@ -0,0 +1,78 @@
+use serde::{Deserialize, Serialize};
+
+/// Smoothing passes, each twice the spacing of the last. Luma noise is fine grained so only
+/// the first few touch it, colour noise comes in blotches and gets all of them.
+pub const DENOISE_LEVELS: usize = 5;
+const LUMA_LEVELS: usize = 3;
+
+/// Luminance and colour noise reduction, run on the framed image before anything else so
+/// the edit (and any grain added on top) starts from a clean picture
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NoiseReduction {
+    /// 0 (off) to 1
+    pub luminance: f32,
+    /// 0 (smoothest) to 1, how much fine texture survives the luminance smoothing
+    pub luminance_detail: f32,
+    /// 0 (off) to 1
+    pub color: f32,
+    /// 0 to 1, how much colour detail survives the colour smoothing
+    pub color_detail: f32,
+}
+
+impl Default for NoiseReduction {
+    fn default() -> Self {
+        Self { luminance: 0.0, luminance_detail: 0.5, color: 0.0, color_detail: 0.5 }
+    }
+}
+
+// Mirrors `DenoiseUniform` in denoise.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DenoiseUniform {
+    pub strength: [f32; 4], // luma sigma, colour sigma, luma detail kept, colour detail kept
+    pub step: i32,          // tap spacing in pixels
+    pub filter_luma: u32,   // whether this pass smooths the luma
+    pub _pad: [u32; 2],
+}
+
+impl NoiseReduction {
+    pub fn is_active(&self) -> bool {
+        self.luminance > 0.0 || self.color > 0.0
+    }
+
+    /// One uniform per smoothing pass. Edges are told apart from noise by how different
+    /// neighbours are, and the noise left to find shrinks with every pass.
+    pub fn uniforms(&self) -> [DenoiseUniform; DENOISE_LEVELS] {
+        std::array::from_fn(|level| DenoiseUniform {
+            strength: [
+                0.12 * self.luminance * 0.6f32.powi(level as i32),
+                0.2 * self.color * 0.75f32.powi(level as i32),
+                0.6 * self.luminance_detail,
+                0.6 * self.color_detail,
+            ],
+            step: 1 << level,
+            filter_luma: (level < LUMA_LEVELS && self.luminance > 0.0) as u32,
+            _pad: [0; 2],
+        })
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.add(egui::Slider::new(&mut self.luminance, 0.0..=1.0).text("Luminance"))
+            .on_hover_text("Smooths grainy brightness noise");
+        ui.add_enabled_ui(self.luminance > 0.0, |ui| {
+            ui.add(egui::Slider::new(&mut self.luminance_detail, 0.0..=1.0).text("Detail"))
+                .on_hover_text("Keeps fine texture at the cost of leaving some noise");
+        });
+        ui.add(egui::Slider::new(&mut self.color, 0.0..=1.0).text("Colour"))
+            .on_hover_text("Removes coloured speckles and blotches");
+        ui.add_enabled_ui(self.color > 0.0, |ui| {
+            ui.add(egui::Slider::new(&mut self.color_detail, 0.0..=1.0).text("Colour Detail"))
+                .on_hover_text("Keeps small coloured details from bleeding into their surroundings");
+        });
+
+        if ui.button("Reset Noise Reduction").clicked() {
+            *self = Self::default();
+        }
+    }
+}
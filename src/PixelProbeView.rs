@@ -0,0 +1,196 @@
+use egui::{Color32, RichText};
+use crate::ImageControls::ImageControls;
+
+/// Size of one probe slot in the readback buffer: an Rgba32Float input texel, an Rgba16Float
+/// working texel and an Rgba8Unorm output texel, each at an offset its texel size divides.
+pub const PROBE_SLOT_BYTES: u64 = 32;
+pub const PROBE_INPUT_OFFSET: u64 = 0;
+pub const PROBE_WORKING_OFFSET: u64 = 16;
+pub const PROBE_OUTPUT_OFFSET: u64 = 24;
+
+/// Hover point plus up to this many pinned samples
+pub const MAX_PINNED_SAMPLES: usize = 8;
+pub const MAX_PROBE_POINTS: usize = MAX_PINNED_SAMPLES + 1;
+
+/// One pixel as it appears at each point of the pipeline
+#[derive(Debug, Copy, Clone)]
+pub struct PixelSample {
+    pub coords: [u32; 2],
+    /// Scene-linear values from the decoder, after crop and rotation
+    pub input: [f32; 3],
+    /// Linear values after all edits, before the output clamp
+    pub working: [f32; 3],
+    /// Display encoded 8-bit values as shown and exported
+    pub output: [u8; 3],
+}
+
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        31 if mantissa == 0.0 => sign * f32::INFINITY,
+        31 => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+impl PixelSample {
+    /// Decodes one `PROBE_SLOT_BYTES` slot of the probe readback
+    pub fn from_slot(coords: [u32; 2], slot: &[u8]) -> Self {
+        let f32_at = |offset: usize| {
+            f32::from_ne_bytes([slot[offset], slot[offset + 1], slot[offset + 2], slot[offset + 3]])
+        };
+        let f16_at = |offset: usize| f16_to_f32(u16::from_ne_bytes([slot[offset], slot[offset + 1]]));
+
+        let input = PROBE_INPUT_OFFSET as usize;
+        let working = PROBE_WORKING_OFFSET as usize;
+        let output = PROBE_OUTPUT_OFFSET as usize;
+        Self {
+            coords,
+            input: [f32_at(input), f32_at(input + 4), f32_at(input + 8)],
+            working: [f16_at(working), f16_at(working + 2), f16_at(working + 4)],
+            output: [slot[output], slot[output + 1], slot[output + 2]],
+        }
+    }
+
+    /// Output values taken back to linear light, with the same 2.2 gamma the compute shader uses
+    fn output_linear(&self) -> [f32; 3] {
+        self.output.map(|c| (c as f32 / 255.0).powf(2.2))
+    }
+}
+
+/// CIE L*a*b* (D65) of a linear sRGB colour
+pub fn linear_srgb_to_lab([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let x = 0.4124 * r + 0.3576 * g + 0.1805 * b;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = 0.0193 * r + 0.1192 * g + 0.9505 * b;
+
+    let f = |t: f32| {
+        if t > 216.0 / 24389.0 {
+            t.cbrt()
+        } else {
+            (24389.0 / 27.0 * t + 16.0) / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x / 0.95047), f(y), f(z / 1.08883));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+fn rgb_text([r, g, b]: [f32; 3]) -> String {
+    format!("{r:.4} {g:.4} {b:.4}")
+}
+
+fn lab_text([l, a, b]: [f32; 3]) -> String {
+    format!("L {l:.1} a {a:.1} b {b:.1}")
+}
+
+/// Status bar readout of the pixel under the cursor, plus a list of pinned sample points.
+/// Values are read back from the GPU textures by `ImageRenderResources`.
+#[derive(Default)]
+pub struct PixelProbeView {
+    /// Image pixel currently under the cursor
+    pub hover: Option<[u32; 2]>,
+    pub pinned: Vec<[u32; 2]>,
+    samples: Vec<PixelSample>,
+    /// Last hover reading, kept so the bar doesn't flicker while the next one is in flight
+    hover_sample: Option<PixelSample>,
+    /// Points and settings of the last readback, nothing needs reading until either changes
+    last_request: Option<(Vec<[u32; 2]>, ImageControls)>,
+}
+
+impl PixelProbeView {
+    /// Points to read this frame, hover first. None when the last readings are still current.
+    pub fn due_points(&self, controls: &ImageControls) -> Option<Vec<[u32; 2]>> {
+        let points: Vec<[u32; 2]> = self.hover.iter().chain(self.pinned.iter()).copied().collect();
+        let current = self.last_request.as_ref().is_some_and(|(last_points, last_controls)| {
+            *last_points == points && bytemuck::bytes_of(last_controls) == bytemuck::bytes_of(controls)
+        });
+        (!current && !points.is_empty()).then_some(points)
+    }
+
+    /// Records a readback that was queued by `ImageRenderResources::probe_pixels`
+    pub fn mark_requested(&mut self, points: Vec<[u32; 2]>, controls: &ImageControls) {
+        self.last_request = Some((points, *controls));
+    }
+
+    pub fn set_samples(&mut self, samples: Vec<PixelSample>) {
+        if let Some(hover) = self.hover {
+            if let Some(sample) = samples.iter().find(|s| s.coords == hover) {
+                self.hover_sample = Some(*sample);
+            }
+        } else {
+            self.hover_sample = None;
+        }
+        self.samples = samples;
+    }
+
+    fn sample_at(&self, coords: [u32; 2]) -> Option<&PixelSample> {
+        self.samples.iter().find(|s| s.coords == coords)
+    }
+
+    /// Pins `coords`, or unpins it if it's already pinned
+    pub fn toggle_pin(&mut self, coords: [u32; 2]) {
+        if let Some(index) = self.pinned.iter().position(|&p| p == coords) {
+            self.pinned.remove(index);
+        } else if self.pinned.len() < MAX_PINNED_SAMPLES {
+            self.pinned.push(coords);
+        }
+    }
+
+    fn sample_rows(ui: &mut egui::Ui, sample: &PixelSample) {
+        let output = sample.output.map(|c| c as f32 / 255.0);
+        ui.label(RichText::new("In").weak());
+        ui.monospace(rgb_text(sample.input));
+        ui.monospace(lab_text(linear_srgb_to_lab(sample.input)));
+        ui.label(RichText::new("Working").weak());
+        ui.monospace(rgb_text(sample.working));
+        ui.monospace(lab_text(linear_srgb_to_lab(sample.working)));
+        ui.label(RichText::new("Out").weak());
+        ui.monospace(format!("{} {} {}", sample.output[0], sample.output[1], sample.output[2]))
+            .on_hover_text(rgb_text(output));
+        ui.monospace(lab_text(linear_srgb_to_lab(sample.output_linear())));
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            match (self.hover, self.hover_sample) {
+                (Some([x, y]), Some(sample)) => {
+                    ui.monospace(format!("{x:>5}, {y:<5}"));
+                    ui.separator();
+                    Self::sample_rows(ui, &sample);
+                }
+                (Some([x, y]), None) => {
+                    ui.monospace(format!("{x:>5}, {y:<5}"));
+                }
+                _ => {
+                    ui.label(RichText::new("Shift-click the image to pin a sample point").weak());
+                }
+            }
+        });
+
+        if self.pinned.is_empty() {
+            return;
+        }
+
+        ui.separator();
+        let mut unpin = None;
+        egui::Grid::new("pinned_samples").striped(true).show(ui, |ui| {
+            for (i, &coords) in self.pinned.iter().enumerate() {
+                ui.label(RichText::new(format!("#{}", i + 1)).color(Color32::YELLOW));
+                ui.monospace(format!("{}, {}", coords[0], coords[1]));
+                if let Some(sample) = self.sample_at(coords) {
+                    Self::sample_rows(ui, sample);
+                }
+                if ui.small_button("✕").clicked() {
+                    unpin = Some(i);
+                }
+                ui.end_row();
+            }
+        });
+        if let Some(i) = unpin {
+            self.pinned.remove(i);
+        }
+    }
+}
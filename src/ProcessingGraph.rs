@@ -0,0 +1,343 @@
+use eframe::wgpu;
+use eframe::wgpu::TextureFormat;
+use crate::BrushMask::BrushMaskData;
+use crate::ImageControls::ImageControls;
+use crate::LocalAdjustments::LocalAdjustmentsUniform;
+use crate::NoiseReduction::NoiseReduction;
+use std::collections::HashMap;
+
+/// The steps between the decoded image and what's on screen, in the order they run
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Stage {
+    Geometry,
+    NoiseReduction,
+    Sharpening,
+    LocalContrast,
+    LocalAdjustments,
+    Color,
+}
+
+pub const STAGES: [Stage; 6] = [
+    Stage::Geometry,
+    Stage::NoiseReduction,
+    Stage::Sharpening,
+    Stage::LocalContrast,
+    Stage::LocalAdjustments,
+    Stage::Color,
+];
+
+impl Stage {
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Geometry => "Geometry",
+            Stage::NoiseReduction => "Noise Reduction",
+            Stage::Sharpening => "Sharpening",
+            Stage::LocalContrast => "Texture, Clarity & Dehaze",
+            Stage::LocalAdjustments => "Local Adjustments",
+            Stage::Color => "Colour & Tone",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Stage::Geometry => "Lens correction, spot removal, crop and rotation",
+            Stage::NoiseReduction => "Wavelet luminance and colour smoothing",
+            Stage::Sharpening => "Unsharp mask against a Gaussian blurred luma",
+            Stage::LocalContrast => "Luma pyramid, then the guided filter reading it",
+            Stage::LocalAdjustments => "Masked layers and brush mask refinement",
+            Stage::Color => "Exposure, colour, tone mapping and output",
+        }
+    }
+
+    /// Geometry frames the picture and colour writes it out, without them there's nothing
+    /// to look at
+    pub fn can_bypass(self) -> bool {
+        !matches!(self, Stage::Geometry | Stage::Color)
+    }
+}
+
+/// Stages switched off to see what they're doing. Only for viewing, not saved with the edit.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct StageBypass {
+    bypassed: [bool; STAGES.len()],
+}
+
+impl StageBypass {
+    pub fn is_bypassed(&self, stage: Stage) -> bool {
+        self.bypassed[stage as usize]
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        for stage in STAGES {
+            let mut enabled = !self.bypassed[stage as usize];
+            ui.add_enabled(stage.can_bypass(), egui::Checkbox::new(&mut enabled, stage.name()))
+                .on_hover_text(stage.description())
+                .on_disabled_hover_text(stage.description());
+            self.bypassed[stage as usize] = !enabled && stage.can_bypass();
+        }
+        if self.bypassed.iter().any(|&b| b) && ui.button("Enable All").clicked() {
+            *self = Self::default();
+        }
+    }
+}
+
+/// What the stages see of the frame being drawn
+pub struct StageFrame<'a> {
+    pub device: &'a wgpu::Device,
+    pub queue: &'a wgpu::Queue,
+    /// With the previews of bypassed stages off, see `ImageControls::bypassing`
+    pub controls: &'a ImageControls,
+    pub noise_reduction: &'a NoiseReduction,
+    pub local_adjustments: &'a LocalAdjustmentsUniform,
+    pub brush_masks: &'a BrushMaskData,
+}
+
+/// One step of the processing graph: its own shaders, settings and output texture
+pub trait GraphStage: Send + Sync {
+    fn stage(&self) -> Stage;
+
+    /// Where the stage leaves its result for the ones after it
+    fn output(&self) -> &wgpu::TextureView;
+
+    /// Whether the settings change anything. Stages that don't are left out like bypassed ones.
+    fn is_active(&self, _frame: &StageFrame) -> bool {
+        true
+    }
+
+    /// Whether the stage's output is to be seen on its own, the stages after it that can be
+    /// bypassed are left out
+    fn isolates(&self, _frame: &StageFrame) -> bool {
+        false
+    }
+
+    /// Points the stage at the texture it reads, the output of the nearest stage before it
+    /// that runs
+    fn bind(&mut self, device: &wgpu::Device, input: &wgpu::TextureView);
+
+    /// Records the stage's passes
+    fn encode(&self, encoder: &mut wgpu::CommandEncoder, frame: &StageFrame);
+}
+
+/// The texture a pass reads, in a bind group of its own so only it is remade when the
+/// stages before change
+pub fn input_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, input: &wgpu::TextureView) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Stage Input Bind Group"),
+        layout,
+        entries: &[wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::TextureView(input) }],
+    })
+}
+
+/// A stage that's one 16x16 dispatch over the framed image. Its input is group 0, the
+/// output and everything else group 1.
+pub struct ComputeStage {
+    stage: Stage,
+    pipeline: wgpu::ComputePipeline,
+    input_layout: wgpu::BindGroupLayout,
+    input_bind_group: wgpu::BindGroup,
+    bind_group: wgpu::BindGroup,
+    output: wgpu::TextureView,
+    size: [u32; 2],
+}
+
+impl ComputeStage {
+    pub fn new(
+        device: &wgpu::Device,
+        stage: Stage,
+        pipeline: &wgpu::ComputePipeline,
+        input_layout: &wgpu::BindGroupLayout,
+        input: &wgpu::TextureView,
+        bind_group: wgpu::BindGroup,
+        output: &wgpu::TextureView,
+    ) -> Self {
+        let size = output.texture().size();
+        Self {
+            stage,
+            pipeline: pipeline.clone(),
+            input_layout: input_layout.clone(),
+            input_bind_group: input_bind_group(device, input_layout, input),
+            bind_group,
+            output: output.clone(),
+            size: [size.width, size.height],
+        }
+    }
+}
+
+impl GraphStage for ComputeStage {
+    fn stage(&self) -> Stage {
+        self.stage
+    }
+
+    fn output(&self) -> &wgpu::TextureView {
+        &self.output
+    }
+
+    fn bind(&mut self, device: &wgpu::Device, input: &wgpu::TextureView) {
+        self.input_bind_group = input_bind_group(device, &self.input_layout, input);
+    }
+
+    fn encode(&self, encoder: &mut wgpu::CommandEncoder, _frame: &StageFrame) {
+        let mut pass = encoder.begin_compute_pass(&Default::default());
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.input_bind_group, &[]);
+        pass.set_bind_group(1, &self.bind_group, &[]);
+        pass.dispatch_workgroups(self.size[0].div_ceil(16), self.size[1].div_ceil(16), 1);
+    }
+}
+
+/// Every stage for one image in the order they run, sized to the framed image
+pub struct ProcessingGraph {
+    stages: Vec<Box<dyn GraphStage>>,
+    /// What the first stage reads
+    source: wgpu::TextureView,
+    bypass: StageBypass,
+    /// Which stages the bind groups were last made for
+    bound: Vec<bool>,
+}
+
+impl ProcessingGraph {
+    /// `stages` come bound to each other's outputs in order, the first to `source`
+    pub fn new(source: &wgpu::TextureView, stages: Vec<Box<dyn GraphStage>>) -> Self {
+        let bound = vec![true; stages.len()];
+        Self { stages, source: source.clone(), bypass: StageBypass::default(), bound }
+    }
+
+    /// Stages to leave out from the next `encode`
+    pub fn set_bypass(&mut self, bypass: StageBypass) {
+        self.bypass = bypass;
+    }
+
+    /// Which stages the frame needs, in order
+    fn running(&self, frame: &StageFrame) -> Vec<bool> {
+        let mut isolated = false;
+        self.stages
+            .iter()
+            .map(|stage| {
+                let kind = stage.stage();
+                let runs = !kind.can_bypass() || (!isolated && !self.bypass.is_bypassed(kind) && stage.is_active(frame));
+                isolated |= runs && stage.isolates(frame);
+                runs
+            })
+            .collect()
+    }
+
+    /// What `stage` reads as the graph was last bound, the output of the nearest stage
+    /// before it that runs
+    pub fn input_of(&self, stage: Stage) -> &wgpu::TextureView {
+        let mut input = &self.source;
+        for (graph_stage, &runs) in self.stages.iter().zip(&self.bound) {
+            if graph_stage.stage() == stage {
+                break;
+            }
+            if runs {
+                input = graph_stage.output();
+            }
+        }
+        input
+    }
+
+    /// Records the passes of every stage that runs. When that's a different set from last
+    /// time, the ones after a change are first pointed at their new input.
+    pub fn encode(&mut self, encoder: &mut wgpu::CommandEncoder, frame: &StageFrame) {
+        let running = self.running(frame);
+        if running != self.bound {
+            let mut input = self.source.clone();
+            for (stage, &runs) in self.stages.iter_mut().zip(&running) {
+                if runs {
+                    stage.bind(frame.device, &input);
+                    input = stage.output().clone();
+                }
+            }
+            self.bound = running;
+        }
+
+        for (stage, &runs) in self.stages.iter().zip(&self.bound) {
+            if runs {
+                stage.encode(encoder, frame);
+            }
+        }
+    }
+}
+
+/// Shape of a texture a stage works in
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Intermediate {
+    pub size: [u32; 2],
+    pub layers: u32,
+    pub mip_levels: u32,
+    pub format: TextureFormat,
+}
+
+impl Intermediate {
+    pub fn new(size: [u32; 2], format: TextureFormat) -> Self {
+        Self { size, layers: 1, mip_levels: 1, format }
+    }
+
+    pub fn layers(self, layers: u32) -> Self {
+        Self { layers, ..self }
+    }
+
+    pub fn mip_levels(self, mip_levels: u32) -> Self {
+        Self { mip_levels, ..self }
+    }
+}
+
+/// Hands out the textures stages work in while a graph is built. Scratch textures only
+/// hold anything while their own stage runs, so stages asking for the same shape share
+/// them. Outputs are read by later stages and always get their own.
+pub struct IntermediatePool {
+    device: wgpu::Device,
+    scratch: HashMap<Intermediate, Vec<wgpu::Texture>>,
+}
+
+/// One stage's claim on the pool's scratch textures
+pub struct StageScratch<'p> {
+    pool: &'p mut IntermediatePool,
+    claimed: HashMap<Intermediate, usize>,
+}
+
+impl IntermediatePool {
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self { device: device.clone(), scratch: HashMap::new() }
+    }
+
+    fn create(&self, label: &str, shape: Intermediate, usage: wgpu::TextureUsages) -> wgpu::Texture {
+        self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: shape.size[0],
+                height: shape.size[1],
+                depth_or_array_layers: shape.layers,
+            },
+            mip_level_count: shape.mip_levels,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: shape.format,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING | usage,
+            view_formats: &[],
+        })
+    }
+
+    /// A texture later stages read, with any usage it needs on top of storage and sampling
+    pub fn output(&self, label: &str, shape: Intermediate, usage: wgpu::TextureUsages) -> wgpu::Texture {
+        self.create(label, shape, usage)
+    }
+
+    /// Start handing out scratch textures for the next stage
+    pub fn stage(&mut self) -> StageScratch<'_> {
+        StageScratch { pool: self, claimed: HashMap::new() }
+    }
+}
+
+impl StageScratch<'_> {
+    /// A texture only this stage uses while it runs, possibly one an earlier stage used too
+    pub fn texture(&mut self, shape: Intermediate) -> wgpu::Texture {
+        let index = self.claimed.get(&shape).copied().unwrap_or(0);
+        self.claimed.insert(shape, index + 1);
+        if self.pool.scratch.get(&shape).map_or(0, Vec::len) == index {
+            let texture = self.pool.create("Scratch Texture", shape, wgpu::TextureUsages::empty());
+            self.pool.scratch.entry(shape).or_default().push(texture);
+        }
+        self.pool.scratch[&shape][index].clone()
+    }
+}
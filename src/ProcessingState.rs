@@ -0,0 +1,130 @@
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::AdjustmentStack::AdjustmentStack;
+use crate::Geometry::Geometry;
+use crate::ImageControls::ImageControls;
+use crate::LensCorrection::LensCorrection;
+use crate::LocalAdjustments::LocalAdjustments;
+use crate::NoiseReduction::NoiseReduction;
+use crate::SpotHealing::SpotHealing;
+
+/// Everything needed to redo an edit. Saved as a JSON sidecar next to the image so the
+/// original file is never touched, and picked up again the next time it's opened.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProcessingState {
+    pub controls: ImageControls,
+    pub geometry: Geometry,
+    pub lens: LensCorrection,
+    pub local_adjustments: LocalAdjustments,
+    pub spot_healing: SpotHealing,
+    pub noise_reduction: NoiseReduction,
+    pub adjustment_stack: AdjustmentStack,
+}
+
+/// "IMG_0001.CR2" -> "IMG_0001.CR2.film.json", keeping the extension so a RAW and its
+/// JPEG don't share edits
+pub fn sidecar_path(image_path: &Path) -> PathBuf {
+    let mut name = image_path.as_os_str().to_owned();
+    name.push(".film.json");
+    PathBuf::from(name)
+}
+
+impl ProcessingState {
+    /// The saved edit for an image, None if there isn't one. A sidecar that can't be read or
+    /// parsed is an error, so it isn't taken for no edit and saved over. Menu choices out of
+    /// range, from a newer version or a hand edit, go back to their defaults.
+    pub fn load(image_path: &Path) -> std::io::Result<Option<Self>> {
+        let json = match std::fs::read_to_string(sidecar_path(image_path)) {
+            Ok(json) => json,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let state: Self = serde_json::from_str(&json)?;
+        Ok(Some(Self {
+            controls: state.controls.validated(),
+            geometry: state.geometry.validated(),
+            ..state
+        }))
+    }
+
+    pub fn save(&self, image_path: &Path) -> std::io::Result<()> {
+        let json = serde_json::to_string_pretty(self).map_err(std::io::Error::other)?;
+        std::fs::write(sidecar_path(image_path), json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LocalAdjustments::LocalAdjustment;
+
+    fn edited() -> ProcessingState {
+        let mut state: ProcessingState =
+            serde_json::from_str(r#"{ "controls": { "exposure": 1.5, "shadow_wheel": [30.0, 0.4, 0.1, 0.0] } }"#).unwrap();
+        state.geometry.quarter_turns = 3;
+        state.geometry.straighten = -4.25;
+        state.noise_reduction.luminance = 0.6;
+        state.local_adjustments.layers.push(LocalAdjustment { exposure: -0.7, invert: true, ..LocalAdjustment::default() });
+        state.adjustment_stack.steps.reverse();
+        state
+    }
+
+    #[test]
+    fn round_trips_through_json() {
+        let state = edited();
+        let json = serde_json::to_string(&state).unwrap();
+        let loaded: ProcessingState = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(loaded.geometry, state.geometry);
+        assert_eq!(loaded.local_adjustments, state.local_adjustments);
+        assert_eq!(loaded.spot_healing, state.spot_healing);
+        assert_eq!(loaded.adjustment_stack, state.adjustment_stack);
+        assert_eq!(loaded.noise_reduction, state.noise_reduction);
+        // ImageControls has no PartialEq, what it writes out has to match instead
+        assert_eq!(serde_json::to_value(&loaded).unwrap(), serde_json::to_value(&state).unwrap());
+        assert_eq!(serde_json::to_value(loaded.controls).unwrap()["exposure"], 1.5);
+    }
+
+    #[test]
+    fn missing_fields_load_as_defaults() {
+        let loaded: ProcessingState = serde_json::from_str(r#"{ "geometry": { "straighten": 2.0 } }"#).unwrap();
+        assert_eq!(loaded.geometry, Geometry { straighten: 2.0, ..Geometry::default() });
+        assert_eq!(loaded.adjustment_stack, AdjustmentStack::default());
+        assert!(loaded.local_adjustments.layers.is_empty());
+    }
+
+    #[test]
+    fn out_of_range_menu_choices_load_as_defaults() {
+        let dir = std::env::temp_dir().join(format!("film-emulator-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let image = dir.join("range.jpg");
+        std::fs::write(sidecar_path(&image), r#"{"controls":{"tone_mapper":99},"geometry":{"aspect":99}}"#).unwrap();
+        let loaded = ProcessingState::load(&image);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let loaded = loaded.unwrap().expect("sidecar loads");
+        let controls = serde_json::to_value(loaded.controls).unwrap();
+        assert_eq!(controls["tone_mapper"], serde_json::to_value(ImageControls::default()).unwrap()["tone_mapper"]);
+        assert!((controls["tone_mapper"].as_u64().unwrap() as usize) < crate::ImageControls::TONE_MAPPER_NAMES.len());
+        assert_eq!(loaded.geometry.aspect, crate::Geometry::ASPECT_FREE);
+    }
+
+    #[test]
+    fn unreadable_sidecar_is_an_error_not_a_missing_one() {
+        let dir = std::env::temp_dir().join(format!("film-emulator-test-{}-corrupt", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (corrupt, missing) = (dir.join("corrupt.jpg"), dir.join("missing.jpg"));
+        std::fs::write(sidecar_path(&corrupt), r#"{"controls":"#).unwrap();
+        let (corrupt, missing) = (ProcessingState::load(&corrupt), ProcessingState::load(&missing));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(corrupt.is_err());
+        assert!(matches!(missing, Ok(None)));
+    }
+
+    #[test]
+    fn sidecar_keeps_the_image_extension() {
+        assert_eq!(sidecar_path(Path::new("/photos/IMG_0001.CR2")), Path::new("/photos/IMG_0001.CR2.film.json"));
+    }
+}
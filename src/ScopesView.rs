@@ -0,0 +1,303 @@
+use egui::{Color32, ColorImage, Pos2, Rect, Sense, Stroke, TextureHandle, TextureOptions, Vec2};
+use crate::GpuScopesPipeline::SCOPE_SIZE;
+
+// Must match VECTORSCOPE_SCALE in scopes.wgsl
+const VECTORSCOPE_SCALE: f32 = 230.0;
+
+// Skin tones sit along this angle (degrees counter-clockwise from +Cb) on a vectorscope
+const SKIN_TONE_ANGLE: f32 = 123.0;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ScopeDock {
+    Right,
+    Bottom,
+    Window,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WaveformMode {
+    Luma,
+    Parade,
+}
+
+/// Waveform and vectorscope views, fed from the GPU scope readbacks
+pub struct ScopesView {
+    pub visible: bool,
+    pub dock: ScopeDock,
+    waveform_mode: WaveformMode,
+    show_waveform: bool,
+    show_vectorscope: bool,
+
+    waveform: Vec<u32>,
+    vectorscope: Vec<u32>,
+    waveform_dirty: bool,
+    vectorscope_dirty: bool,
+    waveform_texture: Option<TextureHandle>,
+    vectorscope_texture: Option<TextureHandle>,
+}
+
+impl Default for ScopesView {
+    fn default() -> Self {
+        Self {
+            visible: false,
+            dock: ScopeDock::Right,
+            waveform_mode: WaveformMode::Luma,
+            show_waveform: true,
+            show_vectorscope: true,
+            waveform: Vec::new(),
+            vectorscope: Vec::new(),
+            waveform_dirty: false,
+            vectorscope_dirty: false,
+            waveform_texture: None,
+            vectorscope_texture: None,
+        }
+    }
+}
+
+/// Maps a bin count to 0..1 brightness. Exponential so sparse traces stay visible
+/// without dense ones saturating straight away.
+fn trace_intensity(count: u32, density: f32) -> f32 {
+    1.0 - (-(count as f32) / density).exp()
+}
+
+/// Rec.601 Cb/Cr of a display-encoded colour, same as scopes.wgsl
+fn rgb_to_cbcr(rgb: [f32; 3]) -> (f32, f32) {
+    let [r, g, b] = rgb;
+    (
+        -0.168736 * r - 0.331264 * g + 0.5 * b,
+        0.5 * r - 0.418688 * g - 0.081312 * b,
+    )
+}
+
+fn upload(ctx: &egui::Context, slot: &mut Option<TextureHandle>, name: &str, image: ColorImage) {
+    match slot {
+        Some(texture) => texture.set(image, TextureOptions::LINEAR),
+        None => *slot = Some(ctx.load_texture(name, image, TextureOptions::LINEAR)),
+    }
+}
+
+impl ScopesView {
+    pub fn wants_data(&self) -> bool {
+        self.visible && (self.show_waveform || self.show_vectorscope)
+    }
+
+    pub fn set_waveform(&mut self, bins: Vec<u32>) {
+        self.waveform = bins;
+        self.waveform_dirty = true;
+    }
+
+    pub fn set_vectorscope(&mut self, bins: Vec<u32>) {
+        self.vectorscope = bins;
+        self.vectorscope_dirty = true;
+    }
+
+    /// Shows the scopes wherever they're docked. Must be called before the central panel.
+    pub fn show(&mut self, ctx: &egui::Context) {
+        if !self.visible {
+            return;
+        }
+
+        match self.dock {
+            ScopeDock::Right => {
+                egui::SidePanel::right("scopes")
+                    .resizable(true)
+                    .default_width(300.0)
+                    .show(ctx, |ui| self.ui(ui, false));
+            }
+            ScopeDock::Bottom => {
+                egui::TopBottomPanel::bottom("scopes")
+                    .resizable(true)
+                    .default_height(280.0)
+                    .show(ctx, |ui| self.ui(ui, true));
+            }
+            ScopeDock::Window => {
+                let mut open = self.visible;
+                egui::Window::new("Scopes")
+                    .open(&mut open)
+                    .default_width(300.0)
+                    .show(ctx, |ui| self.ui(ui, false));
+                self.visible = open;
+            }
+        }
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui, horizontal: bool) {
+        self.update_textures(ui.ctx());
+
+        ui.horizontal_wrapped(|ui| {
+            ui.checkbox(&mut self.show_waveform, "Waveform");
+            ui.checkbox(&mut self.show_vectorscope, "Vectorscope");
+            egui::ComboBox::from_id_salt("waveform_mode")
+                .selected_text(match self.waveform_mode {
+                    WaveformMode::Luma => "Luma",
+                    WaveformMode::Parade => "RGB Parade",
+                })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.waveform_mode, WaveformMode::Luma, "Luma");
+                    ui.selectable_value(&mut self.waveform_mode, WaveformMode::Parade, "RGB Parade");
+                });
+            egui::ComboBox::from_id_salt("scope_dock")
+                .selected_text(match self.dock {
+                    ScopeDock::Right => "Dock Right",
+                    ScopeDock::Bottom => "Dock Bottom",
+                    ScopeDock::Window => "Floating",
+                })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.dock, ScopeDock::Right, "Dock Right");
+                    ui.selectable_value(&mut self.dock, ScopeDock::Bottom, "Dock Bottom");
+                    ui.selectable_value(&mut self.dock, ScopeDock::Window, "Floating");
+                });
+        });
+
+        let draw = |ui: &mut egui::Ui, this: &Self| {
+            if this.show_waveform {
+                this.waveform_ui(ui);
+            }
+            if this.show_vectorscope {
+                this.vectorscope_ui(ui);
+            }
+        };
+        if horizontal {
+            ui.horizontal_top(|ui| draw(ui, self));
+        } else {
+            draw(ui, self);
+        }
+    }
+
+    fn update_textures(&mut self, ctx: &egui::Context) {
+        if self.waveform_dirty && self.waveform.len() == 4 * SCOPE_SIZE * SCOPE_SIZE {
+            self.waveform_dirty = false;
+            let image = self.waveform_image();
+            upload(ctx, &mut self.waveform_texture, "waveform", image);
+        }
+        if self.vectorscope_dirty && self.vectorscope.len() == SCOPE_SIZE * SCOPE_SIZE {
+            self.vectorscope_dirty = false;
+            let image = self.vectorscope_image();
+            upload(ctx, &mut self.vectorscope_texture, "vectorscope", image);
+        }
+    }
+
+    fn waveform_image(&self) -> ColorImage {
+        let plane = SCOPE_SIZE * SCOPE_SIZE;
+
+        // Average samples landing in one column, spread across the levels
+        let samples: u64 = self.waveform[..plane].iter().map(|&c| c as u64).sum();
+        let density = (samples as f32 / (SCOPE_SIZE * SCOPE_SIZE) as f32 * 4.0).max(1.0);
+
+        let channels: Vec<(usize, [f32; 3])> = match self.waveform_mode {
+            WaveformMode::Luma => vec![(0, [0.6, 1.0, 0.6])],
+            WaveformMode::Parade => vec![
+                (1, [1.0, 0.3, 0.3]),
+                (2, [0.3, 1.0, 0.3]),
+                (3, [0.35, 0.5, 1.0]),
+            ],
+        };
+
+        let width = SCOPE_SIZE * channels.len();
+        let mut pixels = vec![Color32::BLACK; width * SCOPE_SIZE];
+        for (slot, (channel, tint)) in channels.iter().enumerate() {
+            let bins = &self.waveform[channel * plane..(channel + 1) * plane];
+            for level in 0..SCOPE_SIZE {
+                // Level 0 is black, which belongs at the bottom
+                let row = SCOPE_SIZE - 1 - level;
+                for column in 0..SCOPE_SIZE {
+                    let v = trace_intensity(bins[level * SCOPE_SIZE + column], density);
+                    pixels[row * width + slot * SCOPE_SIZE + column] = Color32::from_rgb(
+                        (tint[0] * v * 255.0) as u8,
+                        (tint[1] * v * 255.0) as u8,
+                        (tint[2] * v * 255.0) as u8,
+                    );
+                }
+            }
+        }
+        ColorImage::new([width, SCOPE_SIZE], pixels)
+    }
+
+    fn vectorscope_image(&self) -> ColorImage {
+        let samples: u64 = self.vectorscope.iter().map(|&c| c as u64).sum();
+        let density = (samples as f32 / (SCOPE_SIZE * SCOPE_SIZE) as f32 * 8.0).max(1.0);
+        let center = SCOPE_SIZE as f32 * 0.5;
+
+        let mut pixels = vec![Color32::BLACK; SCOPE_SIZE * SCOPE_SIZE];
+        for (i, &count) in self.vectorscope.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            // Colour each trace point with the chroma it represents at mid grey
+            let cb = ((i % SCOPE_SIZE) as f32 - center) / VECTORSCOPE_SCALE;
+            let cr = (center - (i / SCOPE_SIZE) as f32) / VECTORSCOPE_SCALE;
+            let rgb = [0.5 + 1.402 * cr, 0.5 - 0.344136 * cb - 0.714136 * cr, 0.5 + 1.772 * cb];
+            let v = trace_intensity(count, density);
+            let [r, g, b] = rgb.map(|c| (c.clamp(0.0, 1.0) * v * 255.0) as u8);
+            pixels[i] = Color32::from_rgb(r, g, b);
+        }
+        ColorImage::new([SCOPE_SIZE, SCOPE_SIZE], pixels)
+    }
+
+    fn waveform_ui(&self, ui: &mut egui::Ui) {
+        let width = ui.available_width().min(600.0);
+        let (rect, _) = ui.allocate_exact_size(Vec2::new(width, width * 0.5), Sense::hover());
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 0.0, Color32::BLACK);
+        if let Some(texture) = &self.waveform_texture {
+            let uv = Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0));
+            painter.image(texture.id(), rect, uv, Color32::WHITE);
+        }
+
+        // Graticule every 25 IRE-ish
+        let line = Stroke::new(1.0, Color32::from_white_alpha(40));
+        for step in 0..=4 {
+            let y = rect.bottom() - rect.height() * step as f32 / 4.0;
+            painter.hline(rect.x_range(), y, line);
+        }
+    }
+
+    fn vectorscope_ui(&self, ui: &mut egui::Ui) {
+        let side = ui.available_width().min(300.0);
+        let (rect, _) = ui.allocate_exact_size(Vec2::splat(side), Sense::hover());
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 0.0, Color32::BLACK);
+        if let Some(texture) = &self.vectorscope_texture {
+            let uv = Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0));
+            painter.image(texture.id(), rect, uv, Color32::WHITE);
+        }
+
+        // Graticule: scope units to screen
+        let scale = side / SCOPE_SIZE as f32;
+        let center = rect.center();
+        let to_screen = |cb: f32, cr: f32| center + Vec2::new(cb, -cr) * VECTORSCOPE_SCALE * scale;
+
+        let faint = Stroke::new(1.0, Color32::from_white_alpha(40));
+        painter.hline(rect.x_range(), center.y, faint);
+        painter.vline(center.x, rect.y_range(), faint);
+        painter.circle_stroke(center, 0.5 * VECTORSCOPE_SCALE * scale, faint);
+
+        // 75% colour bar targets
+        let targets = [
+            ("R", [0.75, 0.0, 0.0]),
+            ("Mg", [0.75, 0.0, 0.75]),
+            ("B", [0.0, 0.0, 0.75]),
+            ("Cy", [0.0, 0.75, 0.75]),
+            ("G", [0.0, 0.75, 0.0]),
+            ("Yl", [0.75, 0.75, 0.0]),
+        ];
+        for (label, rgb) in targets {
+            let (cb, cr) = rgb_to_cbcr(rgb);
+            let p = to_screen(cb, cr);
+            let target = Stroke::new(1.0, Color32::from_white_alpha(90));
+            painter.rect_stroke(Rect::from_center_size(p, Vec2::splat(8.0)), 0.0, target, egui::StrokeKind::Middle);
+            painter.text(
+                p + Vec2::new(7.0, -7.0),
+                egui::Align2::LEFT_BOTTOM,
+                label,
+                egui::FontId::proportional(10.0),
+                Color32::from_white_alpha(140),
+            );
+        }
+
+        // Skin tone line
+        let angle = SKIN_TONE_ANGLE.to_radians();
+        let end = to_screen(angle.cos() * 0.55, angle.sin() * 0.55);
+        painter.line_segment([center, end], Stroke::new(1.0, Color32::from_rgba_unmultiplied(255, 190, 140, 120)));
+    }
+}
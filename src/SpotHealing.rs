@@ -0,0 +1,282 @@
+use serde::{Deserialize, Serialize};
+
+/// Spots and path points the geometry shader has room for, must match the buffers made
+/// for it in FilmEmulator
+pub const MAX_HEAL_SPOTS: usize = 256;
+pub const MAX_HEAL_POINTS: usize = 4096;
+
+/// Bytes before the spot array in the spot buffer, the count padded out to 16
+pub const SPOT_LIST_HEADER: u64 = 16;
+
+/// Longest side of the copy the source search and dust detection look at. Dust on a
+/// scan is only a few pixels across, so it can't be much smaller.
+const SEARCH_RESOLUTION: u32 = 3072;
+
+/// Directions and distances (in radii) tried when looking for a source patch
+const SEARCH_DIRECTIONS: usize = 24;
+const SEARCH_DISTANCES: [f32; 4] = [2.2, 3.0, 4.0, 5.5];
+
+/// Samples around the ring the search compares, a little outside the spot
+const SEARCH_RING: usize = 12;
+const SEARCH_RING_SCALE: f32 = 1.3;
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HealMode {
+    /// Source texture, blended into the colour and brightness around the defect
+    #[default]
+    Heal,
+    /// Source pixels as they are, feathered at the edge
+    Clone,
+}
+
+impl HealMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            HealMode::Heal => "Heal",
+            HealMode::Clone => "Clone",
+        }
+    }
+}
+
+/// One repaired defect. Positions are in mask coordinates like the local adjustments, so
+/// spots stay on the dust through crops and turns.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HealSpot {
+    /// Path over the defect, a single point for a clicked spot
+    pub points: Vec<[f32; 2]>,
+    pub radius: f32,
+    /// Share of the radius blended into the surroundings
+    pub feather: f32,
+    /// Where the replacement comes from, relative to the defect
+    pub offset: [f32; 2],
+    pub mode: HealMode,
+}
+
+impl HealSpot {
+    /// Defect path moved onto its source patch
+    pub fn source_points(&self) -> impl Iterator<Item = [f32; 2]> + '_ {
+        self.points.iter().map(|p| [p[0] + self.offset[0], p[1] + self.offset[1]])
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpotHealing {
+    pub spots: Vec<HealSpot>,
+}
+
+// Mirrors `Spot` in geometry.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SpotGpu {
+    pub bounds: [f32; 4], // min x, min y, max x, max y including the radius, source pixels
+    pub params: [f32; 4], // radius, feather, heal (1) or clone (0), unused
+    pub offset: [f32; 4], // source patch offset x, y, unused x2
+    pub range: [u32; 4],  // first point, point count, unused x2
+}
+
+impl SpotHealing {
+    /// The spots the shader gets: a spot whose path would overflow the point buffer is passed
+    /// over so the ones after it still apply.
+    fn applied(&self) -> impl Iterator<Item = &HealSpot> {
+        let mut total = 0;
+        self.spots
+            .iter()
+            .filter(move |spot| {
+                let fits = !spot.points.is_empty() && total + spot.points.len() <= MAX_HEAL_POINTS;
+                if fits {
+                    total += spot.points.len();
+                }
+                fits
+            })
+            .take(MAX_HEAL_SPOTS)
+    }
+
+    /// Spots with a path that didn't fit in the point buffer
+    pub fn unapplied(&self) -> usize {
+        self.spots.iter().filter(|s| !s.points.is_empty()).count() - self.applied().count()
+    }
+
+    /// Spots in source pixels for the geometry shader, as many as fit
+    pub fn gpu(&self, source: [u32; 2]) -> (Vec<SpotGpu>, Vec<[f32; 2]>) {
+        let unit = source[0].max(source[1]) as f32;
+        let mut spots = Vec::new();
+        let mut points: Vec<[f32; 2]> = Vec::new();
+        for spot in self.applied() {
+            let mut bounds = [f32::MAX, f32::MAX, f32::MIN, f32::MIN];
+            for p in &spot.points {
+                bounds = [bounds[0].min(p[0]), bounds[1].min(p[1]), bounds[2].max(p[0]), bounds[3].max(p[1])];
+            }
+            let r = spot.radius;
+            spots.push(SpotGpu {
+                bounds: [bounds[0] - r, bounds[1] - r, bounds[2] + r, bounds[3] + r].map(|v| v * unit),
+                params: [r * unit, spot.feather, (spot.mode == HealMode::Heal) as u32 as f32, 0.0],
+                offset: [spot.offset[0] * unit, spot.offset[1] * unit, 0.0, 0.0],
+                range: [points.len() as u32, spot.points.len() as u32, 0, 0],
+            });
+            points.extend(spot.points.iter().map(|p| [p[0] * unit, p[1] * unit]));
+        }
+        (spots, points)
+    }
+}
+
+/// Luma copy of the decoded image for finding source patches and dust on the CPU
+pub struct SpotSearch {
+    width: u32,
+    height: u32,
+    /// Search pixels per source pixel
+    scale: f32,
+    /// Gamma encoded, so differences count about as much in the shadows as they look
+    luma: Vec<f32>,
+}
+
+impl SpotSearch {
+    pub fn new(pixels: &[[f32; 3]], width: u32, height: u32) -> Self {
+        let step = width.max(height).div_ceil(SEARCH_RESOLUTION).max(1);
+        let (w, h) = (width.div_ceil(step), height.div_ceil(step));
+        let mut luma = vec![0.0; (w * h) as usize];
+        // Box filtered down so single pixels of noise don't steer the search
+        for y in 0..h {
+            for x in 0..w {
+                let (mut sum, mut count) = (0.0, 0);
+                for sy in y * step..((y + 1) * step).min(height) {
+                    for sx in x * step..((x + 1) * step).min(width) {
+                        let [r, g, b] = pixels[(sy * width + sx) as usize];
+                        sum += 0.299 * r + 0.587 * g + 0.114 * b;
+                        count += 1;
+                    }
+                }
+                luma[(y * w + x) as usize] = (sum / count.max(1) as f32).clamp(0.0, 1.0).powf(1.0 / 2.2);
+            }
+        }
+        Self { width: w, height: h, scale: 1.0 / step as f32, luma }
+    }
+
+    pub fn empty() -> Self {
+        Self { width: 0, height: 0, scale: 1.0, luma: Vec::new() }
+    }
+
+    /// Bilinear luma at a position in search pixels, None off the image
+    fn sample(&self, p: [f32; 2]) -> Option<f32> {
+        let (x, y) = (p[0] - 0.5, p[1] - 0.5);
+        if x < 0.0 || y < 0.0 || x > (self.width - 1) as f32 || y > (self.height - 1) as f32 {
+            return None;
+        }
+        let (x0, y0) = (x as u32, y as u32);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+        let at = |x: u32, y: u32| self.luma[(y * self.width + x) as usize];
+        let top = at(x0, y0) + (at(x1, y0) - at(x0, y0)) * fx;
+        let bottom = at(x0, y1) + (at(x1, y1) - at(x0, y1)) * fx;
+        Some(top + (bottom - top) * fy)
+    }
+
+    pub(crate) fn size(&self) -> [u32; 2] {
+        [self.width, self.height]
+    }
+
+    /// Analysis pixels per source pixel
+    pub(crate) fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    pub(crate) fn luma(&self) -> &[f32] {
+        &self.luma
+    }
+
+    /// Search pixels -> mask coordinates
+    pub(crate) fn to_mask(&self, p: [f32; 2], unit: f32) -> [f32; 2] {
+        [p[0] / (self.scale * unit), p[1] / (self.scale * unit)]
+    }
+
+    /// Mask coordinates -> search pixels
+    fn to_search(&self, p: [f32; 2], unit: f32) -> [f32; 2] {
+        [p[0] * unit * self.scale, p[1] * unit * self.scale]
+    }
+
+    /// Best looking source patch for a defect, as an offset in mask coordinates. Candidates
+    /// around the defect are scored on how well their surroundings match the defect's and
+    /// on how little is going on inside them, which keeps other dust out of the patch.
+    pub fn find_source(&self, points: &[[f32; 2]], radius: f32, unit: f32) -> [f32; 2] {
+        let fallback = [radius * SEARCH_DISTANCES[0], 0.0];
+        if self.luma.is_empty() || points.is_empty() {
+            return fallback;
+        }
+        let r = (radius * unit * self.scale).max(1.5);
+        // Long paths are compared at a handful of points along them
+        let stride = points.len().div_ceil(16).max(1);
+        let targets: Vec<[f32; 2]> = points.iter().step_by(stride).map(|&p| self.to_search(p, unit)).collect();
+
+        let ring: Vec<[f32; 2]> = (0..SEARCH_RING)
+            .map(|k| {
+                let t = k as f32 / SEARCH_RING as f32 * std::f32::consts::TAU;
+                [t.cos() * r * SEARCH_RING_SCALE, t.sin() * r * SEARCH_RING_SCALE]
+            })
+            .collect();
+        let inside: Vec<[f32; 2]> = std::iter::once([0.0, 0.0])
+            .chain((0..6).map(|k| {
+                let t = k as f32 / 6.0 * std::f32::consts::TAU;
+                [t.cos() * r * 0.5, t.sin() * r * 0.5]
+            }))
+            .collect();
+        let add = |a: [f32; 2], b: [f32; 2]| [a[0] + b[0], a[1] + b[1]];
+
+        let score = |offset: [f32; 2]| -> Option<f32> {
+            let mut context = 0.0;
+            let mut structure = 0.0;
+            for &t in &targets {
+                let s = add(t, offset);
+                let mut ring_mean = 0.0;
+                for &q in &ring {
+                    let source = self.sample(add(s, q))?;
+                    // Off the image around the defect only loses that sample
+                    if let Some(target) = self.sample(add(t, q)) {
+                        context += (target - source) * (target - source);
+                    }
+                    ring_mean += source;
+                }
+                ring_mean /= ring.len() as f32;
+                for &q in &inside {
+                    let v = self.sample(add(s, q))?;
+                    structure += (v - ring_mean) * (v - ring_mean);
+                }
+            }
+            Some(context / ring.len() as f32 + 2.0 * structure / inside.len() as f32)
+        };
+
+        let mut best: Option<([f32; 2], f32)> = None;
+        for distance in SEARCH_DISTANCES {
+            for k in 0..SEARCH_DIRECTIONS {
+                let t = k as f32 / SEARCH_DIRECTIONS as f32 * std::f32::consts::TAU;
+                let offset = [t.cos() * r * distance, t.sin() * r * distance];
+                // Nearer patches win ties, they're more likely to share the light and grain
+                if let Some(s) = score(offset).map(|s| s * (1.0 + 0.05 * distance))
+                    && best.is_none_or(|(_, b)| s < b)
+                {
+                    best = Some((offset, s));
+                }
+            }
+        }
+        best.map_or(fallback, |(offset, _)| self.to_mask(offset, unit))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spot(points: usize) -> HealSpot {
+        HealSpot { points: vec![[0.5, 0.5]; points], radius: 0.01, feather: 0.5, offset: [0.1, 0.0], mode: HealMode::Heal }
+    }
+
+    #[test]
+    fn spot_past_the_point_limit_is_skipped_not_the_rest() {
+        let healing = SpotHealing { spots: vec![spot(10), spot(MAX_HEAL_POINTS), spot(1)] };
+        let (spots, points) = healing.gpu([100, 100]);
+
+        assert_eq!(spots.len(), 2);
+        assert_eq!(points.len(), 11);
+        assert_eq!(spots[1].range, [10, 1, 0, 0]);
+        assert_eq!(healing.unapplied(), 1);
+    }
+}
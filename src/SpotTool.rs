@@ -0,0 +1,323 @@
+use egui::{Color32, Pos2, Rect, Shape, Stroke};
+use crate::DustDetection::{detect_dust, DustCandidate, DustSettings};
+use crate::Geometry::Geometry;
+use crate::MaskTool::{add, sub, FrameMapping};
+use crate::SpotHealing::{HealMode, HealSpot, SpotHealing, SpotSearch, MAX_HEAL_SPOTS};
+
+/// Pointer distance in points at which a spot can be grabbed, on top of its radius
+const SPOT_REACH: f32 = 4.0;
+
+/// A new point is added to a painted defect once the pointer has moved this share of the radius
+const PATH_SPACING: f32 = 0.3;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum SpotHandle {
+    /// The defect, moving it keeps the same source patch
+    Target,
+    Source,
+}
+
+#[derive(Debug, Clone)]
+enum SpotDrag {
+    Move { index: usize, handle: SpotHandle, start_spot: HealSpot, start_pos: [f32; 2] },
+    /// A defect being brushed over, turned into a spot when the drag ends
+    Paint(Vec<[f32; 2]>),
+}
+
+/// Spot removal for dust and scratches, clicked or brushed onto the viewer
+pub struct SpotTool {
+    pub active: bool,
+    pub selected: Option<usize>,
+    pub mode: HealMode,
+    /// Mask coordinates, like the spots
+    pub radius: f32,
+    pub feather: f32,
+    /// The loaded image, for finding source patches
+    pub search: SpotSearch,
+    pub dust: DustSettings,
+    /// Found by the dust detector and waiting for review
+    candidates: Vec<DustCandidate>,
+    drag: Option<SpotDrag>,
+}
+
+impl Default for SpotTool {
+    fn default() -> Self {
+        Self {
+            active: false,
+            selected: None,
+            mode: HealMode::Heal,
+            radius: 0.006,
+            feather: 0.4,
+            search: SpotSearch::empty(),
+            dust: DustSettings::default(),
+            candidates: Vec::new(),
+            drag: None,
+        }
+    }
+}
+
+/// Distance from `p` to a spot's path, in mask coordinates
+fn path_distance(points: &[[f32; 2]], p: [f32; 2]) -> f32 {
+    let mut best = f32::MAX;
+    for (i, &a) in points.iter().enumerate() {
+        let b = points.get(i + 1).copied().unwrap_or(a);
+        let ab = sub(b, a);
+        let ap = sub(p, a);
+        let t = ((ap[0] * ab[0] + ap[1] * ab[1]) / (ab[0] * ab[0] + ab[1] * ab[1]).max(1e-12)).clamp(0.0, 1.0);
+        let [dx, dy] = sub(ap, [ab[0] * t, ab[1] * t]);
+        best = best.min(dx.hypot(dy));
+    }
+    best
+}
+
+impl SpotTool {
+    /// Spot removal section for the side panel
+    pub fn panel_ui(&mut self, ui: &mut egui::Ui, healing: &mut SpotHealing, source: [u32; 2]) {
+        if ui.selectable_label(self.active, "Remove Spots").on_hover_text("Click a spot or brush over a scratch").clicked() {
+            self.active = !self.active;
+        }
+
+        ui.horizontal(|ui| {
+            for mode in [HealMode::Heal, HealMode::Clone] {
+                ui.selectable_value(&mut self.mode, mode, mode.name());
+            }
+        });
+        ui.add(egui::Slider::new(&mut self.radius, 0.001..=0.05).logarithmic(true).text("Size"));
+        ui.add(egui::Slider::new(&mut self.feather, 0.0..=1.0).text("Feather"));
+
+        ui.horizontal(|ui| {
+            ui.label(format!("{} of {} spots", healing.spots.len(), MAX_HEAL_SPOTS));
+            let unapplied = healing.unapplied();
+            if unapplied > 0 {
+                ui.colored_label(ui.visuals().warn_fg_color, format!("{unapplied} spots not applied"))
+                    .on_hover_text("Their paths don't fit with the other spots, shorten or remove a long one");
+            }
+        });
+        if let Some(spot) = self.selected.and_then(|i| healing.spots.get_mut(i)) {
+            ui.separator();
+            ui.horizontal(|ui| {
+                for mode in [HealMode::Heal, HealMode::Clone] {
+                    ui.selectable_value(&mut spot.mode, mode, mode.name());
+                }
+            });
+            ui.add(egui::Slider::new(&mut spot.radius, 0.001..=0.05).logarithmic(true).text("Spot Size"));
+            ui.add(egui::Slider::new(&mut spot.feather, 0.0..=1.0).text("Spot Feather"));
+        }
+        ui.horizontal(|ui| {
+            if ui.add_enabled(self.selected.is_some(), egui::Button::new("Remove Spot")).clicked()
+                && let Some(i) = self.selected.take()
+            {
+                healing.spots.remove(i);
+            }
+            if ui.add_enabled(!healing.spots.is_empty(), egui::Button::new("Clear All")).clicked() {
+                healing.spots.clear();
+                self.selected = None;
+            }
+        });
+
+        egui::CollapsingHeader::new("Detect Dust").id_salt("detect_dust").show(ui, |ui| {
+            ui.add(egui::Slider::new(&mut self.dust.sensitivity, 0.0..=1.0).text("Sensitivity"));
+            ui.add(egui::Slider::new(&mut self.dust.max_size, 2.0..=60.0).suffix(" px").text("Max Size"));
+            ui.horizontal(|ui| {
+                if ui.button("Detect").clicked() {
+                    let unit = source[0].max(source[1]) as f32;
+                    self.candidates = detect_dust(&self.search, &self.dust, unit);
+                    // Found defects are reviewed on the viewer
+                    self.active = true;
+                }
+                let accepted = self.candidates.iter().filter(|c| c.accepted).count();
+                let room = MAX_HEAL_SPOTS.saturating_sub(healing.spots.len());
+                if ui.add_enabled(accepted > 0 && room > 0, egui::Button::new("Heal")).clicked() {
+                    self.heal_candidates(healing, source);
+                }
+                if ui.add_enabled(!self.candidates.is_empty(), egui::Button::new("Discard")).clicked() {
+                    self.candidates.clear();
+                }
+            });
+            if !self.candidates.is_empty() {
+                let accepted = self.candidates.iter().filter(|c| c.accepted).count();
+                ui.label(format!("{} found, {} to heal. Click one to leave it out.", self.candidates.len(), accepted));
+            }
+        });
+    }
+
+    /// Turns the accepted dust candidates into heal spots, strongest first while there's room
+    fn heal_candidates(&mut self, healing: &mut SpotHealing, source: [u32; 2]) {
+        let unit = source[0].max(source[1]) as f32;
+        let room = MAX_HEAL_SPOTS.saturating_sub(healing.spots.len());
+        for candidate in self.candidates.drain(..).filter(|c| c.accepted).take(room) {
+            let offset = self.search.find_source(&candidate.points, candidate.radius, unit);
+            healing.spots.push(HealSpot {
+                points: candidate.points,
+                radius: candidate.radius,
+                feather: self.feather,
+                offset,
+                mode: HealMode::Heal,
+            });
+        }
+        self.selected = None;
+    }
+
+    /// Dust candidate under a screen position
+    fn candidate_at(&self, mapping: &FrameMapping, pos: Pos2) -> Option<usize> {
+        let p = mapping.to_mask(pos);
+        let reach = SPOT_REACH / mapping.frame.width() * mapping.output[0] / mapping.unit;
+        self.candidates.iter().position(|c| path_distance(&c.points, p) < c.radius + reach)
+    }
+
+    /// Spot under a screen position, the selected one first. Grabbing near the source
+    /// patch of the selected spot moves the patch.
+    fn spot_at(&self, mapping: &FrameMapping, healing: &SpotHealing, pos: Pos2) -> Option<(usize, SpotHandle)> {
+        let p = mapping.to_mask(pos);
+        let reach = SPOT_REACH / mapping.frame.width() * mapping.output[0] / mapping.unit;
+        let hit = |points: &[[f32; 2]], radius: f32| path_distance(points, p) < radius + reach;
+        if let Some(i) = self.selected
+            && let Some(spot) = healing.spots.get(i)
+        {
+            if hit(&spot.points, spot.radius) {
+                return Some((i, SpotHandle::Target));
+            }
+            if hit(&spot.source_points().collect::<Vec<_>>(), spot.radius) {
+                return Some((i, SpotHandle::Source));
+            }
+        }
+        healing
+            .spots
+            .iter()
+            .rposition(|spot| hit(&spot.points, spot.radius))
+            .map(|i| (i, SpotHandle::Target))
+    }
+
+    /// Spot handles and brushing on top of the viewer. `frame` is the screen rect of the
+    /// cropped image, spots aren't edited while the crop tool is open.
+    pub fn ui(&mut self, ui: &egui::Ui, response: &egui::Response, frame: Rect, healing: &mut SpotHealing, geometry: &Geometry, source: [u32; 2]) {
+        if !self.active {
+            return;
+        }
+        let mapping = FrameMapping::new(geometry, source, false, frame);
+        let pointer = response.interact_pointer_pos();
+        let room = healing.spots.len() < MAX_HEAL_SPOTS;
+
+        if response.drag_started()
+            && let Some(pos) = pointer
+        {
+            self.drag = match self.spot_at(&mapping, healing, pos) {
+                Some((index, handle)) => {
+                    self.selected = Some(index);
+                    Some(SpotDrag::Move { index, handle, start_spot: healing.spots[index].clone(), start_pos: mapping.to_mask(pos) })
+                }
+                None if room => Some(SpotDrag::Paint(vec![mapping.to_mask(pos)])),
+                None => None,
+            };
+        }
+        if response.dragged()
+            && let Some(pos) = pointer
+        {
+            let p = mapping.to_mask(pos);
+            match &mut self.drag {
+                Some(SpotDrag::Move { index, handle, start_spot, start_pos }) => {
+                    if let Some(spot) = healing.spots.get_mut(*index) {
+                        let delta = sub(p, *start_pos);
+                        match handle {
+                            SpotHandle::Target => {
+                                spot.points = start_spot.points.iter().map(|&q| add(q, delta)).collect();
+                            }
+                            SpotHandle::Source => spot.offset = add(start_spot.offset, delta),
+                        }
+                    }
+                }
+                Some(SpotDrag::Paint(points)) => {
+                    let last = points.last().copied().unwrap_or(p);
+                    let [dx, dy] = sub(p, last);
+                    if dx.hypot(dy) > self.radius * PATH_SPACING {
+                        points.push(p);
+                    }
+                }
+                None => {}
+            }
+        }
+        if response.drag_stopped()
+            && let Some(SpotDrag::Paint(points)) = self.drag.take()
+        {
+            self.add_spot(healing, points, source);
+        }
+        if response.clicked()
+            && let Some(pos) = pointer
+        {
+            // While reviewing detected dust, clicks decide on the candidates first
+            if let Some(i) = self.candidate_at(&mapping, pos) {
+                self.candidates[i].accepted = !self.candidates[i].accepted;
+                self.paint(ui, &mapping, healing, response.hover_pos());
+                return;
+            }
+            match self.spot_at(&mapping, healing, pos) {
+                Some((index, _)) => self.selected = Some(index),
+                None if room => self.add_spot(healing, vec![mapping.to_mask(pos)], source),
+                None => {}
+            }
+        }
+        if let Some(i) = self.selected
+            && ui.input(|input| input.key_pressed(egui::Key::Delete) || input.key_pressed(egui::Key::Backspace))
+            && !ui.ctx().wants_keyboard_input()
+        {
+            healing.spots.remove(i);
+            self.selected = None;
+        }
+
+        if response.hover_pos().is_some_and(|pos| self.spot_at(&mapping, healing, pos).is_some()) {
+            ui.ctx().set_cursor_icon(egui::CursorIcon::Grab);
+        }
+        self.paint(ui, &mapping, healing, response.hover_pos());
+    }
+
+    /// New spot over a defect, with its source patch found automatically
+    fn add_spot(&mut self, healing: &mut SpotHealing, points: Vec<[f32; 2]>, source: [u32; 2]) {
+        let unit = source[0].max(source[1]) as f32;
+        let offset = self.search.find_source(&points, self.radius, unit);
+        healing.spots.push(HealSpot { points, radius: self.radius, feather: self.feather, offset, mode: self.mode });
+        self.selected = Some(healing.spots.len() - 1);
+    }
+
+    fn paint(&self, ui: &egui::Ui, mapping: &FrameMapping, healing: &SpotHealing, hover: Option<Pos2>) {
+        let painter = ui.painter_at(mapping.frame);
+        let to_points = |radius: f32| radius * mapping.unit / mapping.output[0] * mapping.frame.width();
+        let shadow = Stroke::new(3.0, Color32::from_black_alpha(160));
+        let outline = |points: &[Pos2], radius: f32, color: Color32| {
+            for stroke in [shadow, Stroke::new(1.5, color)] {
+                if points.len() > 1 {
+                    painter.add(Shape::line(points.to_vec(), stroke));
+                }
+                painter.circle_stroke(points[0], radius, stroke);
+                if let Some(&last) = points.last().filter(|_| points.len() > 1) {
+                    painter.circle_stroke(last, radius, stroke);
+                }
+            }
+        };
+
+        for (i, spot) in healing.spots.iter().enumerate() {
+            let radius = to_points(spot.radius);
+            let target: Vec<Pos2> = spot.points.iter().map(|&p| mapping.to_screen(p)).collect();
+            if self.selected != Some(i) {
+                outline(&target, radius, Color32::from_gray(200));
+                continue;
+            }
+            let patch: Vec<Pos2> = spot.source_points().map(|p| mapping.to_screen(p)).collect();
+            painter.line_segment([target[0], patch[0]], Stroke::new(1.0, Color32::from_white_alpha(160)));
+            outline(&patch, radius, Color32::from_rgb(120, 200, 255));
+            outline(&target, radius, Color32::from_rgb(255, 200, 0));
+        }
+
+        for candidate in &self.candidates {
+            let path: Vec<Pos2> = candidate.points.iter().map(|&p| mapping.to_screen(p)).collect();
+            let color = if candidate.accepted { Color32::from_rgb(255, 60, 60) } else { Color32::from_gray(120) };
+            outline(&path, to_points(candidate.radius), color);
+        }
+
+        if let Some(SpotDrag::Paint(points)) = &self.drag {
+            let path: Vec<Pos2> = points.iter().map(|&p| mapping.to_screen(p)).collect();
+            outline(&path, to_points(self.radius), Color32::WHITE);
+        } else if let Some(pos) = hover {
+            outline(&[pos], to_points(self.radius), Color32::from_white_alpha(180));
+        }
+    }
+}
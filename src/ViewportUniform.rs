@@ -6,4 +6,41 @@ pub struct ViewportUniform {
     pub zoom: f32,
     pub _pad0: f32,
     pub pan: [f32; 2],           // normalized pan
+    pub overlay_flags: u32,      // OVERLAY_* bits
+    pub output_gamut: u32,       // index into OUTPUT_GAMUT_NAMES
+    pub compare_mode: u32,       // COMPARE_* mode
+    pub split_position: f32,     // 0..1 across the image, for COMPARE_SPLIT
 }
+
+pub const OVERLAY_HIGHLIGHT_CLIPPING: u32 = 1;
+pub const OVERLAY_SHADOW_CLIPPING: u32 = 2;
+pub const OVERLAY_GAMUT: u32 = 4;
+
+pub const COMPARE_OFF: u32 = 0;
+pub const COMPARE_SPLIT: u32 = 1;
+pub const COMPARE_SIDE_BY_SIDE: u32 = 2;
+pub const COMPARE_ORIGINAL: u32 = 3;
+
+// Must match the gamut matrices in shader.wgsl
+pub const OUTPUT_GAMUT_NAMES: [&str; 4] = ["sRGB", "Display P3", "Adobe RGB", "Rec.2020"];
+
+/// Viewer-only display options. They only affect the on-screen render, never exports.
+#[derive(Copy, Clone)]
+pub struct ViewOptions {
+    pub overlay_flags: u32,
+    pub output_gamut: u32,
+    pub compare_mode: u32,
+    pub split_position: f32,
+}
+
+impl Default for ViewOptions {
+    fn default() -> Self {
+        Self {
+            overlay_flags: 0,
+            output_gamut: 0,
+            compare_mode: COMPARE_OFF,
+            split_position: 0.5,
+        }
+    }
+}
+
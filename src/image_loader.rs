@@ -1,9 +1,97 @@
 use std::ffi::CString;
 use std::slice;
-use image::ImageReader;
+use image::{DynamicImage, ImageDecoder, ImageReader};
+use crate::Geometry::Orientation;
 use crate::{libraw_close, libraw_dcraw_clear_mem, libraw_dcraw_make_mem_image, libraw_dcraw_process, libraw_init, libraw_open_file, libraw_unpack};
 
-pub fn load_image_to_linear_rgb(path: &String) -> (u32, u32, Vec<[f32; 3]>) {
+pub struct DecodedImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[f32; 3]>,
+    /// How the camera says the pixels should be turned, left for the geometry stage to apply
+    pub orientation: Orientation,
+    pub lens: Option<LensInfo>,
+}
+
+/// Lens details from the file's metadata, for the lensfun lookup
+#[derive(Debug, Clone, Default)]
+pub struct LensInfo {
+    pub make: String,
+    pub model: String,
+    /// Millimetres, 0 when unknown
+    pub focal_length: f32,
+    pub aperture: f32,
+}
+
+/// NUL terminated C string from a LibRaw char array
+fn c_chars_to_string(chars: &[std::os::raw::c_char]) -> String {
+    let bytes: Vec<u8> = chars.iter().take_while(|&&c| c != 0).map(|&c| c as u8).collect();
+    String::from_utf8_lossy(&bytes).trim().to_string()
+}
+
+/// Reads the lens tags out of a raw EXIF block (a TIFF header and IFDs), as handed
+/// out by the image crate. Only looks at IFD0 and the Exif sub-IFD.
+fn lens_info_from_exif(exif: &[u8]) -> Option<LensInfo> {
+    let little_endian = match exif.get(0..2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None,
+    };
+    let u16_at = |offset: usize| -> Option<u16> {
+        let b = exif.get(offset..offset + 2)?;
+        Some(if little_endian { u16::from_le_bytes([b[0], b[1]]) } else { u16::from_be_bytes([b[0], b[1]]) })
+    };
+    let u32_at = |offset: usize| -> Option<u32> {
+        let b = exif.get(offset..offset + 4)?;
+        let b = [b[0], b[1], b[2], b[3]];
+        Some(if little_endian { u32::from_le_bytes(b) } else { u32::from_be_bytes(b) })
+    };
+
+    // (tag, type, count, offset of the value or of the pointer to it)
+    let entries = |ifd: usize| -> Vec<(u16, u16, u32, usize)> {
+        let count = u16_at(ifd).unwrap_or(0) as usize;
+        (0..count)
+            .filter_map(|i| {
+                let entry = ifd + 2 + i * 12;
+                Some((u16_at(entry)?, u16_at(entry + 2)?, u32_at(entry + 4)?, entry + 8))
+            })
+            .collect()
+    };
+    let ascii = |count: u32, value: usize| -> Option<String> {
+        let start = if count > 4 { u32_at(value)? as usize } else { value };
+        let bytes = exif.get(start..start + count as usize)?;
+        let text: Vec<u8> = bytes.iter().copied().take_while(|&b| b != 0).collect();
+        Some(String::from_utf8_lossy(&text).trim().to_string())
+    };
+    let rational = |value: usize| -> Option<f32> {
+        let start = u32_at(value)? as usize;
+        let denominator = u32_at(start + 4)?;
+        (denominator != 0).then(|| u32_at(start).map(|n| n as f32 / denominator as f32))?
+    };
+
+    const ASCII: u16 = 2;
+    let mut info = LensInfo::default();
+    let mut exif_ifd = None;
+    for (tag, kind, count, value) in entries(u32_at(4)? as usize) {
+        match tag {
+            0x010F if kind == ASCII => info.make = ascii(count, value).unwrap_or_default(),
+            0x8769 => exif_ifd = u32_at(value).map(|o| o as usize),
+            _ => {}
+        }
+    }
+    for (tag, kind, count, value) in entries(exif_ifd?) {
+        match tag {
+            0x829D => info.aperture = rational(value).unwrap_or(0.0),
+            0x920A => info.focal_length = rational(value).unwrap_or(0.0),
+            0xA433 if kind == ASCII => info.make = ascii(count, value).unwrap_or(info.make),
+            0xA434 if kind == ASCII => info.model = ascii(count, value).unwrap_or_default(),
+            _ => {}
+        }
+    }
+    (!info.model.is_empty()).then_some(info)
+}
+
+pub fn load_image_to_linear_rgb(path: &String) -> DecodedImage {
     let lower = path.to_lowercase();
     let is_raw = lower.ends_with(".dng") || lower.ends_with(".cr2") ||
         lower.ends_with(".nef") || lower.ends_with(".arw") ||
@@ -33,6 +121,19 @@ pub fn load_image_to_linear_rgb(path: &String) -> (u32, u32, Vec<[f32; 3]>) {
 
             // 4. CONFIGURE PARAMS (Critical for Film Emulation)
 
+            // Keep the sensor orientation, the geometry stage turns the image instead
+            let orientation = Orientation::from_libraw_flip((*raw_data).sizes.flip);
+            (*raw_data).params.user_flip = 0;
+
+            let lens_model = c_chars_to_string(&(*raw_data).lens.Lens);
+            let lens_make = c_chars_to_string(&(*raw_data).lens.LensMake);
+            let lens = (!lens_model.is_empty()).then(|| LensInfo {
+                make: if lens_make.is_empty() { c_chars_to_string(&(*raw_data).idata.make) } else { lens_make },
+                model: lens_model,
+                focal_length: (*raw_data).other.focal_len,
+                aperture: (*raw_data).other.aperture,
+            });
+
             // Disable Gamma Curve (Set to 1.0 linear)
             (*raw_data).params.gamm[0] = 1.0;
             (*raw_data).params.gamm[1] = 1.0;
@@ -87,13 +188,29 @@ pub fn load_image_to_linear_rgb(path: &String) -> (u32, u32, Vec<[f32; 3]>) {
             libraw_dcraw_clear_mem(processed);
             libraw_close(raw_data);
 
-            return (width, height, out_pixels);
+            return DecodedImage {
+                width,
+                height,
+                pixels: out_pixels,
+                orientation,
+                lens,
+            };
         }
     }
 
     // Fallback for Standard Images
     println!("Loading Standard Image via image crate...");
-    let img = ImageReader::open(path).unwrap().decode().unwrap().to_rgb8();
+    let mut decoder = ImageReader::open(path).unwrap().with_guessed_format().unwrap().into_decoder().unwrap();
+    let orientation = decoder
+        .orientation()
+        .map(|o| Orientation::from_exif(o.to_exif()))
+        .unwrap_or_default();
+    let lens = decoder
+        .exif_metadata()
+        .ok()
+        .flatten()
+        .and_then(|exif| lens_info_from_exif(&exif));
+    let img = DynamicImage::from_decoder(decoder).unwrap().to_rgb8();
     let (w, h) = img.dimensions();
     let mut out = Vec::with_capacity((w * h) as usize);
 
@@ -104,5 +221,84 @@ pub fn load_image_to_linear_rgb(path: &String) -> (u32, u32, Vec<[f32; 3]>) {
             (pixel[2] as f32 / 255.0).powf(2.2),
         ]);
     }
-    (w, h, out)
-}
\ No newline at end of file
+    DecodedImage {
+        width: w,
+        height: h,
+        pixels: out,
+        orientation,
+        lens,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A TIFF header, IFD0 with the make and a pointer to the Exif IFD, and the Exif IFD
+    /// with aperture, focal length and lens model, in either byte order
+    fn exif_block(little_endian: bool) -> Vec<u8> {
+        let mut out = Vec::new();
+        let u16_bytes = |v: u16| if little_endian { v.to_le_bytes() } else { v.to_be_bytes() };
+        let u32_bytes = |v: u32| if little_endian { v.to_le_bytes() } else { v.to_be_bytes() };
+        let entry = |out: &mut Vec<u8>, tag: u16, kind: u16, count: u32, value: u32| {
+            out.extend(u16_bytes(tag));
+            out.extend(u16_bytes(kind));
+            out.extend(u32_bytes(count));
+            out.extend(u32_bytes(value));
+        };
+
+        out.extend(if little_endian { b"II" } else { b"MM" });
+        out.extend(u16_bytes(42));
+        out.extend(u32_bytes(8));
+
+        // IFD0 at 8, the Exif IFD at 38, values from 80
+        let make = b"Canon\0";
+        let model = b"EF50mm f/1.8 STM\0";
+        out.extend(u16_bytes(2));
+        entry(&mut out, 0x010F, 2, make.len() as u32, 80);
+        entry(&mut out, 0x8769, 4, 1, 38);
+        out.extend(u32_bytes(0));
+
+        out.extend(u16_bytes(3));
+        entry(&mut out, 0x829D, 5, 1, 86);
+        entry(&mut out, 0x920A, 5, 1, 94);
+        entry(&mut out, 0xA434, 2, model.len() as u32, 102);
+        out.extend(u32_bytes(0));
+
+        assert_eq!(out.len(), 80);
+        out.extend(make);
+        for v in [18, 10, 50, 1] {
+            out.extend(u32_bytes(v));
+        }
+        out.extend(model);
+        out
+    }
+
+    #[test]
+    fn reads_lens_tags_in_both_byte_orders() {
+        for little_endian in [true, false] {
+            let info = lens_info_from_exif(&exif_block(little_endian)).expect("lens info");
+            assert_eq!(info.make, "Canon");
+            assert_eq!(info.model, "EF50mm f/1.8 STM");
+            assert_eq!(info.aperture, 1.8);
+            assert_eq!(info.focal_length, 50.0);
+        }
+    }
+
+    #[test]
+    fn truncated_exif_has_no_lens() {
+        for little_endian in [true, false] {
+            let block = exif_block(little_endian);
+            for len in 0..block.len() {
+                assert!(lens_info_from_exif(&block[..len]).is_none(), "read a lens from {len} bytes");
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_byte_order_has_no_lens() {
+        let mut block = exif_block(true);
+        block[..2].copy_from_slice(b"XX");
+        assert!(lens_info_from_exif(&block).is_none());
+    }
+}
@@ -12,6 +12,35 @@ mod GpuImageRenderPipeline;
 mod ImageRenderResources;
 mod GpuImageComputePipeline;
 mod ViewportUniform;
+mod ColorWheel;
+mod GpuHistogramPipeline;
+mod GpuReadback;
+mod HistogramView;
+mod GpuScopesPipeline;
+mod ScopesView;
+mod PixelProbeView;
+mod Geometry;
+mod GpuGeometryPipeline;
+mod CropTool;
+mod LensCorrection;
+mod Lensfun;
+mod ProcessingState;
+mod LocalAdjustments;
+mod MaskTool;
+mod BrushMask;
+mod GpuBrushMaskPipeline;
+mod GpuSharpenPipeline;
+mod GpuDenoisePipeline;
+mod GpuLocalContrastPipeline;
+mod GpuLocalAdjustmentsPipeline;
+mod GpuPipelines;
+mod SpotHealing;
+mod SpotTool;
+mod DustDetection;
+mod NoiseReduction;
+mod LocalContrast;
+mod ProcessingGraph;
+mod AdjustmentStack;
 
 use eframe::{egui};
 use std::env;
@@ -20,7 +49,18 @@ include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
 
 fn main() {
 
-    let native_options = eframe::NativeOptions::default();
+    let mut native_options = eframe::NativeOptions::default();
+    // Ask for a pipeline cache where the adapter has one, see GpuPipelines
+    let setup = eframe::egui_wgpu::WgpuSetupCreateNew::default();
+    let device_descriptor = setup.device_descriptor.clone();
+    native_options.wgpu_options.wgpu_setup = eframe::egui_wgpu::WgpuSetup::CreateNew(eframe::egui_wgpu::WgpuSetupCreateNew {
+        device_descriptor: std::sync::Arc::new(move |adapter| {
+            let mut descriptor = device_descriptor(adapter);
+            descriptor.required_features |= adapter.features() & eframe::wgpu::Features::PIPELINE_CACHE;
+            descriptor
+        }),
+        ..setup
+    });
     eframe::run_native("My egui App", native_options,
                        Box::new(|cc| Ok(Box::new(FilmEmulator::FilmEmulator::new(cc).expect("Error starting up")))))
         .expect("TODO: panic message");
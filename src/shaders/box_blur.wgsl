@@ -0,0 +1,36 @@
+// One direction of a box blur over every layer of a texture array. Run twice, across then
+// down, for the local means the guided filter needs.
+// Must match BlurUniform in GpuBrushMaskPipeline.rs
+
+struct BlurUniform {
+    direction: vec2<i32>, // (1, 0) across or (0, 1) down
+    radius: i32,          // Pixels either side
+    _pad: i32,
+}
+
+@group(0) @binding(0)
+var input_texture: texture_2d_array<f32>;
+
+@group(0) @binding(1)
+var output_texture: texture_storage_2d_array<rgba16float, write>;
+
+@group(0) @binding(2)
+var<uniform> blur: BlurUniform;
+
+@compute @workgroup_size(16, 16)
+fn blur_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let dims = vec2<i32>(textureDimensions(input_texture));
+    let coords = vec2<i32>(global_id.xy);
+    if (coords.x >= dims.x || coords.y >= dims.y) {
+        return;
+    }
+    let layer = i32(global_id.z);
+
+    // Edge pixels repeat, so the border isn't pulled towards black
+    var sum = vec4<f32>(0.0);
+    for (var i = -blur.radius; i <= blur.radius; i++) {
+        let p = clamp(coords + blur.direction * i, vec2<i32>(0), dims - 1);
+        sum += textureLoad(input_texture, p, layer, 0);
+    }
+    textureStore(output_texture, coords, layer, sum / f32(2 * blur.radius + 1));
+}
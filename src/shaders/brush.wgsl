@@ -0,0 +1,124 @@
+// Brush masks: rasterises the painted strokes of every brush layer into the mask texture.
+// Runs at mask resolution, only when the strokes or the framing change.
+// Also writes the guide statistics the edge refinement (guided_filter.wgsl) starts from.
+// Must match BrushUniform and StrokeGpu in BrushMask.rs
+
+struct BrushUniform {
+    // Output pixel -> source pixel as a 2x3 affine transform, one row each
+    to_source_x: vec4<f32>,
+    to_source_y: vec4<f32>,
+    // Output pixels per mask pixel on x and y, mask units per source pixel, unused
+    scale: vec4<f32>,
+    counts: vec4<u32>, // Stroke count, unused
+}
+
+struct Stroke {
+    bounds: vec4<f32>, // Min x, min y, max x, max y including the radius, mask units
+    params: vec4<f32>, // Radius, feather, flow, erase
+    range: vec4<u32>,  // First point, point count, mask slot, unused
+}
+
+@group(0) @binding(0)
+var<uniform> brush: BrushUniform;
+
+@group(0) @binding(1)
+var<storage, read> strokes: array<Stroke>;
+
+@group(0) @binding(2)
+var<storage, read> points: array<vec2<f32>>;
+
+// The framed image from the geometry pass, the guide for edge refinement
+@group(0) @binding(3)
+var guide_source: texture_2d<f32>;
+
+// Coverage, one local adjustment slot per channel, four per layer
+@group(0) @binding(4)
+var mask_texture: texture_storage_2d_array<rgba8unorm, write>;
+
+// Guide (I) and its square, first layer only
+@group(0) @binding(5)
+var guide_texture: texture_storage_2d_array<rgba16float, write>;
+
+// Guide times each mask channel
+@group(0) @binding(6)
+var product_texture: texture_storage_2d_array<rgba16float, write>;
+
+const LUMA_WEIGHTS = vec3<f32>(0.299, 0.587, 0.114);
+
+// Edges are found in gamma encoded luma, closer to what the eye calls an edge.
+// Must match guide_luma in local_adjustments.wgsl
+fn guide_luma(rgb: vec3<f32>) -> f32 {
+    return pow(clamp(dot(rgb, LUMA_WEIGHTS), 0.0, 1.0), 1.0 / 2.2);
+}
+
+fn distance_to_segment(p: vec2<f32>, a: vec2<f32>, b: vec2<f32>) -> f32 {
+    let ab = b - a;
+    let t = clamp(dot(p - a, ab) / max(dot(ab, ab), 1e-12), 0.0, 1.0);
+    return length(p - (a + ab * t));
+}
+
+fn distance_to_stroke(p: vec2<f32>, stroke: Stroke) -> f32 {
+    let first = stroke.range.x;
+    var d = length(p - points[first]);
+    for (var i = 1u; i < stroke.range.y; i++) {
+        d = min(d, distance_to_segment(p, points[first + i - 1u], points[first + i]));
+    }
+    return d;
+}
+
+@compute @workgroup_size(16, 16)
+fn brush_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let dims = textureDimensions(mask_texture);
+    if (global_id.x >= dims.x || global_id.y >= dims.y) {
+        return;
+    }
+    let layer = global_id.z;
+
+    // Mask pixel -> output pixel -> source pixel -> mask units
+    let output_pos = vec3<f32>((vec2<f32>(global_id.xy) + 0.5) * brush.scale.xy, 1.0);
+    let source_pos = vec2<f32>(dot(brush.to_source_x.xyz, output_pos), dot(brush.to_source_y.xyz, output_pos));
+    let p = source_pos * brush.scale.z;
+
+    // --- STROKES ---
+    // In the order they were painted: painting covers what's left uncovered by the flow,
+    // erasing takes the same share away
+    var coverage = vec4<f32>(0.0);
+    for (var s = 0u; s < brush.counts.x; s++) {
+        let stroke = strokes[s];
+        if (stroke.range.z / 4u != layer) {
+            continue;
+        }
+        if (any(p < stroke.bounds.xy) || any(p > stroke.bounds.zw)) {
+            continue;
+        }
+        let radius = stroke.params.x;
+        let d = distance_to_stroke(p, stroke);
+        let inner = radius * (1.0 - max(stroke.params.y, 1e-3));
+        let amount = stroke.params.z * (1.0 - smoothstep(inner, radius, d));
+        let channel = stroke.range.z % 4u;
+        if (stroke.params.w != 0.0) {
+            coverage[channel] = coverage[channel] * (1.0 - amount);
+        } else {
+            coverage[channel] = coverage[channel] + (1.0 - coverage[channel]) * amount;
+        }
+    }
+    textureStore(mask_texture, vec2<i32>(global_id.xy), i32(layer), coverage);
+
+    // --- GUIDE ---
+    // A few samples across the output pixels this mask pixel covers
+    let guide_dims = vec2<f32>(textureDimensions(guide_source));
+    var luma = 0.0;
+    for (var j = 0; j < 2; j++) {
+        for (var i = 0; i < 2; i++) {
+            let offset = (vec2<f32>(f32(i), f32(j)) + 0.5) * 0.5;
+            let q = min((vec2<f32>(global_id.xy) + offset) * brush.scale.xy, guide_dims - 1.0);
+            luma += guide_luma(textureLoad(guide_source, vec2<i32>(q), 0).rgb);
+        }
+    }
+    luma *= 0.25;
+
+    if (layer == 0u) {
+        textureStore(guide_texture, vec2<i32>(global_id.xy), 0, vec4<f32>(luma, luma * luma, 0.0, 0.0));
+    }
+    textureStore(product_texture, vec2<i32>(global_id.xy), i32(layer), coverage * luma);
+}
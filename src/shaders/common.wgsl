@@ -0,0 +1,157 @@
+// What the passes after the geometry pass share: the edit's settings, and the output
+// transform the colour pass finishes with and range masks look through. Goes in front of
+// each of their own sources, see GpuImageComputePipeline.rs.
+
+// Must match ImageControls in ImageControls.rs
+struct ImageControls {
+    exposure: f32,   // Stops (e.g. -2.0 to +2.0)
+    contrast: f32,   // Factor (1.0 = Neutral, 1.2 = High Contrast)
+    saturation: f32, // Factor (1.0 = Neutral, 0.0 = B&W)
+    brightness: f32,
+    highlights: f32,
+    shadows: f32,
+    vibrance: f32,   // -1.0 to +1.0, 0.0 = Neutral
+
+    // Colour wheels: hue (degrees), saturation (0-1), luminance offset, unused
+    shadow_wheel: vec4<f32>,
+    midtone_wheel: vec4<f32>,
+    highlight_wheel: vec4<f32>,
+    grading_balance: f32,
+    grading_mode: u32, // 0 = Lift/Gamma/Gain wheels, 1 = Split toning
+
+    split_shadow_hue: f32,
+    split_shadow_saturation: f32,
+    split_highlight_hue: f32,
+    split_highlight_saturation: f32,
+    split_balance: f32,
+
+    // HSL mixer, one value per band (reds, oranges, yellows, greens, aquas, blues, purples, magentas)
+    hsl_hue: array<vec4<f32>, 2>,        // hue shift in degrees
+    hsl_saturation: array<vec4<f32>, 2>, // -1 (grey) to +1
+    hsl_luminance: array<vec4<f32>, 2>,  // -1 (darker) to +1
+
+    // Black & white
+    bw_mixer: vec4<f32>, // red, green, blue weights, unused
+    bw_enabled: u32,
+    bw_filter: u32,      // index into BW_FILTERS
+    bw_toning: u32,      // 0 = None, 1 = Selenium, 2 = Sepia, 3 = Cyanotype
+    bw_toning_amount: f32,
+
+    // Highlight roll-off / display transform
+    tone_mapper: u32,  // 0 = Clip, 1 = Filmic, 2 = ACES, 3 = AgX
+    white_point: f32,  // Scene-linear value that maps to display white
+
+    // Creative vignette
+    vignette_amount: f32,     // Stops at the corners, negative darkens
+    vignette_midpoint: f32,   // 0 (centre) to 1, where the falloff starts
+    vignette_roundness: f32,  // -1 (follows the frame) to +1 (circle)
+    vignette_feather: f32,    // 0 (hard edge) to 1
+    vignette_highlights: f32, // 0 to 1, how much bright areas resist darkening
+
+    // Capture sharpening, see sharpen.wgsl
+    sharpen_amount: f32,  // 0 (off) to 3
+    sharpen_radius: f32,  // Blur sigma in pixels, the blur itself is in gaussian_blur.wgsl
+    sharpen_detail: f32,  // 0 (no overshoot past the local range) to 1
+    sharpen_masking: f32, // 0 (everywhere) to 1 (strong edges only)
+    sharpen_preview: u32, // 0 = None, 1 = Edge mask, 2 = Sharpened luma
+
+    // Local contrast, -1 to +1, see local_contrast.wgsl
+    texture: f32,
+    clarity: f32,
+    dehaze: f32,
+
+    mask_overlay: u32, // 1 while the selected local adjustment's mask is tinted
+}
+
+const LUMA_WEIGHTS = vec3<f32>(0.299, 0.587, 0.114);
+
+fn rgb_to_hsv(c: vec3<f32>) -> vec3<f32> {
+    let max_c = max(c.r, max(c.g, c.b));
+    let delta = max_c - min(c.r, min(c.g, c.b));
+    var h = 0.0;
+    if (delta > 1e-5) {
+        if (max_c == c.r) {
+            h = (c.g - c.b) / delta;
+            if (h < 0.0) { h += 6.0; }
+        } else if (max_c == c.g) {
+            h = (c.b - c.r) / delta + 2.0;
+        } else {
+            h = (c.r - c.g) / delta + 4.0;
+        }
+    }
+    var s = 0.0;
+    if (max_c > 1e-5) {
+        s = delta / max_c;
+    }
+    return vec3<f32>(h * 60.0, s, max_c);
+}
+
+// John Hable's filmic curve (Uncharted 2)
+fn hable(x: vec3<f32>) -> vec3<f32> {
+    let a = 0.15;
+    let b = 0.50;
+    let c = 0.10;
+    let d = 0.20;
+    let e = 0.02;
+    let f = 0.30;
+    return ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f;
+}
+
+// Stephen Hill's fit of the ACES RRT + sRGB ODT, linear sRGB in and out
+fn aces_fitted(color: vec3<f32>) -> vec3<f32> {
+    // sRGB => XYZ => D65_2_D60 => AP1 => RRT_SAT
+    let aces_input = mat3x3<f32>(
+        vec3<f32>(0.59719, 0.07600, 0.02840),
+        vec3<f32>(0.35458, 0.90834, 0.13383),
+        vec3<f32>(0.04823, 0.01566, 0.83777),
+    );
+    // ODT_SAT => XYZ => D60_2_D65 => sRGB
+    let aces_output = mat3x3<f32>(
+        vec3<f32>(1.60475, -0.10208, -0.00327),
+        vec3<f32>(-0.53108, 1.10813, -0.07276),
+        vec3<f32>(-0.07367, -0.00605, 1.07602),
+    );
+    let v = aces_input * color;
+    let rrt_odt = (v * (v + 0.0245786) - 0.000090537) / (v * (0.983729 * v + 0.4329510) + 0.238081);
+    return aces_output * rrt_odt;
+}
+
+// Minimal AgX (Troy Sobotka's base look, polynomial sigmoid fit by Benjamin Wrensch).
+// `max_ev` sets how many stops above middle grey reach white.
+fn agx(color: vec3<f32>, max_ev: f32) -> vec3<f32> {
+    let agx_inset = mat3x3<f32>(
+        vec3<f32>(0.842479062253094, 0.0423282422610123, 0.0423756549057051),
+        vec3<f32>(0.0784335999999992, 0.878468636469772, 0.0784336),
+        vec3<f32>(0.0792237451477643, 0.0791661274605434, 0.879142973793104),
+    );
+    let agx_outset = mat3x3<f32>(
+        vec3<f32>(1.19687900512017, -0.0528968517574562, -0.0529716355144438),
+        vec3<f32>(-0.0980208811401368, 1.15190312990417, -0.0980434501171241),
+        vec3<f32>(-0.0990297440797205, -0.0989611768448433, 1.15107367264116),
+    );
+    let min_ev = -12.47393;
+
+    var v = agx_inset * max(color, vec3<f32>(1e-10));
+    v = clamp((log2(v / 0.18) - min_ev) / (max_ev - min_ev), vec3<f32>(0.0), vec3<f32>(1.0));
+
+    // Sigmoid contrast curve
+    let v2 = v * v;
+    let v4 = v2 * v2;
+    v = 15.5 * v4 * v2 - 40.14 * v4 * v + 31.96 * v4 - 6.868 * v2 * v + 0.4298 * v2 + 0.1191 * v - 0.00232;
+
+    // The curve produces display-encoded values, return to linear for the gamma stage
+    return pow(max(agx_outset * v, vec3<f32>(0.0)), vec3<f32>(2.2));
+}
+
+// Rolls scene-linear highlights off towards display white instead of hard clipping at 1.0.
+// The curves are normalised so `white_point` lands exactly on 1.0.
+fn tone_map(color: vec3<f32>, mode: u32, white_point: f32) -> vec3<f32> {
+    let x = max(color, vec3<f32>(0.0));
+    switch (mode) {
+        case 1u: { return hable(x * 2.0) / hable(vec3<f32>(white_point * 2.0)); }
+        case 2u: { return aces_fitted(x) / aces_fitted(vec3<f32>(white_point)); }
+        case 3u: { return agx(x, max(log2(white_point / 0.18), 1.0)); }
+        default: { return color / white_point; }
+    }
+}
+
@@ -48,24 +48,343 @@
 //
 //
 
-struct ImageControls {
-    exposure: f32,   // Stops (e.g. -2.0 to +2.0)
-    contrast: f32,   // Factor (1.0 = Neutral, 1.2 = High Contrast)
-    saturation: f32, // Factor (1.0 = Neutral, 0.0 = B&W)
-    brightness: f32,
-    highlights: f32,
-    shadows: f32,
+// The colour and tone pass, the last of the graph. ImageControls and the tone maps are in
+// common.wgsl, which goes in front of it.
+
+// One step of the adjustment stack. Must match StackStepUniform in AdjustmentStack.rs
+struct StackStep {
+    kind: u32,     // One of the STEP_ constants
+    opacity: f32,  // Blend of the step's result over its input
+    _pad: vec2<u32>,
+}
+
+// Must match AdjustmentStackUniform in AdjustmentStack.rs
+struct AdjustmentStack {
+    info: vec4<u32>, // Step count, first step after tone mapping, unused, unused
+    steps: array<StackStep, 32>,
 }
 
+// Step kinds, must match the Adjustment enum in AdjustmentStack.rs
+const STEP_EXPOSURE = 0u;
+const STEP_VIGNETTE = 1u;
+const STEP_BLACK_AND_WHITE = 2u;
+const STEP_TONE = 3u;
+const STEP_VIBRANCE = 4u;
+const STEP_HSL_MIXER = 5u;
+const STEP_TONING = 6u;
+const STEP_COLOR_GRADING = 7u;
+
+// Linear transmission of classic B&W lens filters: None, Yellow #8, Orange #21, Red #25, Green #11, Blue #47
+const BW_FILTERS = array<vec3<f32>, 6>(
+    vec3<f32>(1.0, 1.0, 1.0),
+    vec3<f32>(1.0, 0.95, 0.35),
+    vec3<f32>(1.0, 0.6, 0.1),
+    vec3<f32>(1.0, 0.15, 0.05),
+    vec3<f32>(0.45, 1.0, 0.35),
+    vec3<f32>(0.1, 0.3, 1.0),
+);
+
+
+// Centre hue of each HSL mixer band in degrees. Must match HSL_BAND_CENTERS in ImageControls.rs
+const HSL_BAND_CENTERS = array<f32, 8>(0.0, 30.0, 60.0, 120.0, 180.0, 240.0, 270.0, 300.0);
+
+// How far a fully saturated wheel pushes the colour. Kept small so the wheels stay subtle.
+const GRADE_STRENGTH = 0.3;
+
+// The only binding remade when the graph changes, the rest are in group 1
 @group(0) @binding(0)
 var input_texture: texture_2d<f32>;
 
-@group(0) @binding(1)
+@group(1) @binding(1)
 var output_texture: texture_storage_2d<rgba8unorm, write>;
 
-@group(0) @binding(2)
+@group(1) @binding(2)
 var<uniform> imageControls: ImageControls;
 
+// The edited colour in linear light before the output clamp. Only the viewer's clipping
+// and gamut overlays read it.
+@group(1) @binding(3)
+var working_texture: texture_storage_2d<rgba16float, write>;
+
+// The unedited input through the same output transform, for before/after comparison
+@group(1) @binding(4)
+var original_texture: texture_storage_2d<rgba8unorm, write>;
+
+// The framed image before noise reduction and the detail and local stages, what the
+// before view shows
+@group(1) @binding(5)
+var unprocessed_texture: texture_2d<f32>;
+
+// Order, opacity and on/off of the adjustments, see AdjustmentStack.rs
+@group(1) @binding(6)
+var<uniform> adjustment_stack: AdjustmentStack;
+
+// How much of the picture the selected local adjustment's mask covers, see local_adjustments.wgsl
+@group(1) @binding(7)
+var mask_overlay: texture_2d<f32>;
+
+// Fully saturated RGB for a hue in degrees
+fn hue_to_rgb(hue: f32) -> vec3<f32> {
+    let h = fract(hue / 360.0);
+    let k = abs(fract(vec3<f32>(h) + vec3<f32>(0.0, 2.0 / 3.0, 1.0 / 3.0)) * 6.0 - 3.0) - 1.0;
+    return clamp(k, vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+// Chroma-only offset towards a hue. Its luma is zero, so tinting doesn't change brightness.
+fn tint(hue: f32, saturation: f32) -> vec3<f32> {
+    let rgb = hue_to_rgb(hue);
+    return (rgb - dot(rgb, LUMA_WEIGHTS)) * saturation * GRADE_STRENGTH;
+}
+
+fn hsv_to_rgb(hsv: vec3<f32>) -> vec3<f32> {
+    return hsv.z * mix(vec3<f32>(1.0), hue_to_rgb(hsv.x), hsv.y);
+}
+
+struct BandBlend {
+    lower: u32,
+    upper: u32,
+    t: f32, // 0 = all lower band, 1 = all upper band
+}
+
+// The two mixer bands a hue sits between. The smoothstep gives a soft crossover so
+// neighbouring bands never produce a hard edge.
+fn hue_band_blend(hue: f32) -> BandBlend {
+    var centers = HSL_BAND_CENTERS;
+    var lower = 0u;
+    for (var i = 0u; i < 8u; i++) {
+        if (hue >= centers[i]) {
+            lower = i;
+        }
+    }
+    let upper = (lower + 1u) % 8u;
+    var span = centers[upper] - centers[lower];
+    if (upper == 0u) {
+        // Magentas wrap round to reds
+        span = 360.0 - centers[lower];
+    }
+    let t = smoothstep(0.0, 1.0, (hue - centers[lower]) / span);
+    return BandBlend(lower, upper, t);
+}
+
+fn band_value(bands: array<vec4<f32>, 2>, blend: BandBlend) -> f32 {
+    let lower = bands[blend.lower / 4u][blend.lower % 4u];
+    let upper = bands[blend.upper / 4u][blend.upper % 4u];
+    return mix(lower, upper, blend.t);
+}
+
+// OKLab (Björn Ottosson), from linear sRGB
+fn linear_srgb_to_oklab(c: vec3<f32>) -> vec3<f32> {
+    let l = 0.4122214708 * c.r + 0.5363325363 * c.g + 0.0514459929 * c.b;
+    let m = 0.2119034982 * c.r + 0.6806995451 * c.g + 0.1073969566 * c.b;
+    let s = 0.0883024619 * c.r + 0.2817188376 * c.g + 0.6299787005 * c.b;
+    let lms = pow(max(vec3<f32>(l, m, s), vec3<f32>(0.0)), vec3<f32>(1.0 / 3.0));
+    return vec3<f32>(
+        0.2104542553 * lms.x + 0.7936177850 * lms.y - 0.0040720468 * lms.z,
+        1.9779984951 * lms.x - 2.4285922050 * lms.y + 0.4505937099 * lms.z,
+        0.0259040371 * lms.x + 0.7827717662 * lms.y - 0.8086757660 * lms.z,
+    );
+}
+
+fn oklab_to_linear_srgb(lab: vec3<f32>) -> vec3<f32> {
+    let l_ = lab.x + 0.3963377774 * lab.y + 0.2158037573 * lab.z;
+    let m_ = lab.x - 0.1055613458 * lab.y - 0.0638541728 * lab.z;
+    let s_ = lab.x - 0.0894841775 * lab.y - 1.2914855480 * lab.z;
+    let l = l_ * l_ * l_;
+    let m = m_ * m_ * m_;
+    let s = s_ * s_ * s_;
+    return vec3<f32>(
+        4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s,
+        -1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s,
+        -0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s,
+    );
+}
+
+// Saturation boost that backs off for colours that are already saturated and for skin tones.
+// `color` is gamma encoded, the chroma change happens in OKLCh so hue and lightness hold steady.
+fn apply_vibrance(color: vec3<f32>, vibrance: f32, gamma: f32) -> vec3<f32> {
+    let lab = linear_srgb_to_oklab(pow(max(color, vec3<f32>(0.0)), vec3<f32>(gamma)));
+    let chroma = length(lab.yz);
+    let hue = degrees(atan2(lab.z, lab.y));
+
+    // sRGB tops out around 0.32 chroma in OKLab, so anything past ~0.25 is left almost untouched
+    let headroom = 1.0 - smoothstep(0.0, 0.25, chroma);
+
+    // Skin sits roughly between 30 and 80 degrees of OKLCh hue
+    let skin = smoothstep(20.0, 40.0, hue) * (1.0 - smoothstep(70.0, 90.0, hue));
+    var amount = vibrance * headroom;
+    if (vibrance > 0.0) {
+        amount = amount * (1.0 - 0.7 * skin);
+    }
+
+    let scale = max(1.0 + amount, 0.0);
+    let adjusted = vec3<f32>(lab.x, lab.yz * scale);
+    return pow(max(oklab_to_linear_srgb(adjusted), vec3<f32>(0.0)), vec3<f32>(1.0 / gamma));
+}
+
+// Toning tints for (shadows, highlights). The print's grey level blends between them, like a
+// toner that acts more strongly on the dense or the thin parts of the image.
+fn toning_tints(mode: u32) -> array<vec3<f32>, 2> {
+    switch (mode) {
+        // Selenium: cool purple-brown shadows, neutral highlights
+        case 1u: { return array<vec3<f32>, 2>(vec3<f32>(0.9, 0.78, 0.9), vec3<f32>(1.0, 1.0, 1.0)); }
+        // Sepia: warm brown throughout, strongest in the mids and highlights
+        case 2u: { return array<vec3<f32>, 2>(vec3<f32>(0.95, 0.8, 0.62), vec3<f32>(1.08, 0.98, 0.8)); }
+        // Cyanotype: Prussian blue shadows, pale blue highlights
+        case 3u: { return array<vec3<f32>, 2>(vec3<f32>(0.35, 0.6, 1.0), vec3<f32>(0.88, 0.96, 1.05)); }
+        default: { return array<vec3<f32>, 2>(vec3<f32>(1.0), vec3<f32>(1.0)); }
+    }
+}
+
+// Creative vignette mask, 0 in the middle rising to 1 at the corners. The input is the
+// cropped frame so the vignette always follows the crop rather than the original shot.
+fn vignette_mask(coords: vec2<i32>, dims: vec2<u32>) -> f32 {
+    let size = vec2<f32>(dims);
+    // -1..1 across the frame on both axes
+    var p = (vec2<f32>(coords) + 0.5) / size * 2.0 - 1.0;
+
+    // Positive roundness squeezes the long side back towards a circle
+    let roundness = imageControls.vignette_roundness;
+    let aspect = size.x / size.y;
+    if (roundness > 0.0) {
+        let circle = select(vec2<f32>(1.0, 1.0 / aspect), vec2<f32>(aspect, 1.0), aspect >= 1.0);
+        p = p * mix(vec2<f32>(1.0), circle, roundness);
+    }
+
+    // Negative roundness moves from an ellipse towards the frame's own rounded rectangle
+    let n = 2.0 + max(-roundness, 0.0) * 6.0;
+    let q = abs(p);
+    let d = pow(pow(q.x, n) + pow(q.y, n), 1.0 / n);
+
+    // The falloff starts at the midpoint and feather spreads it out towards the corners
+    let corner = length(vec2<f32>(1.0));
+    let start = imageControls.vignette_midpoint;
+    let end = start + imageControls.vignette_feather * (corner - start) + 0.001;
+    return smoothstep(start, end, d);
+}
+
+// Smooth shadow / midtone / highlight weights that always sum to 1.
+// Balance moves the crossover point: negative favours shadows, positive favours highlights.
+fn tonal_weights(luma: f32, balance: f32) -> vec3<f32> {
+    let pivot = clamp(0.5 + balance * 0.4, 0.1, 0.9);
+    let shadow = 1.0 - smoothstep(0.0, pivot, luma);
+    let highlight = smoothstep(pivot, 1.0, luma);
+    return vec3<f32>(shadow, 1.0 - shadow - highlight, highlight);
+}
+
+// One step of the adjustment stack on one pixel. Linear light steps get scene linear
+// colour, the rest gamma encoded colour after tone mapping. The result is blended
+// over the input by the step's opacity, so a duplicate can add a share of an effect again.
+fn apply_step(stack_step: StackStep, input: vec3<f32>, coords: vec2<i32>, dims: vec2<u32>) -> vec3<f32> {
+    let gamma = 2.2;
+    var color = input;
+    switch stack_step.kind {
+        case STEP_EXPOSURE: {
+            // Exposure
+            // We strictly use base-2 power for accurate camera stops
+            color = color * pow(2.0, imageControls.exposure);
+
+            // Brightness (Additive - Digital Offset)
+            // Typical Range: -0.5 to +0.5
+            color = color + imageControls.brightness;
+        }
+        // Creative Vignette
+        // In stops, so it behaves like real light falloff rather than a grey overlay
+        case STEP_VIGNETTE: {
+            if (imageControls.vignette_amount != 0.0) {
+                var stops = imageControls.vignette_amount * vignette_mask(coords, dims);
+                if (stops < 0.0) {
+                    // Highlight protection: bright areas keep most of their light
+                    let brightness = dot(color, LUMA_WEIGHTS) / imageControls.white_point;
+                    stops = stops * (1.0 - imageControls.vignette_highlights * smoothstep(0.25, 1.0, brightness));
+                }
+                color = color * exp2(stops);
+            }
+        }
+        // Black & White Conversion
+        // Lens filters and the channel mixer are multiplicative on light, so this happens in linear
+        case STEP_BLACK_AND_WHITE: {
+            if (imageControls.bw_enabled != 0u) {
+                var filters = BW_FILTERS;
+                let filtered = color * filters[min(imageControls.bw_filter, 5u)];
+                color = vec3<f32>(dot(filtered, imageControls.bw_mixer.rgb));
+            }
+        }
+        case STEP_TONE: {
+            // Contrast
+            // Pivot around 0.5 (Mid-gray in Gamma space)
+            // Formula: (Color - 0.5) * Contrast + 0.5
+            color = (color - 0.5) * imageControls.contrast + 0.5;
+
+            // Saturation (Luma-preserving-ish)
+            // We use the standard Rec.709 Luma coefficients
+            let luma = dot(color, vec3<f32>(0.299, 0.587, 0.114));
+            let luma_vec = vec3<f32>(luma);
+
+            let shadow_mask = (1.0 - luma) * (1.0 - luma);
+            let highlight_mask = luma * luma;
+
+            // Apply Shadows (Additive Lift)
+            // imageControls.shadows range: -0.5 (Crush) to +0.5 (Lift)
+            // We add light specifically to areas where shadow_mask is high
+            color = color + (vec3<f32>(imageControls.shadows) * shadow_mask);
+
+            // Apply Highlights (Multiplicative Gain)
+            // imageControls.highlights range: -1.0 (Recover) to +1.0 (Boost)
+            // We scale the brightness specifically where highlight_mask is high
+            color = color + (color * imageControls.highlights * highlight_mask);
+
+            // Interpolate between Grayscale (luma) and Color
+            color = mix(luma_vec, color, imageControls.saturation);
+        }
+        // Vibrance (Chroma-aware saturation)
+        case STEP_VIBRANCE: {
+            color = apply_vibrance(color, imageControls.vibrance, gamma);
+        }
+        // HSL Mixer
+        // Work in HSV so hue shifts and saturation changes stay within the pixel's own brightness.
+        // Everything is scaled by the pixel's saturation so neutrals are left alone.
+        case STEP_HSL_MIXER: {
+            var hsv = rgb_to_hsv(max(color, vec3<f32>(0.0)));
+            let blend = hue_band_blend(hsv.x);
+            hsv.x = hsv.x + band_value(imageControls.hsl_hue, blend) * hsv.y;
+            hsv.y = clamp(hsv.y * (1.0 + band_value(imageControls.hsl_saturation, blend)), 0.0, 1.0);
+            let hsl_luminance = band_value(imageControls.hsl_luminance, blend) * hsv.y;
+            color = hsv_to_rgb(hsv) * (1.0 + hsl_luminance * 0.5);
+        }
+        // Toning (B&W only, tints the converted print)
+        case STEP_TONING: {
+            if (imageControls.bw_enabled != 0u && imageControls.bw_toning != 0u) {
+                let grey = clamp(dot(color, LUMA_WEIGHTS), 0.0, 1.0);
+                let tints = toning_tints(imageControls.bw_toning);
+                let toned = color * mix(tints[0], tints[1], grey);
+                color = mix(color, toned, imageControls.bw_toning_amount);
+            }
+        }
+        // Colour Grading
+        // Weights come from the graded image's luma so the split follows what you see
+        case STEP_COLOR_GRADING: {
+            let grade_luma = clamp(dot(color, LUMA_WEIGHTS), 0.0, 1.0);
+            if (imageControls.grading_mode == 0u) {
+                let w = tonal_weights(grade_luma, imageControls.grading_balance);
+                let lift = imageControls.shadow_wheel;
+                let gamma_wheel = imageControls.midtone_wheel;
+                let gain = imageControls.highlight_wheel;
+                color = color
+                    + w.x * (tint(lift.x, lift.y) + lift.z)
+                    + w.y * (tint(gamma_wheel.x, gamma_wheel.y) + gamma_wheel.z)
+                    + w.z * (tint(gain.x, gain.y) + gain.z);
+            } else {
+                let w = tonal_weights(grade_luma, imageControls.split_balance);
+                color = color
+                    + w.x * tint(imageControls.split_shadow_hue, imageControls.split_shadow_saturation)
+                    + w.z * tint(imageControls.split_highlight_hue, imageControls.split_highlight_saturation);
+            }
+        }
+        default: {}
+    }
+    return mix(input, color, stack_step.opacity);
+}
+
 @compute @workgroup_size(16, 16)
 fn shader_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
     let dims = textureDimensions(input_texture);
@@ -76,21 +395,38 @@ fn shader_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
         return;
     }
 
-    // 1. Load Input (Assuming Linear Float from previous steps)
+    // 1. Load Input (Linear Float from the stage before, see ProcessingGraph.rs)
     let raw_color = textureLoad(input_texture, coords, 0);
     var color = raw_color.rgb;
 
+    // Before/after reference: only the output transform (tone mapping, gamma, clamp)
+    let unprocessed = textureLoad(unprocessed_texture, coords, 0).rgb;
+    let gamma = 2.2;
+    let original = pow(tone_map(unprocessed, imageControls.tone_mapper, imageControls.white_point), vec3<f32>(1.0 / gamma));
+    textureStore(original_texture, coords, vec4<f32>(clamp(original, vec3<f32>(0.0), vec3<f32>(1.0)), raw_color.a));
+
+    // Alt-dragging a sharpening slider shows its effect on its own in black and white. The
+    // graph stops after the sharpening stage, which left the preview in linear light.
+    if (imageControls.sharpen_preview != 0u) {
+        let preview = clamp(pow(max(color, vec3<f32>(0.0)), vec3<f32>(1.0 / gamma)), vec3<f32>(0.0), vec3<f32>(1.0));
+        textureStore(working_texture, coords, vec4<f32>(color, raw_color.a));
+        textureStore(output_texture, coords, vec4<f32>(preview, raw_color.a));
+        return;
+    }
+
     // -----------------------------------------------------------------
     // STAGE 1: LINEAR OPERATIONS (Physics based)
     // -----------------------------------------------------------------
 
-    // Exposure
-    // We strictly use base-2 power for accurate camera stops
-    color = color * pow(2.0, imageControls.exposure);
+    // Linear light steps, in the order the adjustment stack has them
+    for (var i = 0u; i < adjustment_stack.info.y; i++) {
+        color = apply_step(adjustment_stack.steps[i], color, coords, dims);
+    }
 
-    // 2. Brightness (Additive - Digital Offset)
-    // Typical Range: -0.5 to +0.5
-    color = color + imageControls.brightness;
+    // Tone Mapping (Highlight roll-off)
+    // Has to see scene-linear values, before gamma encoding and long before the output clamp.
+    // Fixed between the linear and perceptual steps, they're written for one side of it.
+    color = tone_map(color, imageControls.tone_mapper, imageControls.white_point);
 
     // -----------------------------------------------------------------
     // STAGE 2: GAMMA CONVERSION
@@ -98,47 +434,35 @@ fn shader_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
     // We must move to perceptual space for "Digital Contrast" to feel right.
     // If we contrast-pivot around 0.5 in Linear (where grey is 0.18), we crush shadows.
     // In Gamma 2.2, grey is approx 0.5, so the pivot works.
-    let gamma = 2.2;
     color = pow(color, vec3<f32>(1.0 / gamma));
 
     // -----------------------------------------------------------------
     // STAGE 3: PERCEPTUAL OPERATIONS (Digital style)
     // -----------------------------------------------------------------
 
-    // Contrast
-    // Pivot around 0.5 (Mid-gray in Gamma space)
-    // Formula: (Color - 0.5) * Contrast + 0.5
-    color = (color - 0.5) * imageControls.contrast + 0.5;
-
-    // Saturation (Luma-preserving-ish)
-    // We use the standard Rec.709 Luma coefficients
-    let luma = dot(color, vec3<f32>(0.299, 0.587, 0.114));
-    let luma_vec = vec3<f32>(luma);
-
-    let shadow_mask = (1.0 - luma) * (1.0 - luma);
-    let highlight_mask = luma * luma;
-
-    // 3. Apply Shadows (Additive Lift)
-    // imageControls.shadows range: -0.5 (Crush) to +0.5 (Lift)
-    // We add light specifically to areas where shadow_mask is high
-    color = color + (vec3<f32>(imageControls.shadows) * shadow_mask);
-
-    // 4. Apply Highlights (Multiplicative Gain)
-    // imageControls.highlights range: -1.0 (Recover) to +1.0 (Boost)
-    // We scale the brightness specifically where highlight_mask is high
-    color = color + (color * imageControls.highlights * highlight_mask);
-
-    // Interpolate between Grayscale (luma) and Color
-    color = mix(luma_vec, color, imageControls.saturation);
+    for (var i = adjustment_stack.info.y; i < adjustment_stack.info.x; i++) {
+        color = apply_step(adjustment_stack.steps[i], color, coords, dims);
+    }
 
     // -----------------------------------------------------------------
     // STAGE 4: OUTPUT
     // -----------------------------------------------------------------
 
+    // Keep the unclamped result, back in linear light, so the viewer can see what's clipping
+    let working = sign(color) * pow(abs(color), vec3<f32>(gamma));
+    textureStore(working_texture, coords, vec4<f32>(working, raw_color.a));
+
     // Clamp to valid sRGB range to prevent weird artifacts on display
     color = clamp(color, vec3<f32>(0.0), vec3<f32>(1.0));
 
+    // Selected mask tinted red while it's being edited. Only written while the local
+    // adjustments stage runs, which it always does while there's a mask to show.
+    if (imageControls.mask_overlay != 0u) {
+        let overlay_weight = textureLoad(mask_overlay, coords, 0).r;
+        color = mix(color, vec3<f32>(1.0, 0.1, 0.1), overlay_weight * 0.5);
+    }
+
     // Note: We are writing Gamma-Corrected values to the storage texture.
     // This assumes your swapchain/display expects sRGB pixel data.
     textureStore(output_texture, coords, vec4<f32>(color, raw_color.a));
-}
\ No newline at end of file
+}
@@ -0,0 +1,154 @@
+// Luminance and colour noise reduction on the framed image, ahead of the colour pipeline.
+//
+// An edge-avoiding a-trous wavelet filter: the same 5x5 B-spline kernel run several times
+// with its taps spread twice as far apart each time, so later passes reach blotchy low
+// frequency noise without a large kernel. Each tap is weighted down by how different it is
+// from the centre pixel, which keeps edges sharp while the noise around them averages out.
+//
+// Three entry points:
+//   convert_main  framed RGB -> YCC (gamma luma and two colour differences)
+//   atrous_main   one smoothing pass, run DENOISE_LEVELS times ping-ponging two textures
+//   resolve_main  puts back the share of detail asked for, YCC -> RGB
+// The first two write half float working textures, only the result is full float.
+//
+// Must match DenoiseUniform in NoiseReduction.rs
+
+struct DenoiseUniform {
+    strength: vec4<f32>, // Luma sigma, colour sigma, luma detail kept, colour detail kept
+    step: i32,           // Tap spacing in pixels
+    filter_luma: u32,    // 1: this pass smooths the luma too
+    _pad: vec2<u32>,
+}
+
+@group(0) @binding(0)
+var input_texture: texture_2d<f32>;
+
+// The framed image, what the detail is put back from
+@group(0) @binding(1)
+var original_texture: texture_2d<f32>;
+
+@group(0) @binding(2)
+var output_texture: texture_storage_2d<rgba16float, write>;
+
+@group(0) @binding(3)
+var<uniform> denoise: DenoiseUniform;
+
+// Same format as the framed image, it stands in for it from here on
+@group(0) @binding(4)
+var resolved_texture: texture_storage_2d<rgba32float, write>;
+
+const LUMA_WEIGHTS = vec3<f32>(0.299, 0.587, 0.114);
+
+// 1D B3 spline, the 2D kernel is its outer product
+const KERNEL = array<f32, 5>(0.0625, 0.25, 0.375, 0.25, 0.0625);
+
+// Colour smoothing stops at brightness edges of about this size, so colour doesn't bleed
+// from one object into the next
+const CHROMA_EDGE_SIGMA = 0.08;
+
+// Gamma encoded so the noise is about the same size in the shadows and the highlights
+fn to_ycc(rgb: vec3<f32>) -> vec3<f32> {
+    let g = pow(max(rgb, vec3<f32>(0.0)), vec3<f32>(1.0 / 2.2));
+    let y = dot(g, LUMA_WEIGHTS);
+    return vec3<f32>(y, g.b - y, g.r - y);
+}
+
+fn from_ycc(ycc: vec3<f32>) -> vec3<f32> {
+    let r = ycc.z + ycc.x;
+    let b = ycc.y + ycc.x;
+    let g = (ycc.x - LUMA_WEIGHTS.r * r - LUMA_WEIGHTS.b * b) / LUMA_WEIGHTS.g;
+    return pow(max(vec3<f32>(r, g, b), vec3<f32>(0.0)), vec3<f32>(2.2));
+}
+
+// --- CONVERT ---
+
+@compute @workgroup_size(16, 16)
+fn convert_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let dims = vec2<i32>(textureDimensions(input_texture));
+    let coords = vec2<i32>(global_id.xy);
+    if (coords.x >= dims.x || coords.y >= dims.y) {
+        return;
+    }
+    let texel = textureLoad(input_texture, coords, 0);
+    textureStore(output_texture, coords, vec4<f32>(to_ycc(texel.rgb), texel.a));
+}
+
+// --- SMOOTH ---
+
+@compute @workgroup_size(16, 16)
+fn atrous_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let dims = vec2<i32>(textureDimensions(input_texture));
+    let coords = vec2<i32>(global_id.xy);
+    if (coords.x >= dims.x || coords.y >= dims.y) {
+        return;
+    }
+    let centre = textureLoad(input_texture, coords, 0);
+
+    let luma_sigma = denoise.strength.x;
+    let chroma_sigma = denoise.strength.y;
+    let filter_luma = denoise.filter_luma != 0u && luma_sigma > 0.0;
+    let filter_chroma = chroma_sigma > 0.0;
+
+    var luma_sum = 0.0;
+    var luma_weights = 0.0;
+    var chroma_sum = vec2<f32>(0.0);
+    var chroma_weights = 0.0;
+    for (var j = 0; j < 5; j++) {
+        for (var i = 0; i < 5; i++) {
+            // Edge pixels repeat, so the border isn't pulled towards black
+            let offset = vec2<i32>(i - 2, j - 2) * denoise.step;
+            let p = clamp(coords + offset, vec2<i32>(0), dims - 1);
+            let tap = textureLoad(input_texture, p, 0).xyz;
+            let h = KERNEL[i] * KERNEL[j];
+            let dy = tap.x - centre.x;
+            if (filter_luma) {
+                let w = h * exp(-dy * dy / (2.0 * luma_sigma * luma_sigma));
+                luma_sum += w * tap.x;
+                luma_weights += w;
+            }
+            if (filter_chroma) {
+                let dc = tap.yz - centre.yz;
+                let w = h * exp(-dy * dy / (2.0 * CHROMA_EDGE_SIGMA * CHROMA_EDGE_SIGMA)
+                    - dot(dc, dc) / (2.0 * chroma_sigma * chroma_sigma));
+                chroma_sum += w * tap.yz;
+                chroma_weights += w;
+            }
+        }
+    }
+
+    // The centre tap always counts fully, so the weights never sum to zero
+    var result = centre.xyz;
+    if (filter_luma) {
+        result.x = luma_sum / luma_weights;
+    }
+    if (filter_chroma) {
+        result = vec3<f32>(result.x, chroma_sum / chroma_weights);
+    }
+    textureStore(output_texture, coords, vec4<f32>(result, centre.a));
+}
+
+// --- RESOLVE ---
+
+@compute @workgroup_size(16, 16)
+fn resolve_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let dims = vec2<i32>(textureDimensions(input_texture));
+    let coords = vec2<i32>(global_id.xy);
+    if (coords.x >= dims.x || coords.y >= dims.y) {
+        return;
+    }
+    let original_rgb = textureLoad(original_texture, coords, 0);
+    let original = to_ycc(original_rgb.rgb);
+    let smoothed = textureLoad(input_texture, coords, 0).xyz;
+
+    // Some of what was taken out goes back, fine texture reads as detail rather than noise
+    // once the noise under it has been turned down
+    let y = mix(smoothed.x, original.x, denoise.strength.z);
+    let c = mix(smoothed.yz, original.yz, denoise.strength.w);
+    var rgb = from_ycc(vec3<f32>(y, c));
+
+    // Values the gamma round trip can't carry (negative or no luma) stay as they were
+    if (all(original_rgb.rgb <= vec3<f32>(0.0))) {
+        rgb = original_rgb.rgb;
+    }
+    textureStore(resolved_texture, coords, vec4<f32>(rgb, original_rgb.a));
+}
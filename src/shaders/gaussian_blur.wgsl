@@ -0,0 +1,56 @@
+// One direction of a Gaussian blur of the picture's luma. Run twice, across then down, for
+// the blurred copy capture sharpening subtracts (see sharpen.wgsl).
+// Must match GaussianUniform in GpuSharpenPipeline.rs
+
+struct GaussianUniform {
+    direction: vec2<i32>, // (1, 0) across or (0, 1) down
+    radius: i32,          // Taps either side, about three sigma
+    from_color: u32,      // 1: input is the framed colour image, take its luma. 0: input is luma already.
+    sigma: vec4<f32>,     // Standard deviation in pixels, unused x3
+}
+
+@group(0) @binding(0)
+var input_texture: texture_2d<f32>;
+
+@group(0) @binding(1)
+var output_texture: texture_storage_2d<r32float, write>;
+
+@group(0) @binding(2)
+var<uniform> blur: GaussianUniform;
+
+const LUMA_WEIGHTS = vec3<f32>(0.299, 0.587, 0.114);
+
+// Sharpening works on gamma encoded luma so it's as strong in the shadows as it looks.
+// Must match sharpen_luma in sharpen.wgsl
+fn sharpen_luma(rgb: vec3<f32>) -> f32 {
+    return pow(max(dot(rgb, LUMA_WEIGHTS), 0.0), 1.0 / 2.2);
+}
+
+fn load_luma(p: vec2<i32>) -> f32 {
+    let texel = textureLoad(input_texture, p, 0);
+    if (blur.from_color != 0u) {
+        return sharpen_luma(texel.rgb);
+    }
+    return texel.r;
+}
+
+@compute @workgroup_size(16, 16)
+fn gaussian_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let dims = vec2<i32>(textureDimensions(input_texture));
+    let coords = vec2<i32>(global_id.xy);
+    if (coords.x >= dims.x || coords.y >= dims.y) {
+        return;
+    }
+
+    // Edge pixels repeat, so the border isn't pulled towards black
+    let falloff = -0.5 / (blur.sigma.x * blur.sigma.x);
+    var sum = 0.0;
+    var weights = 0.0;
+    for (var i = -blur.radius; i <= blur.radius; i++) {
+        let p = clamp(coords + blur.direction * i, vec2<i32>(0), dims - 1);
+        let w = exp(f32(i * i) * falloff);
+        sum += w * load_luma(p);
+        weights += w;
+    }
+    textureStore(output_texture, coords, vec4<f32>(sum / weights, 0.0, 0.0, 1.0));
+}
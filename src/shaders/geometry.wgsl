@@ -0,0 +1,222 @@
+// Geometry stage: lens corrections, spot healing, crop, straighten, 90 degree rotations
+// and flips. Runs before everything else and writes the framed image the colour pipeline
+// works on.
+// Must match GeometryUniform in Geometry.rs and SpotGpu in SpotHealing.rs
+
+struct GeometryUniform {
+    // Output pixel -> source pixel as a 2x3 affine transform, one row each
+    row_x: vec4<f32>,
+    row_y: vec4<f32>,
+    // Source centre in pixels, then 1 / half the shorter side and 1 / half the diagonal
+    lens_center: vec4<f32>,
+    // Ru^1..Ru^4 terms of the lensfun style Rd / Ru polynomial
+    distortion: vec4<f32>,
+    // Constant term of that polynomial, red and blue radius scale, fill scale
+    lens: vec4<f32>,
+    // lensfun "pa" vignetting k1..k3
+    vignetting: vec4<f32>,
+}
+
+// The only binding remade when the graph changes, the rest are in group 1
+@group(0) @binding(0)
+var source_texture: texture_2d<f32>;
+
+@group(1) @binding(1)
+var output_texture: texture_storage_2d<rgba32float, write>;
+
+@group(1) @binding(2)
+var<uniform> geometry: GeometryUniform;
+
+struct Spot {
+    bounds: vec4<f32>, // Min x, min y, max x, max y including the radius, source pixels
+    params: vec4<f32>, // Radius, feather, heal (1) or clone (0), unused
+    offset: vec4<f32>, // Source patch offset x, y, unused x2
+    range: vec4<u32>,  // First point, point count, unused x2
+}
+
+struct SpotList {
+    count: vec4<u32>, // Spot count, unused x3
+    spots: array<Spot>,
+}
+
+@group(1) @binding(3)
+var<storage, read> spot_list: SpotList;
+
+// Defect paths of every spot, source pixels
+@group(1) @binding(4)
+var<storage, read> spot_points: array<vec2<f32>>;
+
+// Samples around a healed spot that its colour and brightness are matched on
+const HEAL_RING_SAMPLES = 16;
+const TAU = 6.283185307;
+
+// Area outside the source frame, only visible while straightening with the crop open
+const OUTSIDE = vec4<f32>(0.0, 0.0, 0.0, 1.0);
+
+fn load_or_outside(p: vec2<i32>, dims: vec2<i32>) -> vec4<f32> {
+    if (any(p < vec2<i32>(0)) || any(p >= dims)) {
+        return OUTSIDE;
+    }
+    return textureLoad(source_texture, p, 0);
+}
+
+fn catmull_rom_weights(t: f32) -> vec4<f32> {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    return vec4<f32>(
+        -0.5 * t3 + t2 - 0.5 * t,
+        1.5 * t3 - 2.5 * t2 + 1.0,
+        -1.5 * t3 + 2.0 * t2 + 0.5 * t,
+        0.5 * t3 - 0.5 * t2,
+    );
+}
+
+// Bicubic (Catmull-Rom) resampling. Keeps more detail than bilinear when straightening or
+// undistorting, and lands exactly on the texel for plain quarter turns and crops.
+// Rgba32Float isn't filterable everywhere, so it's done by hand.
+// `pos` is in pixels with texel centres at .5, as with the output
+fn sample_bicubic(pos: vec2<f32>) -> vec4<f32> {
+    let dims = vec2<i32>(textureDimensions(source_texture));
+    let p = pos - 0.5;
+    let base = vec2<i32>(floor(p));
+    let f = p - floor(p);
+    let wx = catmull_rom_weights(f.x);
+    let wy = catmull_rom_weights(f.y);
+
+    var sum = vec4<f32>(0.0);
+    for (var j = 0; j < 4; j++) {
+        var row = vec4<f32>(0.0);
+        for (var i = 0; i < 4; i++) {
+            row += wx[i] * load_or_outside(base + vec2<i32>(i - 1, j - 1), dims);
+        }
+        sum += wy[j] * row;
+    }
+    // The negative lobes can ring below zero next to hard edges
+    return max(sum, vec4<f32>(0.0));
+}
+
+// Bilinear, for the ring samples of a healed spot where bicubic detail is wasted
+fn sample_bilinear(pos: vec2<f32>) -> vec4<f32> {
+    let dims = vec2<i32>(textureDimensions(source_texture));
+    let p = pos - 0.5;
+    let base = vec2<i32>(floor(p));
+    let f = p - floor(p);
+    let top = mix(load_or_outside(base, dims), load_or_outside(base + vec2<i32>(1, 0), dims), f.x);
+    let bottom = mix(load_or_outside(base + vec2<i32>(0, 1), dims), load_or_outside(base + vec2<i32>(1, 1), dims), f.x);
+    return mix(top, bottom, f.y);
+}
+
+// Where the real lens put a point of the corrected picture, relative to the lens centre
+fn distorted_offset(source: vec2<f32>) -> vec2<f32> {
+    // `source` is where the pixel would be with a perfect lens. Push it out (or in) to where
+    // the real lens put it, in units of half the shorter side like lensfun.
+    let offset = (source - geometry.lens_center.xy) * geometry.lens.w;
+    let r = length(offset) * geometry.lens_center.z;
+    let d = geometry.distortion;
+    let radial = geometry.lens.x + r * (d.x + r * (d.y + r * (d.z + r * d.w)));
+    return offset * radial;
+}
+
+// Vignetting measured from the lens centre in units of half the diagonal, to divide out
+fn vignetting_falloff(distorted: vec2<f32>) -> f32 {
+    let rv2 = dot(distorted, distorted) * geometry.lens_center.w * geometry.lens_center.w;
+    let v = geometry.vignetting;
+    return max(1.0 + rv2 * (v.x + rv2 * (v.y + rv2 * v.z)), 0.05);
+}
+
+// The lens corrected picture at a source position, cheaper and without the CA correction
+fn corrected_fast(source: vec2<f32>) -> vec3<f32> {
+    let distorted = distorted_offset(source);
+    return sample_bilinear(geometry.lens_center.xy + distorted).rgb / vignetting_falloff(distorted);
+}
+
+// The lens corrected picture at a source position
+fn corrected_sample(source: vec2<f32>) -> vec4<f32> {
+    // --- LENS DISTORTION ---
+    let center = geometry.lens_center.xy;
+    let distorted = distorted_offset(source);
+
+    // --- LATERAL CHROMATIC ABERRATION ---
+    // Red and blue land at slightly different radii than green, sample each on its own
+    let red = sample_bicubic(center + distorted * geometry.lens.y).r;
+    let green_sample = sample_bicubic(center + distorted);
+    let blue = sample_bicubic(center + distorted * geometry.lens.z).b;
+    let color = vec4<f32>(red, green_sample.g, blue, green_sample.a);
+
+    // --- VIGNETTING ---
+    return vec4<f32>(color.rgb / vignetting_falloff(distorted), color.a);
+}
+
+// Nearest point to `p` on a spot's defect path
+fn closest_on_path(p: vec2<f32>, spot: Spot) -> vec2<f32> {
+    let first = spot.range.x;
+    var closest = spot_points[first];
+    var best = dot(p - closest, p - closest);
+    for (var i = 1u; i < spot.range.y; i++) {
+        let a = spot_points[first + i - 1u];
+        let ab = spot_points[first + i] - a;
+        let t = clamp(dot(p - a, ab) / max(dot(ab, ab), 1e-6), 0.0, 1.0);
+        let q = a + ab * t;
+        let d = dot(p - q, p - q);
+        if (d < best) {
+            best = d;
+            closest = q;
+        }
+    }
+    return closest;
+}
+
+// What the source patch needs adding to fit in where it lands: the difference between the
+// surroundings of the defect and of the patch, spread smoothly over the inside. An
+// approximation of a Poisson blend, with inverse distance weights to the ring standing in
+// for the membrane solve.
+fn heal_membrane(p: vec2<f32>, center: vec2<f32>, offset: vec2<f32>, radius: f32) -> vec3<f32> {
+    var sum = vec3<f32>(0.0);
+    var weights = 0.0;
+    for (var k = 0; k < HEAL_RING_SAMPLES; k++) {
+        let t = f32(k) / f32(HEAL_RING_SAMPLES) * TAU;
+        // Just outside the spot so the defect's own edge stays out of it
+        let b = center + vec2<f32>(cos(t), sin(t)) * (radius * 1.15 + 1.0);
+        let difference = corrected_fast(b) - corrected_fast(b + offset);
+        let w = 1.0 / max(dot(p - b, p - b), 1e-2);
+        sum += w * difference;
+        weights += w;
+    }
+    return sum / weights;
+}
+
+@compute @workgroup_size(16, 16)
+fn geometry_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let dims = textureDimensions(output_texture);
+    if (global_id.x >= dims.x || global_id.y >= dims.y) {
+        return;
+    }
+
+    let p = vec3<f32>(vec2<f32>(global_id.xy) + 0.5, 1.0);
+    let source = vec2<f32>(dot(geometry.row_x.xyz, p), dot(geometry.row_y.xyz, p));
+    var color = corrected_sample(source);
+
+    // --- SPOT HEALING ---
+    // In source positions so the spots stay on the dust, later spots over earlier ones.
+    // Patches always come from the unhealed picture.
+    for (var s = 0u; s < spot_list.count.x; s++) {
+        let spot = spot_list.spots[s];
+        if (any(source < spot.bounds.xy) || any(source > spot.bounds.zw)) {
+            continue;
+        }
+        let radius = spot.params.x;
+        let center = closest_on_path(source, spot);
+        let d = length(source - center);
+        if (d >= radius) {
+            continue;
+        }
+        let alpha = 1.0 - smoothstep(radius * (1.0 - max(spot.params.y, 1e-3)), radius, d);
+        var replacement = corrected_sample(source + spot.offset.xy);
+        if (spot.params.z != 0.0) {
+            replacement = vec4<f32>(max(replacement.rgb + heal_membrane(source, center, spot.offset.xy, radius), vec3<f32>(0.0)), replacement.a);
+        }
+        color = mix(color, replacement, alpha);
+    }
+
+    textureStore(output_texture, vec2<i32>(global_id.xy), color);
+}
@@ -0,0 +1,47 @@
+// Guided filter coefficients (He et al.) for brush mask edge refinement.
+// Within each window the refined mask is modelled as a * I + b, with I the guide luma.
+// Where the guide is flat a goes to zero and the painted mask is just smoothed, across an
+// edge a follows the guide so the mask snaps to it.
+// The coefficients are worked out at mask resolution, blurred, and applied against the
+// full resolution guide in local_adjustments.wgsl, so the refined edge is as sharp as the
+// image.
+
+@group(0) @binding(0)
+var mean_guide: texture_2d_array<f32>; // Mean of I and I * I, first layer
+
+@group(0) @binding(1)
+var mean_mask: texture_2d_array<f32>;
+
+@group(0) @binding(2)
+var mean_product: texture_2d_array<f32>; // Mean of I * mask
+
+@group(0) @binding(3)
+var a_texture: texture_storage_2d_array<rgba16float, write>;
+
+@group(0) @binding(4)
+var b_texture: texture_storage_2d_array<rgba16float, write>;
+
+// Regularisation. Smaller snaps to fainter edges.
+const EPSILON = 0.002;
+
+@compute @workgroup_size(16, 16)
+fn coefficients_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let dims = textureDimensions(mean_mask);
+    if (global_id.x >= dims.x || global_id.y >= dims.y) {
+        return;
+    }
+    let coords = vec2<i32>(global_id.xy);
+    let layer = i32(global_id.z);
+
+    let guide = textureLoad(mean_guide, coords, 0, 0);
+    let mask = textureLoad(mean_mask, coords, layer, 0);
+    let product = textureLoad(mean_product, coords, layer, 0);
+
+    let variance = max(guide.y - guide.x * guide.x, 0.0);
+    let covariance = product - guide.x * mask;
+    let a = covariance / (variance + EPSILON);
+    let b = mask - a * guide.x;
+
+    textureStore(a_texture, coords, layer, a);
+    textureStore(b_texture, coords, layer, b);
+}
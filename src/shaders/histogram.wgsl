@@ -0,0 +1,57 @@
+// Histogram of the processed (display encoded) image.
+// Bins are laid out as 256 red, 256 green, 256 blue, then 256 luma.
+
+@group(0) @binding(0)
+var image: texture_2d<f32>;
+
+@group(0) @binding(1)
+var<storage, read_write> bins: array<atomic<u32>, 1024>;
+
+// Each workgroup counts into shared memory first and flushes once at the end,
+// so the global atomics only see one add per bin per workgroup.
+var<workgroup> local_bins: array<atomic<u32>, 1024>;
+
+// Every invocation covers a TILE x TILE block, so a workgroup covers 64x64 pixels
+const TILE = 4u;
+
+const LUMA_WEIGHTS = vec3<f32>(0.299, 0.587, 0.114);
+
+fn to_bin(v: f32) -> u32 {
+    return u32(clamp(v, 0.0, 1.0) * 255.0 + 0.5);
+}
+
+@compute @workgroup_size(16, 16)
+fn histogram_main(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32,
+) {
+    // 256 invocations clear 1024 bins, four each
+    for (var i = 0u; i < 4u; i++) {
+        atomicStore(&local_bins[local_index * 4u + i], 0u);
+    }
+    workgroupBarrier();
+
+    let dims = textureDimensions(image);
+    let base = global_id.xy * TILE;
+    for (var y = 0u; y < TILE; y++) {
+        for (var x = 0u; x < TILE; x++) {
+            let p = base + vec2<u32>(x, y);
+            if (p.x < dims.x && p.y < dims.y) {
+                let c = textureLoad(image, vec2<i32>(p), 0).rgb;
+                atomicAdd(&local_bins[to_bin(c.r)], 1u);
+                atomicAdd(&local_bins[256u + to_bin(c.g)], 1u);
+                atomicAdd(&local_bins[512u + to_bin(c.b)], 1u);
+                atomicAdd(&local_bins[768u + to_bin(dot(c, LUMA_WEIGHTS))], 1u);
+            }
+        }
+    }
+    workgroupBarrier();
+
+    for (var i = 0u; i < 4u; i++) {
+        let index = local_index * 4u + i;
+        let count = atomicLoad(&local_bins[index]);
+        if (count > 0u) {
+            atomicAdd(&bins[index], count);
+        }
+    }
+}
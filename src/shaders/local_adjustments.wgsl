@@ -0,0 +1,202 @@
+// Local adjustment layers. Each layer's mask (graduated, radial, painted or the whole
+// frame, narrowed by its range mask) weights its settings, and overlapping layers add up.
+// Exposure and temperature act on linear light like their global versions, contrast and
+// saturation on the gamma encoded picture. common.wgsl goes in front of it.
+
+// One local adjustment layer. Must match LocalLayerUniform in LocalAdjustments.rs
+struct LocalLayer {
+    shape: vec4<f32>,  // Linear: start, end. Radial: centre, radius. Source pixels.
+    params: vec4<f32>, // Radial: cos and sin of the angle, feather, unused
+    flags: vec4<u32>,  // Mask kind, invert, brush: refine, range mask bits
+    adjust: vec4<f32>, // Exposure, contrast, saturation, temperature
+    luminance: vec4<f32>, // Range: low, high, falloff, colour falloff
+    color: vec4<f32>,     // Range: hue, hue width (degrees), saturation, saturation width
+}
+
+const MAX_LOCAL_ADJUSTMENTS = 8u;
+const MASK_LINEAR = 0u;
+const MASK_RADIAL = 1u;
+const MASK_BRUSH = 2u;
+const MASK_FULL = 3u;
+const RANGE_LUMINANCE = 1u;
+const RANGE_COLOR = 2u;
+
+struct LocalAdjustments {
+    // Output pixel -> source pixel, so masks stay on the picture through crops and turns
+    to_source_x: vec4<f32>,
+    to_source_y: vec4<f32>,
+    info: vec4<u32>, // Layer count, overlay layer plus one (0 for none), unused, unused
+    layers: array<LocalLayer, 8>,
+}
+
+// The only binding remade when the graph changes, the rest are in group 1
+@group(0) @binding(0)
+var input_texture: texture_2d<f32>;
+
+@group(1) @binding(1)
+var output_texture: texture_storage_2d<rgba16float, write>;
+
+// The selected layer's mask, which the colour pass tints red while it's being edited
+@group(1) @binding(2)
+var overlay_texture: texture_storage_2d<r32float, write>;
+
+@group(1) @binding(3)
+var<uniform> imageControls: ImageControls;
+
+@group(1) @binding(4)
+var<uniform> local_adjustments: LocalAdjustments;
+
+// Painted brush masks at mask resolution, one local adjustment slot per channel
+@group(1) @binding(5)
+var brush_mask: texture_2d_array<f32>;
+
+// Guided filter coefficients for refined brush masks, see guided_filter.wgsl
+@group(1) @binding(6)
+var brush_refined_a: texture_2d_array<f32>;
+
+@group(1) @binding(7)
+var brush_refined_b: texture_2d_array<f32>;
+
+@group(1) @binding(8)
+var brush_sampler: sampler;
+
+// The framed image before noise reduction. Brush edge refinement follows it because
+// that's what its guide was made from.
+@group(1) @binding(9)
+var unprocessed_texture: texture_2d<f32>;
+
+// Range mask eyedropper: the pixel to read, then where its colour goes, see pick_main
+@group(1) @binding(10)
+var<uniform> pick_coords: vec4<u32>;
+
+@group(1) @binding(11)
+var<storage, read_write> picked_color: vec4<f32>;
+
+// Edge refinement guide, must match guide_luma in brush.wgsl
+fn guide_luma(rgb: vec3<f32>) -> f32 {
+    return pow(clamp(dot(rgb, LUMA_WEIGHTS), 0.0, 1.0), 1.0 / 2.2);
+}
+
+// How much of a graduated or radial adjustment applies at a source pixel position, 0 to 1
+fn shape_weight(layer: LocalLayer, p: vec2<f32>) -> f32 {
+    if (layer.flags.x == MASK_LINEAR) {
+        // Full effect before the start line, none past the end line
+        let d = layer.shape.zw - layer.shape.xy;
+        let t = dot(p - layer.shape.xy, d) / max(dot(d, d), 1e-6);
+        return 1.0 - smoothstep(0.0, 1.0, t);
+    }
+    // Into the ellipse's own axes, where its edge is the unit circle
+    let offset = p - layer.shape.xy;
+    let c = layer.params.x;
+    let s = layer.params.y;
+    let q = vec2<f32>(c * offset.x + s * offset.y, c * offset.y - s * offset.x) / max(layer.shape.zw, vec2<f32>(1e-3));
+    let feather = max(layer.params.z, 1e-3);
+    return 1.0 - smoothstep(1.0 - feather, 1.0, length(q));
+}
+
+// Painted coverage of slot `slot` at `uv` across the frame. Refined masks are rebuilt from
+// the guided filter coefficients against this pixel's own guide value, which puts the
+// edge back at full resolution.
+fn brush_weight(slot: u32, refine: bool, uv: vec2<f32>, guide: f32) -> f32 {
+    let array_layer = i32(slot / 4u);
+    let channel = slot % 4u;
+    if (refine) {
+        let a = textureSampleLevel(brush_refined_a, brush_sampler, uv, array_layer, 0.0)[channel];
+        let b = textureSampleLevel(brush_refined_b, brush_sampler, uv, array_layer, 0.0)[channel];
+        return clamp(a * guide + b, 0.0, 1.0);
+    }
+    return textureSampleLevel(brush_mask, brush_sampler, uv, array_layer, 0.0)[channel];
+}
+
+// How much of the layer's content range a display-referred colour falls in, 0 to 1
+fn range_weight(layer: LocalLayer, display: vec3<f32>) -> f32 {
+    var weight = 1.0;
+    if ((layer.flags.w & RANGE_LUMINANCE) != 0u) {
+        let luma = dot(display, LUMA_WEIGHTS);
+        let falloff = max(layer.luminance.z, 1e-3);
+        weight *= smoothstep(layer.luminance.x - falloff, layer.luminance.x, luma);
+        weight *= 1.0 - smoothstep(layer.luminance.y, layer.luminance.y + falloff, luma);
+    }
+    if ((layer.flags.w & RANGE_COLOR) != 0u) {
+        let hsv = rgb_to_hsv(display);
+        let softness = max(layer.luminance.w, 1e-3);
+        // Shortest way round the hue circle
+        let hue_distance = abs(((hsv.x - layer.color.x) % 360.0 + 540.0) % 360.0 - 180.0);
+        weight *= 1.0 - smoothstep(layer.color.y, layer.color.y + softness * 60.0, hue_distance);
+        let saturation_distance = abs(hsv.y - layer.color.z);
+        weight *= 1.0 - smoothstep(layer.color.w, layer.color.w + softness * 0.5, saturation_distance);
+        // Hue means nothing for greys, they fade out unless the range reaches down to them
+        weight *= mix(smoothstep(0.0, 0.05, hsv.y), 1.0, step(layer.color.z - layer.color.w, 0.0));
+    }
+    return weight;
+}
+
+// Range masks look at the picture as the global exposure leaves it, through the output
+// transform, so they pick what's on screen rather than the raw light
+fn display_color(color: vec3<f32>) -> vec3<f32> {
+    let exposed = color * pow(2.0, imageControls.exposure) + imageControls.brightness;
+    let mapped = tone_map(exposed, imageControls.tone_mapper, imageControls.white_point);
+    return pow(clamp(mapped, vec3<f32>(0.0), vec3<f32>(1.0)), vec3<f32>(1.0 / 2.2));
+}
+
+@compute @workgroup_size(16, 16)
+fn local_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let dims = textureDimensions(input_texture);
+    let coords = vec2<i32>(global_id.xy);
+    if (coords.x >= i32(dims.x) || coords.y >= i32(dims.y)) {
+        return;
+    }
+
+    let texel = textureLoad(input_texture, coords, 0);
+    var color = texel.rgb;
+
+    // Weighted sums of each layer's settings
+    let display = display_color(color);
+    let source_pos = vec3<f32>(vec2<f32>(coords) + 0.5, 1.0);
+    let p = vec2<f32>(dot(local_adjustments.to_source_x.xyz, source_pos), dot(local_adjustments.to_source_y.xyz, source_pos));
+    let uv = (vec2<f32>(coords) + 0.5) / vec2<f32>(dims);
+    let guide = guide_luma(textureLoad(unprocessed_texture, coords, 0).rgb);
+    var local_sum = vec4<f32>(0.0); // exposure, contrast, saturation, temperature
+    var overlay_weight = 0.0;
+    for (var i = 0u; i < min(local_adjustments.info.x, MAX_LOCAL_ADJUSTMENTS); i++) {
+        let layer = local_adjustments.layers[i];
+        var weight: f32;
+        if (layer.flags.x == MASK_BRUSH) {
+            weight = brush_weight(i, layer.flags.z != 0u, uv, guide);
+        } else if (layer.flags.x == MASK_FULL) {
+            weight = 1.0;
+        } else {
+            weight = shape_weight(layer, p);
+        }
+        // Inverting flips the shape, the range mask still picks from what's left
+        if (layer.flags.y != 0u) {
+            weight = 1.0 - weight;
+        }
+        weight *= range_weight(layer, display);
+        local_sum += weight * layer.adjust;
+        if (i + 1u == local_adjustments.info.y) {
+            overlay_weight = weight;
+        }
+    }
+
+    // Exposure and temperature, multiplicative on light
+    color = color * pow(2.0, local_sum.x) * vec3<f32>(pow(2.0, local_sum.w * 0.5), 1.0, pow(2.0, -local_sum.w * 0.5));
+
+    // Contrast and saturation pivot on mid grey, which only sits near 0.5 gamma encoded
+    if (local_sum.y != 0.0 || local_sum.z != 0.0) {
+        var g = pow(max(color, vec3<f32>(0.0)), vec3<f32>(1.0 / 2.2));
+        g = (g - 0.5) * (1.0 + local_sum.y) + 0.5;
+        g = mix(vec3<f32>(dot(g, LUMA_WEIGHTS)), g, 1.0 + local_sum.z);
+        color = sign(g) * pow(abs(g), vec3<f32>(2.2));
+    }
+
+    textureStore(output_texture, coords, vec4<f32>(color, texel.a));
+    textureStore(overlay_texture, coords, vec4<f32>(overlay_weight, 0.0, 0.0, 0.0));
+}
+
+// The colour range_weight compares against at one pixel, so a range picked there selects it
+@compute @workgroup_size(1)
+fn pick_main() {
+    let texel = textureLoad(input_texture, vec2<i32>(pick_coords.xy), 0);
+    picked_color = vec4<f32>(display_color(texel.rgb), 1.0);
+}
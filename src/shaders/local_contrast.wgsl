@@ -0,0 +1,117 @@
+// Texture, clarity and dehaze, read from the local contrast pyramid pyramid.wgsl builds
+// from this stage's input. common.wgsl goes in front of it.
+
+// Must match LocalContrastUniform in LocalContrast.rs
+struct LocalContrastUniform {
+    airlight: vec4<f32>, // Haze colour, gamma encoded, unused
+    levels: vec4<f32>,   // Clarity level, dehaze level, pyramid levels, unused
+}
+
+@group(0) @binding(0)
+var input_texture: texture_2d<f32>;
+
+// Luma statistics at halving sizes, see pyramid.wgsl
+@group(0) @binding(1)
+var contrast_pyramid: texture_2d<f32>;
+
+@group(0) @binding(2)
+var contrast_sampler: sampler;
+
+@group(0) @binding(3)
+var output_texture: texture_storage_2d<rgba16float, write>;
+
+@group(0) @binding(4)
+var<uniform> local_contrast: LocalContrastUniform;
+
+@group(0) @binding(5)
+var<uniform> imageControls: ImageControls;
+
+// Smooth read of a pyramid level at a full size pixel position. A cubic B-spline made of
+// four bilinear taps, so the coarse levels come back as a soft blur rather than blocks.
+// Level k texel centres sit every 2^(k+1) pixels, see pyramid.wgsl.
+fn pyramid_sample(pos: vec2<f32>, level: f32) -> vec4<f32> {
+    let size = vec2<f32>(textureDimensions(contrast_pyramid, i32(level)));
+    let p = pos / exp2(level + 1.0) - 0.5;
+    let i = floor(p);
+    let f = p - i;
+    let f2 = f * f;
+    let f3 = f2 * f;
+    let w0 = (1.0 - 3.0 * f + 3.0 * f2 - f3) / 6.0;
+    let w1 = (4.0 - 6.0 * f2 + 3.0 * f3) / 6.0;
+    let w2 = (1.0 + 3.0 * f + 3.0 * f2 - 3.0 * f3) / 6.0;
+    let w3 = f3 / 6.0;
+    let g0 = w0 + w1;
+    let g1 = w2 + w3;
+    // Where between texels each pair's bilinear tap has to land to weight them right
+    let h0 = (i - 1.0 + w1 / g0 + 0.5) / size;
+    let h1 = (i + 1.0 + w3 / g1 + 0.5) / size;
+    return g0.y * (g0.x * textureSampleLevel(contrast_pyramid, contrast_sampler, h0, level)
+            + g1.x * textureSampleLevel(contrast_pyramid, contrast_sampler, vec2<f32>(h1.x, h0.y), level))
+        + g1.y * (g0.x * textureSampleLevel(contrast_pyramid, contrast_sampler, vec2<f32>(h0.x, h1.y), level)
+            + g1.x * textureSampleLevel(contrast_pyramid, contrast_sampler, h1, level));
+}
+
+// Texture, clarity and dehaze on one pixel, linear in and out.
+//
+// Dehaze inverts the haze model I = J t + A (1 - t): the transmission t comes from the dark
+// channel, refined by a guided filter with the luma as guide so it follows edges instead
+// of the pyramid's blocks (He et al.). Clarity and texture add back detail: clarity the
+// difference from an edge-preserving (self-guided) smoothing, so strong edges don't halo,
+// and texture a band of fine detail that's coarser than noise.
+fn apply_local_contrast(color: vec3<f32>, pos: vec2<f32>) -> vec3<f32> {
+    var g = pow(max(color, vec3<f32>(0.0)), vec3<f32>(1.0 / 2.2));
+    let levels = local_contrast.levels;
+
+    if (imageControls.dehaze != 0.0) {
+        let airlight = local_contrast.airlight.rgb;
+        let stats = pyramid_sample(pos, levels.y);
+        let luma = dot(g, LUMA_WEIGHTS);
+        let variance = max(stats.g - stats.r * stats.r, 0.0);
+        let covariance = stats.a - stats.r * stats.b;
+        let a = covariance / (variance + 0.001);
+        let dark = clamp(a * luma + stats.b - a * stats.r, 0.0, 1.0);
+        if (imageControls.dehaze > 0.0) {
+            // Never all the way, some haze is what makes distance look far away
+            let transmission = max(1.0 - 0.95 * imageControls.dehaze * dark, 0.1);
+            g = (g - airlight) / transmission + airlight;
+        } else {
+            // More haze where there's already some, the way fog thickens with distance
+            g = mix(g, airlight, -imageControls.dehaze * (0.3 + 0.5 * dark));
+        }
+        g = max(g, vec3<f32>(0.0));
+    }
+
+    let luma = dot(g, LUMA_WEIGHTS);
+    var contrasted = luma;
+    if (imageControls.clarity != 0.0) {
+        let stats = pyramid_sample(pos, levels.x);
+        let variance = max(stats.g - stats.r * stats.r, 0.0);
+        let a = variance / (variance + 0.01);
+        let base = stats.r + a * (luma - stats.r);
+        // Mostly the midtones, pushing shadows and highlights apart just clips them
+        let midtones = clamp(1.0 - pow(2.0 * luma - 1.0, 2.0), 0.0, 1.0);
+        contrasted += imageControls.clarity * 1.5 * midtones * (luma - base);
+    }
+    if (imageControls.texture != 0.0) {
+        let fine = pyramid_sample(pos, 0.0).r;
+        let coarse = pyramid_sample(pos, min(2.0, levels.z - 1.0)).r;
+        contrasted += imageControls.texture * 1.5 * (fine - coarse);
+    }
+    if (luma > 0.0) {
+        g *= max(contrasted, 0.0) / luma;
+    }
+    return pow(g, vec3<f32>(2.2));
+}
+
+@compute @workgroup_size(16, 16)
+fn local_contrast_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let dims = vec2<i32>(textureDimensions(input_texture));
+    let coords = vec2<i32>(global_id.xy);
+    if (coords.x >= dims.x || coords.y >= dims.y) {
+        return;
+    }
+
+    let texel = textureLoad(input_texture, coords, 0);
+    let color = apply_local_contrast(texel.rgb, vec2<f32>(coords) + 0.5);
+    textureStore(output_texture, coords, vec4<f32>(color, texel.a));
+}
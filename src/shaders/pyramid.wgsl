@@ -0,0 +1,82 @@
+// Local contrast pyramid: statistics of the picture's luma at halving sizes, for clarity,
+// texture and dehaze in local_contrast.wgsl. Each level is a [1 3 3 1] binomial downsample
+// of the one before, so reading a level back with a smooth filter gives a Gaussian-like
+// blur that would take hundreds of taps at full size.
+//
+// Every level holds four means over its window:
+//   r: luma              g: luma squared
+//   b: dark channel      a: luma times dark channel
+// which is all a guided filter needs, so the edge-aware smoothing comes almost free.
+//
+// Must match LocalContrastUniform in LocalContrast.rs
+
+struct LocalContrastUniform {
+    airlight: vec4<f32>, // Haze colour, gamma encoded, unused
+    levels: vec4<f32>,   // Clarity level, dehaze level, pyramid levels, unused
+}
+
+@group(0) @binding(0)
+var input_texture: texture_2d<f32>;
+
+@group(0) @binding(1)
+var output_texture: texture_storage_2d<rgba16float, write>;
+
+@group(0) @binding(2)
+var<uniform> contrast: LocalContrastUniform;
+
+const LUMA_WEIGHTS = vec3<f32>(0.299, 0.587, 0.114);
+
+const BINOMIAL = array<f32, 4>(0.125, 0.375, 0.375, 0.125);
+
+// Input texel under tap (i, j) of the 4x4 footprint of an output texel. Edge texels repeat.
+fn footprint(coords: vec2<i32>, i: i32, j: i32) -> vec2<i32> {
+    let dims = vec2<i32>(textureDimensions(input_texture));
+    return clamp(coords * 2 + vec2<i32>(i - 1, j - 1), vec2<i32>(0), dims - 1);
+}
+
+// --- FIRST LEVEL ---
+
+// Straight from the colour image. The dark channel is the minimum over the footprint, as
+// the prior asks, not the mean: a single colourful pixel is enough to show there's no haze.
+@compute @workgroup_size(16, 16)
+fn pyramid_color_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let coords = vec2<i32>(global_id.xy);
+    if (any(coords >= vec2<i32>(textureDimensions(output_texture)))) {
+        return;
+    }
+
+    var luma = 0.0;
+    var luma_squared = 0.0;
+    var dark = 1e6;
+    for (var j = 0; j < 4; j++) {
+        for (var i = 0; i < 4; i++) {
+            let rgb = textureLoad(input_texture, footprint(coords, i, j), 0).rgb;
+            let g = pow(max(rgb, vec3<f32>(0.0)), vec3<f32>(1.0 / 2.2));
+            let l = dot(g, LUMA_WEIGHTS);
+            let w = BINOMIAL[i] * BINOMIAL[j];
+            luma += w * l;
+            luma_squared += w * l * l;
+            let scaled = g / max(contrast.airlight.rgb, vec3<f32>(1e-3));
+            dark = min(dark, min(scaled.r, min(scaled.g, scaled.b)));
+        }
+    }
+    textureStore(output_texture, coords, vec4<f32>(luma, luma_squared, dark, luma * dark));
+}
+
+// --- LATER LEVELS ---
+
+@compute @workgroup_size(16, 16)
+fn pyramid_down_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let coords = vec2<i32>(global_id.xy);
+    if (any(coords >= vec2<i32>(textureDimensions(output_texture)))) {
+        return;
+    }
+
+    var sum = vec4<f32>(0.0);
+    for (var j = 0; j < 4; j++) {
+        for (var i = 0; i < 4; i++) {
+            sum += BINOMIAL[i] * BINOMIAL[j] * textureLoad(input_texture, footprint(coords, i, j), 0);
+        }
+    }
+    textureStore(output_texture, coords, sum);
+}
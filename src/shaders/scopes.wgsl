@@ -0,0 +1,74 @@
+// Waveform and vectorscope accumulation over the processed (display encoded) image.
+//
+// waveform: 4 planes (luma, red, green, blue) of SCOPE_SIZE levels x SCOPE_SIZE columns,
+//           level 0 is black
+// vectorscope: SCOPE_SIZE x SCOPE_SIZE grid of Cb (x) / Cr (y, up is positive)
+
+@group(0) @binding(0)
+var image: texture_2d<f32>;
+
+@group(0) @binding(1)
+var<storage, read_write> waveform: array<atomic<u32>>;
+
+@group(0) @binding(2)
+var<storage, read_write> vectorscope: array<atomic<u32>>;
+
+const SCOPE_SIZE = 256u;
+
+// Scopes only need a rough sample of the image, read at most this many pixels across.
+// Must match MAX_SAMPLES_ACROSS in GpuScopesPipeline.rs
+const MAX_SAMPLES_ACROSS = 1024u;
+
+// Pixels per unit of chroma. Cb/Cr of +-0.5 lands just inside the edge of the grid.
+// Must match VECTORSCOPE_SCALE in ScopesView.rs
+const VECTORSCOPE_SCALE = 230.0;
+
+const LUMA_WEIGHTS = vec3<f32>(0.299, 0.587, 0.114);
+
+fn sample_step(dims: vec2<u32>) -> u32 {
+    return max(1u, (dims.x + MAX_SAMPLES_ACROSS - 1u) / MAX_SAMPLES_ACROSS);
+}
+
+fn load_sample(global_id: vec3<u32>, dims: vec2<u32>) -> vec4<f32> {
+    let p = global_id.xy * sample_step(dims);
+    // Negative alpha marks a sample outside the image
+    if (p.x >= dims.x || p.y >= dims.y) {
+        return vec4<f32>(0.0, 0.0, 0.0, -1.0);
+    }
+    let c = clamp(textureLoad(image, vec2<i32>(p), 0).rgb, vec3<f32>(0.0), vec3<f32>(1.0));
+    return vec4<f32>(c, f32(p.x) / f32(dims.x));
+}
+
+@compute @workgroup_size(16, 16)
+fn waveform_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let dims = textureDimensions(image);
+    let sample = load_sample(global_id, dims);
+    if (sample.a < 0.0) {
+        return;
+    }
+
+    let column = min(u32(sample.a * f32(SCOPE_SIZE)), SCOPE_SIZE - 1u);
+    let levels = vec4<u32>(vec4<f32>(dot(sample.rgb, LUMA_WEIGHTS), sample.rgb) * 255.0 + 0.5);
+    let plane = SCOPE_SIZE * SCOPE_SIZE;
+    for (var channel = 0u; channel < 4u; channel++) {
+        atomicAdd(&waveform[channel * plane + levels[channel] * SCOPE_SIZE + column], 1u);
+    }
+}
+
+@compute @workgroup_size(16, 16)
+fn vectorscope_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let dims = textureDimensions(image);
+    let sample = load_sample(global_id, dims);
+    if (sample.a < 0.0) {
+        return;
+    }
+
+    // Rec.601 colour difference signals
+    let cb = dot(sample.rgb, vec3<f32>(-0.168736, -0.331264, 0.5));
+    let cr = dot(sample.rgb, vec3<f32>(0.5, -0.418688, -0.081312));
+
+    let center = f32(SCOPE_SIZE) * 0.5;
+    let pos = vec2<f32>(center + cb * VECTORSCOPE_SCALE, center - cr * VECTORSCOPE_SCALE);
+    let cell = vec2<u32>(clamp(pos, vec2<f32>(0.0), vec2<f32>(f32(SCOPE_SIZE - 1u))));
+    atomicAdd(&vectorscope[cell.y * SCOPE_SIZE + cell.x], 1u);
+}
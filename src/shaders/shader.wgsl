@@ -6,8 +6,22 @@ struct VSOut {
 struct ViewParams {
     viewport_size: vec2<f32>,
     image_size: vec2<f32>,
+    zoom: f32,
+    pan: vec2<f32>,
+    overlay_flags: u32,  // 1 = highlight clipping, 2 = shadow clipping, 4 = gamut warning
+    output_gamut: u32,   // 0 = sRGB, 1 = Display P3, 2 = Adobe RGB, 3 = Rec.2020
+    compare_mode: u32,   // 0 = Off, 1 = Split, 2 = Side by side, 3 = Original only
+    split_position: f32, // 0..1 across the image, for split mode
 };
 
+const OVERLAY_HIGHLIGHT_CLIPPING = 1u;
+const OVERLAY_SHADOW_CLIPPING = 2u;
+const OVERLAY_GAMUT = 4u;
+
+const COMPARE_SPLIT = 1u;
+const COMPARE_SIDE_BY_SIDE = 2u;
+const COMPARE_ORIGINAL = 3u;
+
 @group(0) @binding(1)
 var<uniform> view: ViewParams;
 
@@ -31,7 +45,11 @@ fn vs_main(@builtin(vertex_index) i: u32) -> VSOut {
         vec2(0.0, 0.0),
     );
 
-    let image_aspect = view.image_size.x / view.image_size.y;
+    var image_aspect = view.image_size.x / view.image_size.y;
+    if (view.compare_mode == COMPARE_SIDE_BY_SIDE) {
+        // Two copies next to each other
+        image_aspect = image_aspect * 2.0;
+    }
     let view_aspect  = view.viewport_size.x / view.viewport_size.y;
 
     var scale = vec2(1.0, 1.0);
@@ -52,10 +70,112 @@ fn vs_main(@builtin(vertex_index) i: u32) -> VSOut {
 @group(0) @binding(0)
 var image_tex: texture_2d<f32>;
 
+// Unclamped linear result from the compute pass
+@group(0) @binding(2)
+var working_tex: texture_2d<f32>;
+
+// Unedited image through the same output transform, for before/after
+@group(0) @binding(3)
+var original_tex: texture_2d<f32>;
+
+// Linear sRGB to the linear RGB of each output space. Written as rows, so multiply
+// with the colour on the left.
+fn to_output_gamut(c: vec3<f32>, gamut: u32) -> vec3<f32> {
+    switch (gamut) {
+        // Display P3
+        case 1u: { return c * mat3x3<f32>(
+            vec3<f32>(0.8225, 0.1774, 0.0000),
+            vec3<f32>(0.0332, 0.9669, 0.0000),
+            vec3<f32>(0.0171, 0.0724, 0.9108)); }
+        // Adobe RGB (1998)
+        case 2u: { return c * mat3x3<f32>(
+            vec3<f32>(0.7152, 0.2848, 0.0000),
+            vec3<f32>(0.0000, 1.0000, 0.0000),
+            vec3<f32>(0.0000, 0.0412, 0.9588)); }
+        // Rec.2020
+        case 3u: { return c * mat3x3<f32>(
+            vec3<f32>(0.6274, 0.3293, 0.0433),
+            vec3<f32>(0.0691, 0.9195, 0.0114),
+            vec3<f32>(0.0164, 0.0880, 0.8956)); }
+        default: { return c; }
+    }
+}
+
+// Clipping: all three channels clipped shows solid red (highlights) or blue (shadows).
+// Single channels show that channel's colour, hatched so they read as partial clipping.
+fn apply_overlays(color: vec3<f32>, working: vec3<f32>, frag_pos: vec2<f32>) -> vec3<f32> {
+    let eps = 1.0 / 512.0;
+    let hatch = (u32(frag_pos.x + frag_pos.y) / 4u) % 2u == 0u;
+    var out = color;
+
+    if ((view.overlay_flags & OVERLAY_GAMUT) != 0u) {
+        let c = to_output_gamut(working, view.output_gamut);
+        if (any(c < vec3<f32>(-eps)) || any(c > vec3<f32>(1.0 + eps))) {
+            out = vec3<f32>(0.5);
+        }
+    }
+
+    if ((view.overlay_flags & OVERLAY_SHADOW_CLIPPING) != 0u) {
+        let crushed = working <= vec3<f32>(0.0);
+        if (all(crushed)) {
+            out = vec3<f32>(0.0, 0.3, 1.0);
+        } else if (any(crushed) && hatch) {
+            out = select(vec3<f32>(0.0), vec3<f32>(1.0), crushed);
+        }
+    }
+
+    if ((view.overlay_flags & OVERLAY_HIGHLIGHT_CLIPPING) != 0u) {
+        let clipped = working >= vec3<f32>(1.0 - eps);
+        if (all(clipped)) {
+            out = vec3<f32>(1.0, 0.0, 0.0);
+        } else if (any(clipped) && hatch) {
+            out = select(vec3<f32>(0.0), vec3<f32>(1.0), clipped);
+        }
+    }
+
+    return out;
+}
+
 @fragment
 fn fs_main(in: VSOut) -> @location(0) vec4<f32> {
     // The UVs are already correct from the vertex shader
     // Just sample the texture directly
     let dims = vec2<i32>(textureDimensions(image_tex));
-    return textureLoad(image_tex, vec2<i32>(in.uv * vec2<f32>(dims)), 0);
+    let uv_per_pixel = fwidth(in.uv.x);
+
+    // Work out which image this fragment shows and where in it
+    var uv = in.uv;
+    var show_original = view.compare_mode == COMPARE_ORIGINAL;
+    if (view.compare_mode == COMPARE_SIDE_BY_SIDE) {
+        show_original = uv.x < 0.5;
+        uv.x = fract(uv.x * 2.0);
+    } else if (view.compare_mode == COMPARE_SPLIT) {
+        show_original = uv.x < view.split_position;
+    }
+
+    let coords = min(vec2<i32>(uv * vec2<f32>(dims)), dims - 1);
+
+    // Divider between the two halves
+    if (view.compare_mode == COMPARE_SPLIT || view.compare_mode == COMPARE_SIDE_BY_SIDE) {
+        var divider_x = view.split_position;
+        if (view.compare_mode == COMPARE_SIDE_BY_SIDE) {
+            divider_x = 0.5;
+        }
+        if (abs(in.uv.x - divider_x) < uv_per_pixel * 1.5) {
+            return vec4<f32>(1.0, 1.0, 1.0, 1.0);
+        }
+    }
+
+    if (show_original) {
+        return textureLoad(original_tex, coords, 0);
+    }
+
+    let color = textureLoad(image_tex, coords, 0);
+
+    // Overlays are drawn here rather than in the compute pass so they never reach an export
+    if (view.overlay_flags != 0u) {
+        let working = textureLoad(working_tex, coords, 0).rgb;
+        return vec4<f32>(apply_overlays(color.rgb, working, in.pos.xy), color.a);
+    }
+    return color;
 }
@@ -0,0 +1,95 @@
+// Capture sharpening: restores the crispness the lens and demosaic take away, so it runs
+// before any of the creative work. Only the luma changes, sharpening colour just makes
+// coloured fringes. The blurred luma it subtracts comes from gaussian_blur.wgsl.
+// common.wgsl goes in front of it.
+
+@group(0) @binding(0)
+var input_texture: texture_2d<f32>;
+
+// Gaussian blurred luma of the input
+@group(0) @binding(1)
+var sharpen_blurred: texture_2d<f32>;
+
+@group(0) @binding(2)
+var output_texture: texture_storage_2d<rgba16float, write>;
+
+@group(0) @binding(3)
+var<uniform> imageControls: ImageControls;
+
+// Must match the sharpening previews in ImageControls.rs
+const SHARPEN_PREVIEW_MASK = 1u;
+const SHARPEN_PREVIEW_LUMA = 2u;
+
+// Sharpening works on gamma encoded luma so it's as strong in the shadows as it looks.
+// Must match sharpen_luma in gaussian_blur.wgsl
+fn sharpen_luma(rgb: vec3<f32>) -> f32 {
+    return pow(max(dot(rgb, LUMA_WEIGHTS), 0.0), 1.0 / 2.2);
+}
+
+struct Sharpened {
+    luma: f32, // Gamma encoded, sharpened
+    mask: f32, // Edge mask it was weighted by
+}
+
+// Unsharp mask: the difference between the luma and its blurred copy is the detail, added
+// back scaled up. Overshoot is held to the neighbourhood's own range (widened by the detail
+// setting) so edges get crisper without bright or dark halos, and the masking setting keeps
+// it to edges, away from flat areas where it would only bring up noise.
+fn capture_sharpen(coords: vec2<i32>, dims: vec2<i32>, luma: f32) -> Sharpened {
+    let blurred = textureLoad(sharpen_blurred, coords, 0).r;
+
+    // Edge strength from the blurred copy, so noise doesn't count as an edge
+    let px = textureLoad(sharpen_blurred, min(coords + vec2<i32>(1, 0), dims - 1), 0).r;
+    let nx = textureLoad(sharpen_blurred, max(coords - vec2<i32>(1, 0), vec2<i32>(0)), 0).r;
+    let py = textureLoad(sharpen_blurred, min(coords + vec2<i32>(0, 1), dims - 1), 0).r;
+    let ny = textureLoad(sharpen_blurred, max(coords - vec2<i32>(0, 1), vec2<i32>(0)), 0).r;
+    let gradient = length(vec2<f32>(px - nx, py - ny)) * imageControls.sharpen_radius;
+    let threshold = imageControls.sharpen_masking * 0.06;
+    var mask = 1.0;
+    if (imageControls.sharpen_masking > 0.0) {
+        mask = smoothstep(threshold, threshold * 2.0 + 0.002, gradient);
+    }
+
+    // Range of the 3x3 neighbourhood the result may overshoot by a share of
+    var low = luma;
+    var high = luma;
+    for (var dy = -1; dy <= 1; dy++) {
+        for (var dx = -1; dx <= 1; dx++) {
+            let p = clamp(coords + vec2<i32>(dx, dy), vec2<i32>(0), dims - 1);
+            let l = sharpen_luma(textureLoad(input_texture, p, 0).rgb);
+            low = min(low, l);
+            high = max(high, l);
+        }
+    }
+    let slack = imageControls.sharpen_detail * (high - low);
+
+    let sharpened = luma + (luma - blurred) * imageControls.sharpen_amount * mask;
+    return Sharpened(clamp(sharpened, low - slack, high + slack), mask);
+}
+
+@compute @workgroup_size(16, 16)
+fn sharpen_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let dims = vec2<i32>(textureDimensions(input_texture));
+    let coords = vec2<i32>(global_id.xy);
+    if (coords.x >= dims.x || coords.y >= dims.y) {
+        return;
+    }
+
+    let texel = textureLoad(input_texture, coords, 0);
+    var color = texel.rgb;
+    let luma = sharpen_luma(color);
+    let sharpened = capture_sharpen(coords, dims, luma);
+    if (luma > 0.0) {
+        color *= pow(max(sharpened.luma, 0.0) / luma, 2.2);
+    }
+
+    // Alt-dragging a slider shows the effect on its own in black and white. The stages after
+    // this one are skipped and the colour pass only gamma encodes, so it goes out linear.
+    if (imageControls.sharpen_preview == SHARPEN_PREVIEW_MASK) {
+        color = vec3<f32>(pow(sharpened.mask, 2.2));
+    } else if (imageControls.sharpen_preview == SHARPEN_PREVIEW_LUMA) {
+        color = vec3<f32>(pow(clamp(sharpened.luma, 0.0, 1.0), 2.2));
+    }
+
+    textureStore(output_texture, coords, vec4<f32>(color, texel.a));
+}